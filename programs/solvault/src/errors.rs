@@ -43,4 +43,134 @@ pub enum VaultError {
 
     #[msg("No pending authority transfer to accept")]
     NoPendingTransfer,
+
+    #[msg("Vault is in async mode; use batch requests")]
+    AsyncModeEnabled,
+
+    #[msg("Vault is not in async mode")]
+    AsyncModeDisabled,
+
+    #[msg("Epoch batch has not been settled yet")]
+    EpochNotSettled,
+
+    #[msg("Slippage bound exceeded")]
+    SlippageExceeded,
+
+    #[msg("Position does not exist and rent was not sponsored")]
+    PositionNotInitialized,
+
+    #[msg("Shares cannot be transferred to this recipient")]
+    InvalidTransferTarget,
+
+    #[msg("Account is not a legacy position")]
+    NotLegacyPosition,
+
+    #[msg("Token vaults require the base mint, vault and user token accounts and token program")]
+    MissingTokenAccounts,
+
+    #[msg("Token account, mint or token program does not match the vault's base asset")]
+    InvalidTokenAccount,
+
+    #[msg("Base mint uses an unsupported Token-2022 extension")]
+    UnsupportedMint,
+
+    #[msg("Only available for native SOL vaults")]
+    NativeVaultOnly,
+
+    #[msg("LST is not approved for deposits")]
+    LstNotApproved,
+
+    #[msg("Exchange rate account does not match the LST or its program")]
+    InvalidRateSource,
+
+    #[msg("Exchange rate has not been updated this epoch")]
+    StaleExchangeRate,
+
+    #[msg("Vault does not hold enough of this LST")]
+    InsufficientLstBalance,
+
+    #[msg("The same LST was passed more than once")]
+    DuplicateLst,
+
+    #[msg("Validator is not approved for stake deposits")]
+    ValidatorNotApproved,
+
+    #[msg("Account is not a delegated stake account")]
+    InvalidStakeAccount,
+
+    #[msg("Stake is not fully active")]
+    StakeNotActive,
+
+    #[msg("Stake account lockup is in force")]
+    StakeLocked,
+
+    #[msg("Account is already at the current layout version")]
+    AlreadyMigrated,
+
+    #[msg("Protocol is not in the registry")]
+    ProtocolNotRegistered,
+
+    #[msg("Protocol is deprecated or frozen")]
+    ProtocolNotActive,
+
+    #[msg("Protocol is already registered")]
+    DuplicateProtocol,
+
+    #[msg("Protocol registry is full")]
+    RegistryFull,
+
+    #[msg("Allocation target breaches a protocol risk limit")]
+    RiskLimitBreached,
+
+    #[msg("Risk limits are inconsistent")]
+    InvalidRiskLimits,
+
+    #[msg("Vault must be migrated to the current layout first")]
+    MigrationRequired,
+    #[msg("Allocation target account is missing or not owned by the protocol")]
+    InvalidAllocationTarget,
+
+    #[msg("Allocation params exceed what the protocol accepts")]
+    InvalidAllocationParams,
+    #[msg("Ramp must end after it starts and last at most 365 days")]
+    InvalidRamp,
+
+    #[msg("Scheduled allocation change must activate in the future")]
+    InvalidSchedule,
+
+    #[msg("Yield data is missing or older than the feed's staleness threshold")]
+    StaleYieldData,
+
+    #[msg("Staleness threshold must be positive")]
+    InvalidFeedConfig,
+
+    #[msg("A rebalance session is in progress")]
+    RebalanceInProgress,
+
+    #[msg("Allocation index is out of range or already rebalanced")]
+    InvalidRebalanceStep,
+
+    #[msg("Rebalance session still has allocations to move")]
+    RebalanceIncomplete,
+
+    #[msg("Allocations exceed the vault's total deposits")]
+    RebalanceTotalsMismatch,
+
+    #[msg("Position deposited too recently to withdraw")]
+    WithdrawalTooSoon,
+
+    #[msg("Withdrawal exceeds the outflow limit for the current window")]
+    OutflowLimitExceeded,
+
+    #[msg("Circuit breaker has tripped")]
+    CircuitBreakerActive,
+
+    #[msg("Outflow limit exceeds 100% or its window is not positive")]
+    InvalidBreakerConfig,
+
+    #[msg("Rebalance session has lapsed; cancel it")]
+    RebalanceLapsed,
+
+    #[msg("Ticket has deposits to credit but no position was passed")]
+    PositionRequired,
 }
@@ -0,0 +1,162 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use anchor_spl::token_interface::{
+    self, Mint, TokenAccount, TokenInterface, TransferChecked,
+};
+use crate::errors::VaultError;
+use crate::state::*;
+
+// Moves the vault's base asset in and out. Native vaults custody lamports in the
+// vault PDA itself; token vaults hold an SPL Token / Token-2022 account owned by it.
+
+/// Token accounts needed to move the base asset of a token vault.
+pub(crate) struct TokenTransfer<'a, 'info> {
+    pub mint: &'a InterfaceAccount<'info, Mint>,
+    pub vault_token_account: &'a InterfaceAccount<'info, TokenAccount>,
+    /// Source on the way in, destination on the way out
+    pub user_token_account: &'a InterfaceAccount<'info, TokenAccount>,
+    pub token_program: &'a Interface<'info, TokenInterface>,
+}
+
+/// Resolve the optional token accounts of an instruction against the vault's asset.
+/// Returns `None` for native vaults; token vaults require every account.
+pub(crate) fn token_transfer<'a, 'info>(
+    vault: &Vault,
+    mint: &'a Option<InterfaceAccount<'info, Mint>>,
+    vault_token_account: &'a Option<InterfaceAccount<'info, TokenAccount>>,
+    user_token_account: &'a Option<InterfaceAccount<'info, TokenAccount>>,
+    token_program: &'a Option<Interface<'info, TokenInterface>>,
+) -> Result<Option<TokenTransfer<'a, 'info>>> {
+    if vault.is_native() {
+        return Ok(None);
+    }
+
+    match (mint, vault_token_account, user_token_account, token_program) {
+        (Some(mint), Some(vault_token_account), Some(user_token_account), Some(token_program)) => {
+            require_keys_eq!(mint.key(), vault.base_mint, VaultError::InvalidTokenAccount);
+            require_keys_eq!(
+                vault_token_account.key(),
+                vault.vault_token_account,
+                VaultError::InvalidTokenAccount
+            );
+            require_keys_eq!(
+                token_program.key(),
+                vault.token_program,
+                VaultError::InvalidTokenAccount
+            );
+            require_keys_eq!(
+                user_token_account.mint,
+                vault.base_mint,
+                VaultError::InvalidTokenAccount
+            );
+            Ok(Some(TokenTransfer {
+                mint,
+                vault_token_account,
+                user_token_account,
+                token_program,
+            }))
+        }
+        _ => err!(VaultError::MissingTokenAccounts),
+    }
+}
+
+/// Move `amount` of the base asset from `from` (a signer) into the vault.
+pub(crate) fn transfer_in<'info>(
+    vault: &AccountLoader<'info, Vault>,
+    from: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    token: Option<TokenTransfer<'_, 'info>>,
+    amount: u64,
+) -> Result<()> {
+    match token {
+        None => system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                system_program::Transfer {
+                    from: from.clone(),
+                    to: vault.to_account_info(),
+                },
+            ),
+            amount,
+        ),
+        Some(token) => token_interface::transfer_checked(
+            CpiContext::new(
+                token.token_program.to_account_info(),
+                TransferChecked {
+                    from: token.user_token_account.to_account_info(),
+                    mint: token.mint.to_account_info(),
+                    to: token.vault_token_account.to_account_info(),
+                    authority: from.clone(),
+                },
+            ),
+            amount,
+            token.mint.decimals,
+        ),
+    }
+}
+
+/// Pay `amount` of the base asset out of the vault. Native payouts go to
+/// `recipient` and keep the vault PDA rent-exempt; token payouts go to the
+/// resolved token account, signed by the vault PDA.
+/// The vault must not be loaded mutably while this runs.
+pub(crate) fn transfer_out<'info>(
+    vault: &AccountLoader<'info, Vault>,
+    recipient: &AccountInfo<'info>,
+    token: Option<TokenTransfer<'_, 'info>>,
+    amount: u64,
+) -> Result<()> {
+    match token {
+        None => {
+            // Ensure vault stays above rent-exempt minimum after transfer
+            let vault_account_info = vault.to_account_info();
+            let rent = Rent::get()?;
+            let min_balance = rent.minimum_balance(vault_account_info.data_len());
+            let vault_lamports_after = vault_account_info
+                .lamports()
+                .checked_sub(amount)
+                .ok_or(VaultError::MathOverflow)?;
+            require!(
+                vault_lamports_after >= min_balance,
+                VaultError::BelowRentExemption
+            );
+
+            **vault_account_info.try_borrow_mut_lamports()? = vault_lamports_after;
+            **recipient.try_borrow_mut_lamports()? = recipient
+                .lamports()
+                .checked_add(amount)
+                .ok_or(VaultError::MathOverflow)?;
+            Ok(())
+        }
+        Some(token) => {
+            let bump = vault.load()?.bump;
+            let signer_seeds: &[&[&[u8]]] = &[&[VAULT_SEED, &[bump]]];
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    token.token_program.to_account_info(),
+                    TransferChecked {
+                        from: token.vault_token_account.to_account_info(),
+                        mint: token.mint.to_account_info(),
+                        to: token.user_token_account.to_account_info(),
+                        authority: vault.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                amount,
+                token.mint.decimals,
+            )
+        }
+    }
+}
+
+/// Base asset the vault actually holds: lamports above rent for native vaults,
+/// the vault token account balance for token vaults.
+pub(crate) fn vault_balance(vault: &AccountLoader<Vault>, token: &Option<TokenTransfer>) -> Result<u64> {
+    match token {
+        None => {
+            let vault_account_info = vault.to_account_info();
+            let min_balance = Rent::get()?.minimum_balance(vault_account_info.data_len());
+            Ok(vault_account_info.lamports().saturating_sub(min_balance))
+        }
+        Some(token) => Ok(token.vault_token_account.amount),
+    }
+}
@@ -0,0 +1,147 @@
+use anchor_lang::prelude::*;
+use crate::errors::VaultError;
+use crate::state::*;
+
+// ── Authority limits how much of the vault may leave within a rolling window ──
+
+#[derive(Accounts)]
+pub struct UpdateCircuitBreaker<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+}
+
+pub fn set_circuit_breaker_handler(
+    ctx: Context<UpdateCircuitBreaker>,
+    outflow_limit_bps: u16,
+    outflow_window_secs: i64,
+    action: BreakerAction,
+) -> Result<()> {
+    require!(
+        outflow_limit_bps as u64 <= BPS_DENOMINATOR && outflow_window_secs > 0,
+        VaultError::InvalidBreakerConfig
+    );
+
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    // Withdrawals over the limit queue into epoch batches, which hold lamports
+    require!(
+        outflow_limit_bps == 0 || vault.is_native(),
+        VaultError::NativeVaultOnly
+    );
+
+    // Carry the recent outflow over into the new window
+    let now = Clock::get()?.unix_timestamp;
+    vault.record_outflow(0, now);
+    vault.outflow_limit_bps = outflow_limit_bps;
+    vault.outflow_window_secs = outflow_window_secs;
+    vault.breaker_action = action as u8;
+
+    emit!(CircuitBreakerConfigUpdatedEvent {
+        outflow_limit_bps,
+        outflow_window_secs,
+        action,
+    });
+
+    msg!(
+        "Outflow limit: {} bps per {} seconds",
+        outflow_limit_bps,
+        outflow_window_secs
+    );
+    Ok(())
+}
+
+/// Clear a tripped breaker and its window. Undo the action it took with
+/// `update_config` (pause) or `update_epoch_config` (async mode).
+pub fn reset_circuit_breaker_handler(ctx: Context<UpdateCircuitBreaker>) -> Result<()> {
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    let now = Clock::get()?.unix_timestamp;
+    vault.breaker_tripped = 0;
+    vault.recent_outflow = 0;
+    vault.outflow_updated_ts = now;
+
+    emit!(CircuitBreakerResetEvent { timestamp: now });
+
+    msg!("Circuit breaker reset");
+    Ok(())
+}
+
+/// Outflow within the window at `now` and the most it may reach: `outflow_limit_bps`
+/// of NAV at the start of the window (today's total plus what has left since)
+fn outflow_limit(vault: &Vault, now: i64) -> Result<(u64, u64)> {
+    let recent = vault.recent_outflow_at(now);
+    let limit: u64 = (vault.total_deposited as u128 + recent as u128)
+        .checked_mul(vault.outflow_limit_bps as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_div(BPS_DENOMINATOR as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .try_into()
+        .map_err(|_| VaultError::MathOverflow)?;
+    Ok((recent, limit))
+}
+
+/// Whether an outflow of `amount` now would go over the limit
+pub(crate) fn exceeds_outflow_limit(vault: &Vault, amount: u64, now: i64) -> Result<bool> {
+    if vault.outflow_limit_bps == 0 {
+        return Ok(false);
+    }
+    let (recent, limit) = outflow_limit(vault, now)?;
+    Ok(recent.saturating_add(amount) > limit)
+}
+
+/// Check an instant withdrawal of `amount` against the outflow limit.
+/// Withdrawals over the limit fail; `request_withdraw` queues them instead.
+pub(crate) fn check_outflow(vault: &Vault, amount: u64) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    require!(
+        !exceeds_outflow_limit(vault, amount, now)?,
+        VaultError::OutflowLimitExceeded
+    );
+    Ok(())
+}
+
+/// Check an instant withdrawal of `amount` against the outflow limit and record it.
+pub(crate) fn admit_outflow(vault: &mut Vault, amount: u64) -> Result<()> {
+    check_outflow(vault, amount)?;
+    vault.record_outflow(amount, Clock::get()?.unix_timestamp);
+    Ok(())
+}
+
+/// Trip the breaker for a redemption of `amount` the outflow limit turned away,
+/// and take its action. Does nothing if it has already tripped.
+pub(crate) fn trip_circuit_breaker(vault: &mut Vault, amount: u64, now: i64) -> Result<()> {
+    if vault.breaker_tripped != 0 {
+        return Ok(());
+    }
+    let (recent, limit) = outflow_limit(vault, now)?;
+
+    vault.breaker_tripped = 1;
+    let action = if vault.breaker_action == BreakerAction::Pause as u8 {
+        vault.is_paused = 1;
+        BreakerAction::Pause
+    } else {
+        vault.async_mode = 1;
+        BreakerAction::Queue
+    };
+
+    emit!(CircuitBreakerTrippedEvent {
+        recent_outflow: recent,
+        attempted: amount,
+        limit,
+        action,
+        timestamp: now,
+    });
+
+    msg!(
+        "Circuit breaker tripped: {} + {} lamports over the {} limit",
+        recent,
+        amount,
+        limit
+    );
+    Ok(())
+}
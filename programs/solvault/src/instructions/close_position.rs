@@ -3,6 +3,7 @@ use crate::errors::VaultError;
 use crate::state::*;
 
 #[derive(Accounts)]
+#[instruction(index: u16)]
 pub struct ClosePosition<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
@@ -10,7 +11,7 @@ pub struct ClosePosition<'info> {
     #[account(
         mut,
         close = user,
-        seeds = [POSITION_SEED, user.key().as_ref()],
+        seeds = [POSITION_SEED, user.key().as_ref(), &index.to_le_bytes()],
         bump = position.bump,
         constraint = position.owner == user.key() @ VaultError::Unauthorized,
         constraint = position.shares == 0 @ VaultError::InsufficientShares,
@@ -18,9 +19,10 @@ pub struct ClosePosition<'info> {
     pub position: Account<'info, UserPosition>,
 }
 
-pub fn handler(ctx: Context<ClosePosition>) -> Result<()> {
+pub fn handler(ctx: Context<ClosePosition>, index: u16) -> Result<()> {
     emit!(PositionClosedEvent {
         user: ctx.accounts.user.key(),
+        index,
     });
 
     msg!("Position closed, rent reclaimed");
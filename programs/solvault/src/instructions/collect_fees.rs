@@ -1,5 +1,7 @@
 use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 use crate::errors::VaultError;
+use crate::instructions::asset::{token_transfer, transfer_out, vault_balance};
 use crate::state::*;
 
 #[derive(Accounts)]
@@ -10,48 +12,64 @@ pub struct CollectFees<'info> {
     #[account(
         mut,
         seeds = [VAULT_SEED],
-        bump = vault.bump,
+        bump = vault.load()?.bump,
         has_one = authority @ VaultError::Unauthorized,
     )]
-    pub vault: Account<'info, Vault>,
+    pub vault: AccountLoader<'info, Vault>,
+
+    /// Base asset accounts; required for token vaults, omitted for native SOL
+    pub base_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub vault_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub authority_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
 }
 
 pub fn handler(ctx: Context<CollectFees>) -> Result<()> {
-    let vault = &ctx.accounts.vault;
+    let vault = ctx.accounts.vault.load()?;
     let fee_amount = vault.accrued_fees;
 
     require!(fee_amount > 0, VaultError::ZeroAmount);
+    require!(!vault.breaker_paused(), VaultError::CircuitBreakerActive);
+
+    let token = token_transfer(
+        &vault,
+        &ctx.accounts.base_mint,
+        &ctx.accounts.vault_token_account,
+        &ctx.accounts.authority_token_account,
+        &ctx.accounts.token_program,
+    )?;
 
-    // Ensure vault stays above rent-exempt minimum after transfer
-    let vault_account_info = ctx.accounts.vault.to_account_info();
-    let rent = Rent::get()?;
-    let min_balance = rent.minimum_balance(vault_account_info.data_len());
-    let vault_lamports_after = vault_account_info
-        .lamports()
+    // Balance above rent must still cover depositor claims after the transfer,
+    // including unsettled batch deposits and unclaimed redemptions.
+    // Claims backed by deposited LST or stake accounts are not held in the base asset.
+    let balance_after = vault_balance(&ctx.accounts.vault, &token)?
         .checked_sub(fee_amount)
         .ok_or(VaultError::MathOverflow)?;
-
-    // Must retain rent-exemption AND enough to cover depositor claims
-    let required_minimum = min_balance
-        .checked_add(vault.total_deposited)
+    let required_minimum = vault
+        .total_deposited
+        .saturating_sub(vault.lst_value)
+        .saturating_sub(vault.stake_value)
+        .checked_add(vault.pending_deposits)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_add(vault.pending_redemptions)
         .ok_or(VaultError::MathOverflow)?;
     require!(
-        vault_lamports_after >= required_minimum,
+        balance_after >= required_minimum,
         VaultError::BelowRentExemption
     );
+    drop(vault);
 
-    // Transfer accrued fees from vault PDA to authority
-    **vault_account_info.try_borrow_mut_lamports()? = vault_lamports_after;
-    **ctx.accounts.authority.try_borrow_mut_lamports()? = ctx
-        .accounts
-        .authority
-        .lamports()
-        .checked_add(fee_amount)
-        .ok_or(VaultError::MathOverflow)?;
+    // Transfer accrued fees from the vault to the authority
+    let authority_info = ctx.accounts.authority.to_account_info();
+    transfer_out(&ctx.accounts.vault, &authority_info, token, fee_amount)?;
 
     // Reset accrued fees
-    let vault = &mut ctx.accounts.vault;
-    vault.accrued_fees = 0;
+    ctx.accounts.vault.load_mut()?.accrued_fees = 0;
 
     emit!(FeeCollectedEvent {
         authority: ctx.accounts.authority.key(),
@@ -1,9 +1,11 @@
 use anchor_lang::prelude::*;
-use anchor_lang::system_program;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 use crate::errors::VaultError;
+use crate::instructions::asset::{token_transfer, transfer_in};
 use crate::state::*;
 
 #[derive(Accounts)]
+#[instruction(index: u16)]
 pub struct Deposit<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
@@ -11,57 +13,252 @@ pub struct Deposit<'info> {
     #[account(
         mut,
         seeds = [VAULT_SEED],
-        bump = vault.bump,
+        bump = vault.load()?.bump,
     )]
-    pub vault: Account<'info, Vault>,
+    pub vault: AccountLoader<'info, Vault>,
 
     #[account(
         init_if_needed,
         payer = user,
         space = 8 + UserPosition::INIT_SPACE,
-        seeds = [POSITION_SEED, user.key().as_ref()],
+        seeds = [POSITION_SEED, user.key().as_ref(), &index.to_le_bytes()],
         bump,
     )]
     pub position: Account<'info, UserPosition>,
 
+    /// Base asset accounts; required for token vaults, omitted for native SOL
+    pub base_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub vault_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+
     pub system_program: Program<'info, System>,
 }
 
-pub fn handler(ctx: Context<Deposit>, amount: u64) -> Result<()> {
-    let vault = &ctx.accounts.vault;
+/// Exact-in: deposit `amount` lamports, minting at least `min_shares_out` shares
+pub fn handler(
+    ctx: Context<Deposit>,
+    index: u16,
+    amount: u64,
+    min_shares_out: u64,
+) -> Result<()> {
+    let shares_to_mint = {
+        let vault = ctx.accounts.vault.load()?;
 
-    require!(!vault.is_paused, VaultError::VaultPaused);
-    require!(amount >= MIN_DEPOSIT_LAMPORTS, VaultError::BelowMinimumDeposit);
+        require!(vault.async_mode == 0, VaultError::AsyncModeEnabled);
 
-    if vault.deposit_cap > 0 {
-        let new_total = vault
-            .total_deposited
-            .checked_add(amount)
-            .ok_or(VaultError::MathOverflow)?;
-        require!(new_total <= vault.deposit_cap, VaultError::DepositCapExceeded);
-    }
+        // Calculate shares to mint
+        let shares_to_mint = quote_deposit(&vault, amount)?;
+        require!(shares_to_mint >= min_shares_out, VaultError::SlippageExceeded);
+        shares_to_mint
+    };
+
+    execute_deposit(ctx, index, amount, shares_to_mint)
+}
+
+/// Exact-out: mint exactly `shares`, paying at most `max_lamports`
+pub fn mint_handler(
+    ctx: Context<Deposit>,
+    index: u16,
+    shares: u64,
+    max_lamports: u64,
+) -> Result<()> {
+    let amount = {
+        let vault = ctx.accounts.vault.load()?;
+
+        require!(vault.async_mode == 0, VaultError::AsyncModeEnabled);
+        require!(shares > 0, VaultError::ZeroAmount);
+
+        // Cost of the shares rounds UP so the minter never underpays
+        let amount =
+            calculate_deposit_for_shares(shares, vault.total_deposited, vault.total_shares)?;
+        require!(amount <= max_lamports, VaultError::SlippageExceeded);
+        validate_deposit(&vault, amount)?;
+        amount
+    };
+
+    execute_deposit(ctx, index, amount, shares)
+}
+
+fn execute_deposit(
+    ctx: Context<Deposit>,
+    index: u16,
+    amount: u64,
+    shares_to_mint: u64,
+) -> Result<()> {
+    // Transfer the base asset from user to vault
+    let token = token_transfer(
+        &*ctx.accounts.vault.load()?,
+        &ctx.accounts.base_mint,
+        &ctx.accounts.vault_token_account,
+        &ctx.accounts.user_token_account,
+        &ctx.accounts.token_program,
+    )?;
+    transfer_in(
+        &ctx.accounts.vault,
+        &ctx.accounts.user.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        token,
+        amount,
+    )?;
+
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    let position = &mut ctx.accounts.position;
+    credit_deposit(
+        &mut vault,
+        position,
+        ctx.accounts.user.key(),
+        index,
+        ctx.bumps.position,
+        amount,
+        shares_to_mint,
+    )?;
+
+    emit!(DepositEvent {
+        user: ctx.accounts.user.key(),
+        index,
+        amount,
+        shares_minted: shares_to_mint,
+        total_deposited: vault.total_deposited,
+        total_shares: vault.total_shares,
+    });
+
+    msg!(
+        "Deposited {} lamports, minted {} shares",
+        amount,
+        shares_to_mint
+    );
+    Ok(())
+}
+
+// ── Deposit on behalf of another beneficiary ──
+
+#[derive(Accounts)]
+#[instruction(beneficiary: Pubkey, index: u16)]
+pub struct DepositFor<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, beneficiary.as_ref(), &index.to_le_bytes()],
+        bump,
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    /// Base asset accounts; required for token vaults, omitted for native SOL
+    pub base_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub vault_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub payer_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
 
-    // Calculate shares to mint
-    let shares_to_mint = calculate_shares_for_deposit(
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Payer funds a deposit credited to `beneficiary`'s position. If the position
+/// does not exist yet, the payer only covers its rent when `sponsor_rent` is set.
+pub fn deposit_for_handler(
+    ctx: Context<DepositFor>,
+    beneficiary: Pubkey,
+    index: u16,
+    amount: u64,
+    min_shares_out: u64,
+    sponsor_rent: bool,
+) -> Result<()> {
+    let vault = ctx.accounts.vault.load()?;
+
+    require!(vault.async_mode == 0, VaultError::AsyncModeEnabled);
+    require!(beneficiary != Pubkey::default(), VaultError::Unauthorized);
+
+    // A freshly created position is still zeroed; only keep it if the payer opted in
+    let rent_sponsored = ctx.accounts.position.owner == Pubkey::default();
+    require!(
+        !rent_sponsored || sponsor_rent,
+        VaultError::PositionNotInitialized
+    );
+
+    let shares_to_mint = quote_deposit(&vault, amount)?;
+    require!(shares_to_mint >= min_shares_out, VaultError::SlippageExceeded);
+
+    // Transfer the base asset from payer to vault
+    let token = token_transfer(
+        &vault,
+        &ctx.accounts.base_mint,
+        &ctx.accounts.vault_token_account,
+        &ctx.accounts.payer_token_account,
+        &ctx.accounts.token_program,
+    )?;
+    drop(vault);
+    transfer_in(
+        &ctx.accounts.vault,
+        &ctx.accounts.payer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        token,
         amount,
-        vault.total_deposited,
-        vault.total_shares,
     )?;
 
-    // Transfer SOL from user to vault PDA
-    system_program::transfer(
-        CpiContext::new(
-            ctx.accounts.system_program.to_account_info(),
-            system_program::Transfer {
-                from: ctx.accounts.user.to_account_info(),
-                to: ctx.accounts.vault.to_account_info(),
-            },
-        ),
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    let position = &mut ctx.accounts.position;
+    credit_deposit(
+        &mut vault,
+        position,
+        beneficiary,
+        index,
+        ctx.bumps.position,
         amount,
+        shares_to_mint,
     )?;
 
+    emit!(DepositForEvent {
+        payer: ctx.accounts.payer.key(),
+        beneficiary,
+        index,
+        amount,
+        shares_minted: shares_to_mint,
+        rent_sponsored,
+        total_deposited: vault.total_deposited,
+        total_shares: vault.total_shares,
+    });
+
+    msg!(
+        "Deposited {} lamports for {}, minted {} shares",
+        amount,
+        beneficiary,
+        shares_to_mint
+    );
+    Ok(())
+}
+
+/// Book a settled deposit: grow vault totals and credit the owner's position.
+pub(crate) fn credit_deposit(
+    vault: &mut Vault,
+    position: &mut UserPosition,
+    owner: Pubkey,
+    index: u16,
+    position_bump: u8,
+    amount: u64,
+    shares_to_mint: u64,
+) -> Result<()> {
     // Update vault state
-    let vault = &mut ctx.accounts.vault;
     vault.total_deposited = vault
         .total_deposited
         .checked_add(amount)
@@ -72,10 +269,10 @@ pub fn handler(ctx: Context<Deposit>, amount: u64) -> Result<()> {
         .ok_or(VaultError::MathOverflow)?;
 
     // Update user position
-    let position = &mut ctx.accounts.position;
     let is_new_depositor = position.shares == 0;
 
-    position.owner = ctx.accounts.user.key();
+    position.owner = owner;
+    position.index = index;
     position.shares = position
         .shares
         .checked_add(shares_to_mint)
@@ -85,7 +282,9 @@ pub fn handler(ctx: Context<Deposit>, amount: u64) -> Result<()> {
         .checked_add(amount)
         .ok_or(VaultError::MathOverflow)?;
     position.last_deposit_ts = Clock::get()?.unix_timestamp;
-    position.bump = ctx.bumps.position;
+    vault.lock_deposit(position, shares_to_mint)?;
+    position.bump = position_bump;
+    position.version = POSITION_VERSION;
 
     if is_new_depositor {
         vault.depositor_count = vault
@@ -94,49 +293,84 @@ pub fn handler(ctx: Context<Deposit>, amount: u64) -> Result<()> {
             .ok_or(VaultError::MathOverflow)?;
     }
 
-    emit!(DepositEvent {
-        user: ctx.accounts.user.key(),
-        amount,
-        shares_minted: shares_to_mint,
-        total_deposited: vault.total_deposited,
-        total_shares: vault.total_shares,
-    });
+    Ok(())
+}
+
+/// Price a deposit with the same checks the deposit instruction enforces:
+/// pause state, minimum amount and deposit cap.
+pub(crate) fn quote_deposit(vault: &Vault, amount: u64) -> Result<u64> {
+    validate_deposit(vault, amount)?;
+    calculate_shares_for_deposit(amount, vault.total_deposited, vault.total_shares)
+}
+
+/// Checks every deposit path enforces on the lamports coming in.
+pub(crate) fn validate_deposit(vault: &Vault, amount: u64) -> Result<()> {
+    require!(vault.is_paused == 0, VaultError::VaultPaused);
+    require!(amount >= MIN_DEPOSIT_LAMPORTS, VaultError::BelowMinimumDeposit);
+
+    if vault.deposit_cap > 0 {
+        let new_total = vault
+            .total_deposited
+            .checked_add(vault.pending_deposits)
+            .ok_or(VaultError::MathOverflow)?
+            .checked_add(amount)
+            .ok_or(VaultError::MathOverflow)?;
+        require!(new_total <= vault.deposit_cap, VaultError::DepositCapExceeded);
+    }
 
-    msg!(
-        "Deposited {} lamports, minted {} shares",
-        amount,
-        shares_to_mint
-    );
     Ok(())
 }
 
-/// Calculate shares to mint for a given deposit amount.
-/// First deposit: 1 SOL = SHARES_PER_SOL shares.
-/// Subsequent: proportional to existing share/deposit ratio.
+/// Calculate shares to mint for a given deposit amount:
+/// shares = amount * (total_shares + VIRTUAL_SHARES) / (total_deposited + VIRTUAL_ASSETS).
+/// The virtual offset prices an empty vault at 1 share per lamport and keeps a
+/// donation from rounding the next depositor's shares down to zero.
 /// Rounding: integer division truncates DOWN, so the depositor receives
 /// slightly fewer shares, protecting existing share holders.
-fn calculate_shares_for_deposit(
+pub(crate) fn calculate_shares_for_deposit(
     deposit_amount: u64,
     total_deposited: u64,
     total_shares: u64,
 ) -> Result<u64> {
-    if total_shares == 0 || total_deposited == 0 {
-        // First deposit: use fixed ratio for precision
-        let shares = (deposit_amount as u128)
-            .checked_mul(SHARES_PER_SOL as u128)
-            .ok_or(VaultError::MathOverflow)?
-            .checked_div(1_000_000_000) // lamports per SOL
-            .ok_or(VaultError::MathOverflow)?;
-        let shares: u64 = shares.try_into().map_err(|_| VaultError::MathOverflow)?;
-        Ok(shares)
-    } else {
-        // Proportional: shares = deposit_amount * total_shares / total_deposited
-        let shares = (deposit_amount as u128)
-            .checked_mul(total_shares as u128)
-            .ok_or(VaultError::MathOverflow)?
-            .checked_div(total_deposited as u128)
-            .ok_or(VaultError::MathOverflow)?;
-        let shares: u64 = shares.try_into().map_err(|_| VaultError::MathOverflow)?;
-        Ok(shares)
-    }
+    let shares: u64 = (deposit_amount as u128)
+        .checked_mul(virtual_shares(total_shares))
+        .ok_or(VaultError::MathOverflow)?
+        .checked_div(virtual_assets(total_deposited))
+        .ok_or(VaultError::MathOverflow)?
+        .try_into()
+        .map_err(|_| VaultError::MathOverflow)?;
+    Ok(shares)
+}
+
+/// Calculate lamports required to mint an exact number of shares:
+/// amount = shares * (total_deposited + VIRTUAL_ASSETS) / (total_shares + VIRTUAL_SHARES).
+/// Rounding: rounds UP, so the minter pays for any fractional lamport,
+/// protecting existing share holders.
+pub(crate) fn calculate_deposit_for_shares(
+    shares: u64,
+    total_deposited: u64,
+    total_shares: u64,
+) -> Result<u64> {
+    let numerator = (shares as u128)
+        .checked_mul(virtual_assets(total_deposited))
+        .ok_or(VaultError::MathOverflow)?;
+    let denominator = virtual_shares(total_shares);
+    let amount: u64 = numerator
+        .checked_add(denominator - 1)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_div(denominator)
+        .ok_or(VaultError::MathOverflow)?
+        .try_into()
+        .map_err(|_| VaultError::MathOverflow)?;
+    Ok(amount)
+}
+
+/// Shares outstanding plus the virtual offset, as used in every share-price calculation
+pub(crate) fn virtual_shares(total_shares: u64) -> u128 {
+    total_shares as u128 + VIRTUAL_SHARES as u128
+}
+
+/// Assets held plus the virtual offset, as used in every share-price calculation
+pub(crate) fn virtual_assets(total_deposited: u64) -> u128 {
+    total_deposited as u128 + VIRTUAL_ASSETS as u128
 }
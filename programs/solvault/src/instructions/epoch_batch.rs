@@ -0,0 +1,612 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use crate::errors::VaultError;
+use crate::instructions::circuit_breaker::{exceeds_outflow_limit, trip_circuit_breaker};
+use crate::instructions::deposit::calculate_shares_for_deposit;
+use crate::instructions::withdraw::{calculate_performance_fee, calculate_withdrawal_amount};
+use crate::state::*;
+
+// ── Configure async mode and the keeper allowed to close epochs ──
+
+#[derive(Accounts)]
+pub struct UpdateEpochConfig<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+}
+
+pub fn update_epoch_config_handler(
+    ctx: Context<UpdateEpochConfig>,
+    async_mode: Option<bool>,
+    keeper: Option<Pubkey>,
+) -> Result<()> {
+    let mut vault = ctx.accounts.vault.load_mut()?;
+
+    if let Some(enabled) = async_mode {
+        // Batch escrow and claims settle in lamports
+        require!(!enabled || vault.is_native(), VaultError::NativeVaultOnly);
+        vault.async_mode = enabled as u8;
+        msg!("Async mode: {}", enabled);
+    }
+
+    if let Some(new_keeper) = keeper {
+        vault.keeper = new_keeper;
+        msg!("Keeper set to {}", new_keeper);
+    }
+
+    emit!(EpochConfigUpdatedEvent {
+        async_mode,
+        keeper,
+    });
+
+    Ok(())
+}
+
+// ── Queue a deposit into the current epoch ──
+
+#[derive(Accounts)]
+pub struct RequestDeposit<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + EpochBatch::INIT_SPACE,
+        seeds = [BATCH_SEED, &vault.load()?.current_epoch.to_le_bytes()],
+        bump,
+    )]
+    pub batch: Account<'info, EpochBatch>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + BatchTicket::INIT_SPACE,
+        seeds = [TICKET_SEED, &vault.load()?.current_epoch.to_le_bytes(), user.key().as_ref()],
+        bump,
+    )]
+    pub ticket: Account<'info, BatchTicket>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn request_deposit_handler(ctx: Context<RequestDeposit>, amount: u64) -> Result<()> {
+    let vault = ctx.accounts.vault.load()?;
+
+    require!(vault.is_paused == 0, VaultError::VaultPaused);
+    require!(vault.async_mode != 0, VaultError::AsyncModeDisabled);
+    require!(amount >= MIN_DEPOSIT_LAMPORTS, VaultError::BelowMinimumDeposit);
+
+    if vault.deposit_cap > 0 {
+        // Queued deposits count against the cap so settlement can never exceed it
+        let new_total = vault
+            .total_deposited
+            .checked_add(vault.pending_deposits)
+            .ok_or(VaultError::MathOverflow)?
+            .checked_add(amount)
+            .ok_or(VaultError::MathOverflow)?;
+        require!(new_total <= vault.deposit_cap, VaultError::DepositCapExceeded);
+    }
+    drop(vault);
+
+    // Transfer SOL from user to vault PDA; it stays outside total_deposited until settlement
+    system_program::transfer(
+        CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.user.to_account_info(),
+                to: ctx.accounts.vault.to_account_info(),
+            },
+        ),
+        amount,
+    )?;
+
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    let epoch = vault.current_epoch;
+    vault.pending_deposits = vault
+        .pending_deposits
+        .checked_add(amount)
+        .ok_or(VaultError::MathOverflow)?;
+
+    let batch = &mut ctx.accounts.batch;
+    batch.epoch = epoch;
+    batch.bump = ctx.bumps.batch;
+    batch.deposit_lamports = batch
+        .deposit_lamports
+        .checked_add(amount)
+        .ok_or(VaultError::MathOverflow)?;
+
+    let ticket = &mut ctx.accounts.ticket;
+    ticket.owner = ctx.accounts.user.key();
+    ticket.epoch = epoch;
+    ticket.bump = ctx.bumps.ticket;
+    ticket.deposit_lamports = ticket
+        .deposit_lamports
+        .checked_add(amount)
+        .ok_or(VaultError::MathOverflow)?;
+
+    emit!(BatchRequestEvent {
+        user: ctx.accounts.user.key(),
+        epoch,
+        deposit_lamports: amount,
+        redeem_shares: 0,
+    });
+
+    msg!("Queued deposit of {} lamports for epoch {}", amount, epoch);
+    Ok(())
+}
+
+// ── Queue a redemption into the current epoch ──
+
+#[derive(Accounts)]
+#[instruction(index: u16)]
+pub struct RequestWithdraw<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user.key().as_ref(), &index.to_le_bytes()],
+        bump = position.bump,
+        constraint = position.owner == user.key() @ VaultError::Unauthorized,
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + EpochBatch::INIT_SPACE,
+        seeds = [BATCH_SEED, &vault.load()?.current_epoch.to_le_bytes()],
+        bump,
+    )]
+    pub batch: Account<'info, EpochBatch>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + BatchTicket::INIT_SPACE,
+        seeds = [TICKET_SEED, &vault.load()?.current_epoch.to_le_bytes(), user.key().as_ref()],
+        bump,
+    )]
+    pub ticket: Account<'info, BatchTicket>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn request_withdraw_handler(
+    ctx: Context<RequestWithdraw>,
+    _index: u16,
+    shares: u64,
+) -> Result<()> {
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    let position = &ctx.accounts.position;
+
+    // Like withdraw, redemption requests are allowed even when paused
+    require!(shares > 0, VaultError::ZeroAmount);
+    require!(position.shares >= shares, VaultError::InsufficientShares);
+    // Freshly minted shares can't be queued out any sooner than withdrawn
+    vault.check_withdraw_delay(position, shares)?;
+
+    // Cost basis travels with the escrowed shares so the fee is charged on yield only
+    let cost_basis: u64 = (position.deposited_amount as u128)
+        .checked_mul(shares as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_div(position.shares as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .try_into()
+        .map_err(|_| VaultError::MathOverflow)?;
+
+    // Outside async mode, only redemptions the outflow limit turns away may queue.
+    // They are measured net of the fee, as `admit_outflow` measures instant ones.
+    if vault.async_mode == 0 {
+        let now = Clock::get()?.unix_timestamp;
+        let value =
+            calculate_withdrawal_amount(shares, vault.total_deposited, vault.total_shares)?;
+        let net_value = value
+            .checked_sub(calculate_performance_fee(
+                value,
+                cost_basis,
+                vault.performance_fee_bps,
+            )?)
+            .ok_or(VaultError::MathOverflow)?;
+        require!(
+            exceeds_outflow_limit(&vault, net_value, now)?,
+            VaultError::AsyncModeDisabled
+        );
+        // Tripping acts on the whole vault, so it takes a sizeable redemption
+        if vault.breaker_tripped == 0 {
+            let min_trip = (vault.total_deposited as u128) * (MIN_BREAKER_TRIP_BPS as u128)
+                / (BPS_DENOMINATOR as u128);
+            require!(net_value as u128 >= min_trip, VaultError::OutflowLimitExceeded);
+        }
+        trip_circuit_breaker(&mut vault, net_value, now)?;
+    }
+
+    // Check remaining shares won't leave dust (escrowed shares are still outstanding)
+    let remaining_shares = position
+        .shares
+        .checked_sub(shares)
+        .ok_or(VaultError::MathOverflow)?;
+    if remaining_shares > 0 {
+        let remaining_value = calculate_withdrawal_amount(
+            remaining_shares,
+            vault.total_deposited,
+            vault.total_shares,
+        )?;
+        require!(
+            remaining_value >= MIN_DEPOSIT_LAMPORTS,
+            VaultError::DustWithdrawal
+        );
+    }
+
+    let epoch = vault.current_epoch;
+    if remaining_shares == 0 {
+        vault.depositor_count = vault
+            .depositor_count
+            .checked_sub(1)
+            .ok_or(VaultError::MathOverflow)?;
+    }
+
+    let position = &mut ctx.accounts.position;
+    position.shares = remaining_shares;
+    position.deposited_amount = position
+        .deposited_amount
+        .checked_sub(cost_basis)
+        .ok_or(VaultError::MathOverflow)?;
+
+    let batch = &mut ctx.accounts.batch;
+    batch.epoch = epoch;
+    batch.bump = ctx.bumps.batch;
+    batch.redeem_shares = batch
+        .redeem_shares
+        .checked_add(shares)
+        .ok_or(VaultError::MathOverflow)?;
+
+    let ticket = &mut ctx.accounts.ticket;
+    ticket.owner = ctx.accounts.user.key();
+    ticket.epoch = epoch;
+    ticket.bump = ctx.bumps.ticket;
+    ticket.redeem_shares = ticket
+        .redeem_shares
+        .checked_add(shares)
+        .ok_or(VaultError::MathOverflow)?;
+    ticket.redeem_cost_basis = ticket
+        .redeem_cost_basis
+        .checked_add(cost_basis)
+        .ok_or(VaultError::MathOverflow)?;
+
+    emit!(BatchRequestEvent {
+        user: ctx.accounts.user.key(),
+        epoch,
+        deposit_lamports: 0,
+        redeem_shares: shares,
+    });
+
+    msg!("Queued redemption of {} shares for epoch {}", shares, epoch);
+    Ok(())
+}
+
+// ── Authority or keeper closes the epoch and settles it at one share price ──
+
+#[derive(Accounts)]
+pub struct CloseEpoch<'info> {
+    #[account(
+        mut,
+        constraint = caller.key() == vault.load()?.authority
+            || caller.key() == vault.load()?.keeper @ VaultError::Unauthorized,
+    )]
+    pub caller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = caller,
+        space = 8 + EpochBatch::INIT_SPACE,
+        seeds = [BATCH_SEED, &vault.load()?.current_epoch.to_le_bytes()],
+        bump,
+    )]
+    pub batch: Account<'info, EpochBatch>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn close_epoch_handler(ctx: Context<CloseEpoch>) -> Result<()> {
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    let batch = &ctx.accounts.batch;
+
+    // Both sides settle at the pre-settlement share price
+    let shares_minted = if batch.deposit_lamports > 0 {
+        calculate_shares_for_deposit(
+            batch.deposit_lamports,
+            vault.total_deposited,
+            vault.total_shares,
+        )?
+    } else {
+        0
+    };
+    let lamports_owed = if batch.redeem_shares > 0 {
+        calculate_withdrawal_amount(
+            batch.redeem_shares,
+            vault.total_deposited,
+            vault.total_shares,
+        )?
+    } else {
+        0
+    };
+
+    let deposit_lamports = batch.deposit_lamports;
+    let redeem_shares = batch.redeem_shares;
+
+    let epoch = vault.current_epoch;
+    vault.total_deposited = vault
+        .total_deposited
+        .checked_add(deposit_lamports)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_sub(lamports_owed)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.check_rebalance_liquidity(0)?;
+    vault.total_shares = vault
+        .total_shares
+        .checked_add(shares_minted)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_sub(redeem_shares)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.pending_deposits = vault
+        .pending_deposits
+        .checked_sub(deposit_lamports)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.pending_redemptions = vault
+        .pending_redemptions
+        .checked_add(lamports_owed)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.current_epoch = epoch.checked_add(1).ok_or(VaultError::MathOverflow)?;
+    // Batched redemptions count toward the outflow window but are never held back
+    let now = Clock::get()?.unix_timestamp;
+    vault.record_outflow(lamports_owed, now);
+
+    let batch = &mut ctx.accounts.batch;
+    batch.epoch = epoch;
+    batch.bump = ctx.bumps.batch;
+    batch.shares_minted = shares_minted;
+    batch.lamports_owed = lamports_owed;
+    batch.is_settled = true;
+    batch.settled_ts = now;
+    // With no requests there are no tickets to claim it, so hand the rent straight back
+    if deposit_lamports == 0 && redeem_shares == 0 {
+        batch.close(ctx.accounts.caller.to_account_info())?;
+    }
+
+    emit!(EpochSettledEvent {
+        epoch,
+        deposit_lamports,
+        shares_minted,
+        redeem_shares,
+        lamports_owed,
+        total_deposited: vault.total_deposited,
+        total_shares: vault.total_shares,
+    });
+
+    msg!(
+        "Settled epoch {}: {} lamports in for {} shares, {} shares out for {} lamports",
+        epoch,
+        deposit_lamports,
+        shares_minted,
+        redeem_shares,
+        lamports_owed
+    );
+    Ok(())
+}
+
+// ── User claims shares and/or lamports from a settled batch into one of their positions ──
+
+#[derive(Accounts)]
+#[instruction(epoch: u64, index: u16)]
+pub struct ClaimBatch<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [BATCH_SEED, epoch.to_le_bytes().as_ref()],
+        bump = batch.bump,
+        constraint = batch.is_settled @ VaultError::EpochNotSettled,
+    )]
+    pub batch: Account<'info, EpochBatch>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [TICKET_SEED, epoch.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump = ticket.bump,
+        constraint = ticket.owner == user.key() @ VaultError::Unauthorized,
+    )]
+    pub ticket: Account<'info, BatchTicket>,
+
+    /// Only needed when the ticket has deposits to credit, so a redemption-only
+    /// claim doesn't pay rent for an empty position
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user.key().as_ref(), &index.to_le_bytes()],
+        bump,
+    )]
+    pub position: Option<Account<'info, UserPosition>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn claim_handler(ctx: Context<ClaimBatch>, epoch: u64, index: u16) -> Result<()> {
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    let batch = &ctx.accounts.batch;
+    let ticket = &ctx.accounts.ticket;
+
+    // Pro-rata share of each side of the batch; rounding down favors the vault
+    let shares_received: u64 = if ticket.deposit_lamports > 0 {
+        (ticket.deposit_lamports as u128)
+            .checked_mul(batch.shares_minted as u128)
+            .ok_or(VaultError::MathOverflow)?
+            .checked_div(batch.deposit_lamports as u128)
+            .ok_or(VaultError::MathOverflow)?
+            .try_into()
+            .map_err(|_| VaultError::MathOverflow)?
+    } else {
+        0
+    };
+    let gross_amount: u64 = if ticket.redeem_shares > 0 {
+        (ticket.redeem_shares as u128)
+            .checked_mul(batch.lamports_owed as u128)
+            .ok_or(VaultError::MathOverflow)?
+            .checked_div(batch.redeem_shares as u128)
+            .ok_or(VaultError::MathOverflow)?
+            .try_into()
+            .map_err(|_| VaultError::MathOverflow)?
+    } else {
+        0
+    };
+
+    let fee = calculate_performance_fee(
+        gross_amount,
+        ticket.redeem_cost_basis,
+        vault.performance_fee_bps,
+    )?;
+    let net_amount = gross_amount
+        .checked_sub(fee)
+        .ok_or(VaultError::MathOverflow)?;
+    let deposit_lamports = ticket.deposit_lamports;
+    let redeem_shares = ticket.redeem_shares;
+
+    if net_amount > 0 {
+        // Ensure vault stays above rent-exempt minimum after transfer
+        let vault_account_info = ctx.accounts.vault.to_account_info();
+        let rent = Rent::get()?;
+        let min_balance = rent.minimum_balance(vault_account_info.data_len());
+        let vault_lamports_after = vault_account_info
+            .lamports()
+            .checked_sub(net_amount)
+            .ok_or(VaultError::MathOverflow)?;
+        require!(
+            vault_lamports_after >= min_balance,
+            VaultError::BelowRentExemption
+        );
+
+        **vault_account_info.try_borrow_mut_lamports()? = vault_lamports_after;
+        **ctx.accounts.user.try_borrow_mut_lamports()? = ctx
+            .accounts
+            .user
+            .lamports()
+            .checked_add(net_amount)
+            .ok_or(VaultError::MathOverflow)?;
+    }
+
+    vault.pending_redemptions = vault
+        .pending_redemptions
+        .checked_sub(gross_amount)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.accrued_fees = vault
+        .accrued_fees
+        .checked_add(fee)
+        .ok_or(VaultError::MathOverflow)?;
+
+    require!(
+        shares_received == 0 || ctx.accounts.position.is_some(),
+        VaultError::PositionRequired
+    );
+    if let Some(position) = ctx.accounts.position.as_mut() {
+        position.owner = ctx.accounts.user.key();
+        position.index = index;
+        position.bump = ctx.bumps.position.ok_or(VaultError::PositionRequired)?;
+        position.version = POSITION_VERSION;
+        if shares_received > 0 {
+            if position.shares == 0 {
+                vault.depositor_count = vault
+                    .depositor_count
+                    .checked_add(1)
+                    .ok_or(VaultError::MathOverflow)?;
+            }
+            position.shares = position
+                .shares
+                .checked_add(shares_received)
+                .ok_or(VaultError::MathOverflow)?;
+            position.deposited_amount = position
+                .deposited_amount
+                .checked_add(deposit_lamports)
+                .ok_or(VaultError::MathOverflow)?;
+            position.last_deposit_ts = Clock::get()?.unix_timestamp;
+            vault.lock_deposit(position, shares_received)?;
+        }
+    }
+
+    // Take the ticket off the batch, so the last claim gets whatever rounding left
+    // and the batch closes once every ticket is claimed
+    let batch = &mut ctx.accounts.batch;
+    batch.deposit_lamports = batch
+        .deposit_lamports
+        .checked_sub(deposit_lamports)
+        .ok_or(VaultError::MathOverflow)?;
+    batch.shares_minted = batch
+        .shares_minted
+        .checked_sub(shares_received)
+        .ok_or(VaultError::MathOverflow)?;
+    batch.redeem_shares = batch
+        .redeem_shares
+        .checked_sub(redeem_shares)
+        .ok_or(VaultError::MathOverflow)?;
+    batch.lamports_owed = batch
+        .lamports_owed
+        .checked_sub(gross_amount)
+        .ok_or(VaultError::MathOverflow)?;
+    if batch.deposit_lamports == 0 && batch.redeem_shares == 0 {
+        batch.close(ctx.accounts.user.to_account_info())?;
+    }
+
+    emit!(BatchClaimedEvent {
+        user: ctx.accounts.user.key(),
+        epoch,
+        shares_received,
+        amount_returned: net_amount,
+        fee_charged: fee,
+    });
+
+    msg!(
+        "Claimed epoch {}: {} shares, {} lamports (fee: {})",
+        epoch,
+        shares_received,
+        net_amount,
+        fee
+    );
+    Ok(())
+}
@@ -0,0 +1,329 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+use crate::errors::VaultError;
+use crate::instructions::asset::transfer_out;
+use crate::instructions::circuit_breaker::admit_outflow;
+use crate::instructions::lst::{booked_value_of, lst_amount_for_sol, transfer_lst_out};
+use crate::instructions::withdraw::{quote_withdrawal, settle_withdrawal, WithdrawalQuote};
+use crate::state::*;
+
+/// Accounts per LST in `withdraw_in_kind` remaining accounts:
+/// lst_config (mut), mint, vault_lst_account (mut), user_lst_account (mut)
+const IN_KIND_ACCOUNTS_PER_LST: usize = 4;
+
+// ── Redeem shares for a single approved LST at its exchange rate ──
+
+#[derive(Accounts)]
+#[instruction(index: u16, mint: Pubkey)]
+pub struct WithdrawAs<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user.key().as_ref(), &index.to_le_bytes()],
+        bump = position.bump,
+        constraint = position.owner == user.key() @ VaultError::Unauthorized,
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    #[account(
+        mut,
+        seeds = [LST_SEED, mint.as_ref()],
+        bump = lst_config.bump,
+        has_one = rate_state @ VaultError::InvalidRateSource,
+        has_one = vault_lst_account @ VaultError::InvalidTokenAccount,
+        has_one = token_program @ VaultError::InvalidTokenAccount,
+    )]
+    pub lst_config: Account<'info, LstConfig>,
+
+    #[account(address = mint @ VaultError::InvalidTokenAccount)]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: bound to `lst_config.rate_state`; parsed when the rate is read
+    pub rate_state: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub vault_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Burn `shares` and receive their value after fees in the chosen LST,
+/// receiving at least `min_lst_out`
+pub fn withdraw_as_handler(
+    ctx: Context<WithdrawAs>,
+    _index: u16,
+    mint: Pubkey,
+    shares: u64,
+    min_lst_out: u64,
+) -> Result<()> {
+    let vault = ctx.accounts.vault.load()?;
+    let lst_config = &ctx.accounts.lst_config;
+
+    require!(vault.async_mode == 0, VaultError::AsyncModeEnabled);
+
+    let quote = quote_withdrawal(&vault, &ctx.accounts.position, shares)?;
+    drop(vault);
+    admit_outflow(&mut *ctx.accounts.vault.load_mut()?, quote.net_amount)?;
+    let lst_amount = lst_amount_for_sol(lst_config, &ctx.accounts.rate_state, quote.net_amount)?;
+    require!(lst_amount >= min_lst_out, VaultError::SlippageExceeded);
+    require!(
+        lst_amount <= lst_config.lst_balance,
+        VaultError::InsufficientLstBalance
+    );
+
+    transfer_lst_out(
+        &ctx.accounts.vault,
+        &ctx.accounts.token_program.to_account_info(),
+        &ctx.accounts.vault_lst_account.to_account_info(),
+        &ctx.accounts.lst_mint,
+        &ctx.accounts.user_lst_account.to_account_info(),
+        lst_amount,
+    )?;
+
+    let protocol_id = lst_config.protocol_id;
+    // The tokens leave the books at their deposit-time value, the basis `lst_value`
+    // is kept in, not at the current rate they were paid out at
+    let booked_value = booked_value_of(lst_config, lst_amount)?;
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    settle_withdrawal(&mut vault, &mut ctx.accounts.position, shares, &quote)?;
+
+    // A rebalance may have booked the allocation below the LST it holds
+    if let Some(allocation) = vault
+        .active_allocations_mut()
+        .iter_mut()
+        .find(|a| a.protocol_id == protocol_id)
+    {
+        allocation.current_amount = allocation.current_amount.saturating_sub(booked_value);
+    }
+    vault.lst_value = vault
+        .lst_value
+        .checked_sub(booked_value)
+        .ok_or(VaultError::MathOverflow)?;
+
+    let lst_config = &mut ctx.accounts.lst_config;
+    lst_config.lst_balance = lst_config
+        .lst_balance
+        .checked_sub(lst_amount)
+        .ok_or(VaultError::MathOverflow)?;
+    lst_config.booked_value = lst_config
+        .booked_value
+        .checked_sub(booked_value)
+        .ok_or(VaultError::MathOverflow)?;
+
+    emit!(LstWithdrawEvent {
+        user: ctx.accounts.user.key(),
+        index: ctx.accounts.position.index,
+        mint,
+        shares_burned: shares,
+        sol_value: quote.net_amount,
+        lst_amount,
+        fee_charged: quote.fee,
+        total_deposited: vault.total_deposited,
+        total_shares: vault.total_shares,
+    });
+
+    msg!(
+        "Withdrew {} LST worth {} lamports (fee: {}), burned {} shares",
+        lst_amount,
+        quote.net_amount,
+        quote.fee,
+        shares
+    );
+    Ok(())
+}
+
+// ── Redeem shares for a pro-rata slice of every holding ──
+
+#[derive(Accounts)]
+#[instruction(index: u16)]
+pub struct WithdrawInKind<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user.key().as_ref(), &index.to_le_bytes()],
+        bump = position.bump,
+        constraint = position.owner == user.key() @ VaultError::Unauthorized,
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Burn `shares` and receive the same fraction of the vault's booked SOL and of
+/// each LST passed in remaining accounts, scaled down by the performance fee.
+/// The fraction is the burned shares' value over NAV, priced as any other exit.
+/// LSTs left out of remaining accounts stay booked, so their slice goes to the
+/// other holders.
+pub fn withdraw_in_kind_handler<'info>(
+    ctx: Context<'_, '_, 'info, 'info, WithdrawInKind<'info>>,
+    _index: u16,
+    shares: u64,
+) -> Result<()> {
+    let vault = ctx.accounts.vault.load()?;
+
+    require!(vault.async_mode == 0, VaultError::AsyncModeEnabled);
+    require!(vault.is_native(), VaultError::NativeVaultOnly);
+    require!(
+        ctx.remaining_accounts.len().is_multiple_of(IN_KIND_ACCOUNTS_PER_LST),
+        VaultError::InvalidTokenAccount
+    );
+
+    let mut quote = quote_withdrawal(&vault, &ctx.accounts.position, shares)?;
+    let total_deposited = vault.total_deposited;
+    let lst_value = vault.lst_value;
+
+    // SOL side of NAV, from the books rather than the PDA balance so donations and
+    // other untracked lamports stay put. Stake accounts can't be split out here,
+    // so their slice is paid from idle SOL too.
+    let sol_value = total_deposited
+        .checked_sub(lst_value)
+        .ok_or(VaultError::MathOverflow)?;
+    let lamports_out = in_kind_share(sol_value, total_deposited, &quote)?;
+    drop(vault);
+
+    let mut paid_mints: Vec<Pubkey> = Vec::new();
+    let mut paid_booked_value: u64 = 0;
+    for chunk in ctx.remaining_accounts.chunks(IN_KIND_ACCOUNTS_PER_LST) {
+        let mut lst_config: Account<'info, LstConfig> = Account::try_from(&chunk[0])?;
+        let mint: InterfaceAccount<'info, Mint> = InterfaceAccount::try_from(&chunk[1])?;
+        require_keys_eq!(mint.key(), lst_config.mint, VaultError::InvalidTokenAccount);
+        require_keys_eq!(
+            chunk[2].key(),
+            lst_config.vault_lst_account,
+            VaultError::InvalidTokenAccount
+        );
+        require_keys_eq!(
+            ctx.accounts.token_program.key(),
+            lst_config.token_program,
+            VaultError::InvalidTokenAccount
+        );
+        // Each LST may only be paid once
+        require!(!paid_mints.contains(&mint.key()), VaultError::DuplicateLst);
+        paid_mints.push(mint.key());
+
+        let lst_amount = in_kind_share(lst_config.lst_balance, total_deposited, &quote)?;
+        if lst_amount == 0 {
+            continue;
+        }
+        let booked_value = booked_value_of(&lst_config, lst_amount)?;
+
+        transfer_lst_out(
+            &ctx.accounts.vault,
+            &ctx.accounts.token_program.to_account_info(),
+            &chunk[2],
+            &mint,
+            &chunk[3],
+            lst_amount,
+        )?;
+
+        lst_config.lst_balance = lst_config
+            .lst_balance
+            .checked_sub(lst_amount)
+            .ok_or(VaultError::MathOverflow)?;
+        lst_config.booked_value = lst_config
+            .booked_value
+            .checked_sub(booked_value)
+            .ok_or(VaultError::MathOverflow)?;
+        lst_config.exit(&crate::ID)?;
+        paid_booked_value = paid_booked_value
+            .checked_add(booked_value)
+            .ok_or(VaultError::MathOverflow)?;
+
+        msg!("Paid {} of LST {}", lst_amount, mint.key());
+    }
+
+    // The slice of LSTs left out stays in NAV for the other holders: the exit only
+    // takes off the books what it was paid
+    let forfeited = in_kind_share(lst_value, total_deposited, &quote)?
+        .saturating_sub(paid_booked_value);
+    quote.gross_amount = quote
+        .gross_amount
+        .checked_sub(forfeited)
+        .ok_or(VaultError::MathOverflow)?;
+    quote.net_amount = quote
+        .net_amount
+        .checked_sub(forfeited)
+        .ok_or(VaultError::MathOverflow)?;
+    admit_outflow(&mut *ctx.accounts.vault.load_mut()?, quote.net_amount)?;
+
+    if lamports_out > 0 {
+        let recipient = ctx.accounts.user.to_account_info();
+        transfer_out(&ctx.accounts.vault, &recipient, None, lamports_out)?;
+    }
+
+    // NAV shrinks by the booked value taken, so allocations shrink in proportion
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    for allocation in vault.active_allocations_mut().iter_mut() {
+        let removed = pro_rata(allocation.current_amount, quote.gross_amount, total_deposited)?;
+        allocation.current_amount = allocation.current_amount.saturating_sub(removed);
+    }
+    vault.lst_value = vault
+        .lst_value
+        .checked_sub(paid_booked_value)
+        .ok_or(VaultError::MathOverflow)?;
+
+    settle_withdrawal(&mut vault, &mut ctx.accounts.position, shares, &quote)?;
+
+    emit!(InKindWithdrawEvent {
+        user: ctx.accounts.user.key(),
+        index: ctx.accounts.position.index,
+        shares_burned: shares,
+        lamports_returned: lamports_out,
+        lst_mints_paid: paid_mints.len() as u8,
+        fee_charged: quote.fee,
+        total_deposited: vault.total_deposited,
+        total_shares: vault.total_shares,
+    });
+
+    msg!(
+        "Withdrew in kind: {} lamports and {} LSTs (fee: {}), burned {} shares",
+        lamports_out,
+        paid_mints.len(),
+        quote.fee,
+        shares
+    );
+    Ok(())
+}
+
+/// `balance * part / whole`, rounded down.
+fn pro_rata(balance: u64, part: u64, whole: u64) -> Result<u64> {
+    let amount: u64 = (balance as u128)
+        .checked_mul(part as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_div(whole as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .try_into()
+        .map_err(|_| VaultError::MathOverflow)?;
+    Ok(amount)
+}
+
+/// Slice of `balance` paid for the burned shares: their share of NAV
+/// (`gross / total_deposited`), reduced by the fee ratio (`net / gross`) so the
+/// fee stays in the vault. Rounds down.
+fn in_kind_share(balance: u64, total_deposited: u64, quote: &WithdrawalQuote) -> Result<u64> {
+    if quote.net_amount == 0 {
+        return Ok(0);
+    }
+    pro_rata(balance, quote.net_amount, total_deposited)
+}
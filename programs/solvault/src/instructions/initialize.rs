@@ -1,5 +1,9 @@
 use anchor_lang::prelude::*;
+use anchor_spl::token_2022::spl_token_2022::extension::transfer_fee::TransferFeeConfig;
+use anchor_spl::token_interface::{get_mint_extension_data, Mint, TokenAccount, TokenInterface};
 use crate::errors::VaultError;
+use crate::instructions::asset::{transfer_in, TokenTransfer};
+use crate::instructions::registry::validate_targets;
 use crate::state::*;
 
 #[derive(Accounts)]
@@ -10,11 +14,31 @@ pub struct Initialize<'info> {
     #[account(
         init,
         payer = authority,
-        space = 8 + Vault::INIT_SPACE,
+        space = Vault::SPACE,
         seeds = [VAULT_SEED],
         bump,
     )]
-    pub vault: Account<'info, Vault>,
+    pub vault: AccountLoader<'info, Vault>,
+
+    /// Allocation targets must be active protocols in the registry
+    #[account(
+        seeds = [REGISTRY_SEED],
+        bump = registry.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub registry: Account<'info, ProtocolRegistry>,
+
+    /// Base asset mint; omit for a native SOL vault
+    pub base_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    /// Token account owned by the vault PDA that will custody the base asset
+    pub vault_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Authority's base asset account, funding the dead shares of a token vault
+    #[account(mut)]
+    pub authority_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
 
     pub system_program: Program<'info, System>,
 }
@@ -23,7 +47,7 @@ pub fn handler(
     ctx: Context<Initialize>,
     performance_fee_bps: u16,
     deposit_cap: u64,
-    allocations: Vec<Allocation>,
+    allocations: Vec<AllocationTarget>,
 ) -> Result<()> {
     require!(
         performance_fee_bps <= MAX_FEE_BPS,
@@ -34,38 +58,85 @@ pub fn handler(
         VaultError::TooManyAllocations
     );
 
-    let total_pct: u16 = allocations.iter().map(|a| a.target_pct as u16).sum();
-    require!(total_pct == 100, VaultError::InvalidAllocations);
+    let total_bps: u64 = allocations.iter().map(|a| a.target_bps as u64).sum();
+    require!(total_bps == BPS_DENOMINATOR, VaultError::InvalidAllocations);
 
-    // Validate no duplicate protocol IDs
-    for (i, a) in allocations.iter().enumerate() {
-        for (j, b) in allocations.iter().enumerate() {
-            if i != j && a.protocol_id == b.protocol_id {
-                return err!(VaultError::InvalidAllocations);
-            }
+    // Nothing is deposited yet, so only the target bounds can bind
+    validate_targets(
+        &ctx.accounts.registry,
+        &allocations,
+        0,
+        Some(ctx.remaining_accounts),
+    )?;
+
+    let (base_mint, vault_token_account, token_program, token) = match (
+        &ctx.accounts.base_mint,
+        &ctx.accounts.vault_token_account,
+        &ctx.accounts.authority_token_account,
+        &ctx.accounts.token_program,
+    ) {
+        (None, None, None, None) => {
+            (Pubkey::default(), Pubkey::default(), Pubkey::default(), None)
+        }
+        (Some(mint), Some(token_account), Some(authority_token_account), Some(token_program)) => {
+            let mint_info = mint.to_account_info();
+            require_keys_eq!(
+                *mint_info.owner,
+                token_program.key(),
+                VaultError::InvalidTokenAccount
+            );
+            require_keys_eq!(token_account.mint, mint.key(), VaultError::InvalidTokenAccount);
+            require_keys_eq!(
+                token_account.owner,
+                ctx.accounts.vault.key(),
+                VaultError::InvalidTokenAccount
+            );
+            // Fee-on-transfer mints would credit shares for tokens the vault never receives
+            require!(
+                get_mint_extension_data::<TransferFeeConfig>(&mint_info).is_err(),
+                VaultError::UnsupportedMint
+            );
+            require_keys_eq!(
+                authority_token_account.mint,
+                mint.key(),
+                VaultError::InvalidTokenAccount
+            );
+            let token = TokenTransfer {
+                mint,
+                vault_token_account: token_account,
+                user_token_account: authority_token_account,
+                token_program,
+            };
+            (mint.key(), token_account.key(), token_program.key(), Some(token))
         }
-    }
+        _ => return err!(VaultError::MissingTokenAccounts),
+    };
 
-    // Zero out current_amount to prevent stale values from init args
-    let mut cleaned_allocations = allocations;
-    for alloc in cleaned_allocations.iter_mut() {
-        alloc.current_amount = 0;
-    }
+    // Dead shares belong to no position and can never be redeemed, so the share
+    // supply never returns to zero
+    transfer_in(
+        &ctx.accounts.vault,
+        &ctx.accounts.authority.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        token,
+        DEAD_SHARES,
+    )?;
 
-    let vault = &mut ctx.accounts.vault;
+    // The account starts zeroed, so only non-zero fields need setting
+    let mut vault = ctx.accounts.vault.load_init()?;
     vault.authority = ctx.accounts.authority.key();
-    vault.total_deposited = 0;
-    vault.total_shares = 0;
     vault.performance_fee_bps = performance_fee_bps;
     vault.deposit_cap = deposit_cap;
-    vault.is_paused = false;
-    vault.num_allocations = cleaned_allocations.len() as u8;
-    vault.allocations = cleaned_allocations;
+    let now = Clock::get()?.unix_timestamp;
+    vault.set_allocations(&allocations, 0, 0, now)?;
     vault.bump = ctx.bumps.vault;
-    vault.accrued_fees = 0;
-    vault.last_rebalance_ts = Clock::get()?.unix_timestamp;
-    vault.depositor_count = 0;
-    vault.pending_authority = Pubkey::default();
+    vault.last_rebalance_ts = now;
+    vault.base_mint = base_mint;
+    vault.vault_token_account = vault_token_account;
+    vault.token_program = token_program;
+    vault.version = VAULT_VERSION;
+    vault.total_deposited = DEAD_SHARES;
+    vault.total_shares = DEAD_SHARES;
 
     msg!("Vault initialized with {} allocations", vault.num_allocations);
     Ok(())
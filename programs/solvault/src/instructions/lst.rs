@@ -0,0 +1,445 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{
+    self, Mint, TokenAccount, TokenInterface, TransferChecked,
+};
+use crate::errors::VaultError;
+use crate::instructions::deposit::{credit_deposit, quote_deposit};
+use crate::state::*;
+
+/// `pool_mint` in an SPL stake pool account
+const STAKE_POOL_MINT_OFFSET: usize = 162;
+/// `total_lamports`, `pool_token_supply`, `last_update_epoch` in an SPL stake pool account
+const STAKE_POOL_TOTALS_OFFSET: usize = 258;
+/// `msol_mint` in the Marinade state account (after the 8-byte discriminator)
+const MARINADE_MSOL_MINT_OFFSET: usize = 8;
+/// `msol_price` in the Marinade state account
+const MARINADE_MSOL_PRICE_OFFSET: usize = 512;
+/// Marinade prices mSOL in lamports scaled by 2^32
+const MARINADE_PRICE_DENOMINATOR: u128 = 1 << 32;
+
+// ── Authority approves an LST and where its exchange rate comes from ──
+
+#[derive(Accounts)]
+pub struct RegisterLst<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: stake pool / Marinade state; owner and mint are verified when the rate is read
+    pub rate_state: UncheckedAccount<'info>,
+
+    #[account(
+        token::mint = mint,
+        token::authority = vault,
+        token::token_program = token_program,
+    )]
+    pub vault_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + LstConfig::INIT_SPACE,
+        seeds = [LST_SEED, mint.key().as_ref()],
+        bump,
+    )]
+    pub lst_config: Account<'info, LstConfig>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn register_lst_handler(
+    ctx: Context<RegisterLst>,
+    protocol_id: u8,
+    rate_source: LstRateSource,
+) -> Result<()> {
+    require!(
+        ctx.accounts
+            .vault
+            .load()?
+            .active_allocations()
+            .iter()
+            .any(|a| a.protocol_id == protocol_id),
+        VaultError::InvalidAllocations
+    );
+
+    let mint = ctx.accounts.mint.key();
+    read_exchange_rate(rate_source, &ctx.accounts.rate_state, &mint)?;
+
+    let lst_config = &mut ctx.accounts.lst_config;
+    lst_config.mint = mint;
+    lst_config.rate_source = rate_source;
+    lst_config.rate_state = ctx.accounts.rate_state.key();
+    lst_config.vault_lst_account = ctx.accounts.vault_lst_account.key();
+    lst_config.token_program = ctx.accounts.token_program.key();
+    lst_config.protocol_id = protocol_id;
+    lst_config.lst_balance = 0;
+    lst_config.booked_value = 0;
+    lst_config.is_enabled = true;
+    lst_config.bump = ctx.bumps.lst_config;
+
+    emit!(LstConfigUpdatedEvent {
+        mint,
+        protocol_id,
+        is_enabled: true,
+    });
+
+    msg!("Approved LST {} for allocation {}", mint, protocol_id);
+    Ok(())
+}
+
+// ── Authority pauses or resumes deposits of an approved LST ──
+
+#[derive(Accounts)]
+pub struct UpdateLst<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [LST_SEED, lst_config.mint.as_ref()],
+        bump = lst_config.bump,
+    )]
+    pub lst_config: Account<'info, LstConfig>,
+}
+
+pub fn update_lst_handler(ctx: Context<UpdateLst>, is_enabled: bool) -> Result<()> {
+    let lst_config = &mut ctx.accounts.lst_config;
+    lst_config.is_enabled = is_enabled;
+
+    emit!(LstConfigUpdatedEvent {
+        mint: lst_config.mint,
+        protocol_id: lst_config.protocol_id,
+        is_enabled,
+    });
+
+    msg!("LST {} deposits enabled: {}", lst_config.mint, is_enabled);
+    Ok(())
+}
+
+// ── Deposit an approved LST, valued in SOL at its on-chain exchange rate ──
+
+#[derive(Accounts)]
+#[instruction(index: u16, mint: Pubkey)]
+pub struct DepositLst<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user.key().as_ref(), &index.to_le_bytes()],
+        bump,
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    #[account(
+        mut,
+        seeds = [LST_SEED, mint.as_ref()],
+        bump = lst_config.bump,
+        has_one = rate_state @ VaultError::InvalidRateSource,
+        has_one = vault_lst_account @ VaultError::InvalidTokenAccount,
+        has_one = token_program @ VaultError::InvalidTokenAccount,
+    )]
+    pub lst_config: Account<'info, LstConfig>,
+
+    #[account(address = mint @ VaultError::InvalidTokenAccount)]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: bound to `lst_config.rate_state`; parsed when the rate is read
+    pub rate_state: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub vault_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn deposit_lst_handler(
+    ctx: Context<DepositLst>,
+    index: u16,
+    mint: Pubkey,
+    amount: u64,
+    min_shares_out: u64,
+) -> Result<()> {
+    let vault = ctx.accounts.vault.load()?;
+    let lst_config = &ctx.accounts.lst_config;
+
+    require!(vault.async_mode == 0, VaultError::AsyncModeEnabled);
+    // Shares of a token vault are priced in its own base asset, not SOL
+    require!(vault.is_native(), VaultError::NativeVaultOnly);
+    require!(lst_config.is_enabled, VaultError::LstNotApproved);
+    require!(amount > 0, VaultError::ZeroAmount);
+
+    // Value the LST in SOL; minimum, cap and pause checks apply to that value
+    let sol_value = lst_sol_value(lst_config, &ctx.accounts.rate_state, amount)?;
+    let shares_to_mint = quote_deposit(&vault, sol_value)?;
+    require!(shares_to_mint >= min_shares_out, VaultError::SlippageExceeded);
+    drop(vault);
+
+    token_interface::transfer_checked(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.user_lst_account.to_account_info(),
+                mint: ctx.accounts.lst_mint.to_account_info(),
+                to: ctx.accounts.vault_lst_account.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+            },
+        ),
+        amount,
+        ctx.accounts.lst_mint.decimals,
+    )?;
+
+    let protocol_id = lst_config.protocol_id;
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    credit_deposit(
+        &mut vault,
+        &mut ctx.accounts.position,
+        ctx.accounts.user.key(),
+        index,
+        ctx.bumps.position,
+        sol_value,
+        shares_to_mint,
+    )?;
+
+    // Book the LST straight into its allocation instead of the idle reserve
+    let allocation = vault
+        .active_allocations_mut()
+        .iter_mut()
+        .find(|a| a.protocol_id == protocol_id)
+        .ok_or(VaultError::InvalidAllocations)?;
+    allocation.current_amount = allocation
+        .current_amount
+        .checked_add(sol_value)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.lst_value = vault
+        .lst_value
+        .checked_add(sol_value)
+        .ok_or(VaultError::MathOverflow)?;
+
+    let lst_config = &mut ctx.accounts.lst_config;
+    lst_config.lst_balance = lst_config
+        .lst_balance
+        .checked_add(amount)
+        .ok_or(VaultError::MathOverflow)?;
+    lst_config.booked_value = lst_config
+        .booked_value
+        .checked_add(sol_value)
+        .ok_or(VaultError::MathOverflow)?;
+
+    emit!(LstDepositEvent {
+        user: ctx.accounts.user.key(),
+        index,
+        mint,
+        lst_amount: amount,
+        sol_value,
+        shares_minted: shares_to_mint,
+        total_deposited: vault.total_deposited,
+        total_shares: vault.total_shares,
+    });
+
+    msg!(
+        "Deposited {} LST worth {} lamports, minted {} shares",
+        amount,
+        sol_value,
+        shares_to_mint
+    );
+    Ok(())
+}
+
+/// LST exchange rate as a lamports / token-supply ratio.
+pub(crate) struct ExchangeRate {
+    pub lamports: u128,
+    pub supply: u128,
+    /// Epoch the rate was last refreshed, when the source records it
+    pub last_update_epoch: Option<u64>,
+}
+
+/// Read the exchange rate of `mint` from its stake pool or Marinade state account,
+/// verifying the account belongs to the expected program and prices this mint.
+pub(crate) fn read_exchange_rate(
+    rate_source: LstRateSource,
+    rate_state: &AccountInfo,
+    mint: &Pubkey,
+) -> Result<ExchangeRate> {
+    let data = rate_state.try_borrow_data()?;
+    match rate_source {
+        LstRateSource::SplStakePool => {
+            require_keys_eq!(
+                *rate_state.owner,
+                SPL_STAKE_POOL_PROGRAM_ID,
+                VaultError::InvalidRateSource
+            );
+            // account_type 1 = initialized StakePool
+            require!(
+                data.len() >= STAKE_POOL_TOTALS_OFFSET + 24 && data[0] == 1,
+                VaultError::InvalidRateSource
+            );
+            require!(
+                read_pubkey(&data, STAKE_POOL_MINT_OFFSET) == *mint,
+                VaultError::InvalidRateSource
+            );
+            Ok(ExchangeRate {
+                lamports: read_u64(&data, STAKE_POOL_TOTALS_OFFSET) as u128,
+                supply: read_u64(&data, STAKE_POOL_TOTALS_OFFSET + 8) as u128,
+                last_update_epoch: Some(read_u64(&data, STAKE_POOL_TOTALS_OFFSET + 16)),
+            })
+        }
+        LstRateSource::Marinade => {
+            require_keys_eq!(
+                *rate_state.owner,
+                MARINADE_PROGRAM_ID,
+                VaultError::InvalidRateSource
+            );
+            require!(
+                data.len() >= MARINADE_MSOL_PRICE_OFFSET + 8,
+                VaultError::InvalidRateSource
+            );
+            require!(
+                read_pubkey(&data, MARINADE_MSOL_MINT_OFFSET) == *mint,
+                VaultError::InvalidRateSource
+            );
+            Ok(ExchangeRate {
+                lamports: read_u64(&data, MARINADE_MSOL_PRICE_OFFSET) as u128,
+                supply: MARINADE_PRICE_DENOMINATOR,
+                last_update_epoch: None,
+            })
+        }
+    }
+}
+
+/// Exchange rate of an approved LST, usable for pricing right now.
+/// Stake pools must have been updated this epoch so the rate includes all rewards.
+fn current_rate(lst_config: &LstConfig, rate_state: &AccountInfo) -> Result<ExchangeRate> {
+    let rate = read_exchange_rate(lst_config.rate_source, rate_state, &lst_config.mint)?;
+    if let Some(last_update_epoch) = rate.last_update_epoch {
+        require!(
+            last_update_epoch == Clock::get()?.epoch,
+            VaultError::StaleExchangeRate
+        );
+    }
+    require!(
+        rate.lamports > 0 && rate.supply > 0,
+        VaultError::InvalidRateSource
+    );
+    Ok(rate)
+}
+
+/// SOL value of `amount` LST at the current exchange rate, rounded down.
+pub(crate) fn lst_sol_value(
+    lst_config: &LstConfig,
+    rate_state: &AccountInfo,
+    amount: u64,
+) -> Result<u64> {
+    let rate = current_rate(lst_config, rate_state)?;
+    let value: u64 = (amount as u128)
+        .checked_mul(rate.lamports)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_div(rate.supply)
+        .ok_or(VaultError::MathOverflow)?
+        .try_into()
+        .map_err(|_| VaultError::MathOverflow)?;
+    Ok(value)
+}
+
+/// LST amount worth `lamports` at the current exchange rate, rounded down.
+pub(crate) fn lst_amount_for_sol(
+    lst_config: &LstConfig,
+    rate_state: &AccountInfo,
+    lamports: u64,
+) -> Result<u64> {
+    let rate = current_rate(lst_config, rate_state)?;
+    let amount: u64 = (lamports as u128)
+        .checked_mul(rate.supply)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_div(rate.lamports)
+        .ok_or(VaultError::MathOverflow)?
+        .try_into()
+        .map_err(|_| VaultError::MathOverflow)?;
+    Ok(amount)
+}
+
+/// Booked SOL value of `amount` of the LST's `lst_balance`, rounded down; the last
+/// tokens out take whatever is left, so the booked value never strands.
+pub(crate) fn booked_value_of(lst_config: &LstConfig, amount: u64) -> Result<u64> {
+    if amount >= lst_config.lst_balance {
+        return Ok(lst_config.booked_value);
+    }
+    let value: u64 = (lst_config.booked_value as u128)
+        .checked_mul(amount as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_div(lst_config.lst_balance as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .try_into()
+        .map_err(|_| VaultError::MathOverflow)?;
+    Ok(value)
+}
+
+/// Send `amount` LST from the vault's token account, signed by the vault PDA.
+pub(crate) fn transfer_lst_out<'info>(
+    vault: &AccountLoader<'info, Vault>,
+    token_program: &AccountInfo<'info>,
+    vault_lst_account: &AccountInfo<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    destination: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    let bump = vault.load()?.bump;
+    let signer_seeds: &[&[&[u8]]] = &[&[VAULT_SEED, &[bump]]];
+    token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            token_program.clone(),
+            TransferChecked {
+                from: vault_lst_account.clone(),
+                mint: mint.to_account_info(),
+                to: destination.clone(),
+                authority: vault.to_account_info(),
+            },
+            signer_seeds,
+        ),
+        amount,
+        mint.decimals,
+    )
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn read_pubkey(data: &[u8], offset: usize) -> Pubkey {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&data[offset..offset + 32]);
+    Pubkey::new_from_array(bytes)
+}
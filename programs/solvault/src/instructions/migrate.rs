@@ -0,0 +1,250 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
+use crate::errors::VaultError;
+use crate::state::*;
+
+// In-place layout upgrades. Positions only ever gain appended fields where zero is
+// a valid default, so migrating means growing the account (zero-extended), filling
+// in anything that needs a non-zero value and stamping the version. Vaults moved
+// from borsh to zero-copy in v3, so older vaults are rewritten field by field;
+// zero-copy vaults are upgraded in place.
+
+#[derive(Accounts)]
+pub struct MigrateVault<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: may still hold a borsh layout that `AccountLoader<Vault>` cannot load;
+    /// program ownership, discriminator and authority are verified in the handler
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump,
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn migrate_vault_handler(ctx: Context<MigrateVault>) -> Result<()> {
+    let vault_info = ctx.accounts.vault.to_account_info();
+
+    // `authority` is the first field after the discriminator in every layout
+    let stored_authority = read_owner_field(&vault_info, Vault::DISCRIMINATOR)?;
+    require_keys_eq!(
+        stored_authority,
+        ctx.accounts.authority.key(),
+        VaultError::Unauthorized
+    );
+
+    // Zero-copy layouts have a fixed size: v3/v4 vaults are exactly
+    // `LEGACY_ZERO_COPY_SPACE` and anything shorter is a borsh (v1/v2) layout
+    let old_len = vault_info.data_len();
+    require!(old_len < Vault::SPACE, VaultError::AlreadyMigrated);
+    grow_account(
+        &vault_info,
+        &ctx.accounts.authority.to_account_info(),
+        &ctx.accounts.system_program,
+        Vault::SPACE,
+    )?;
+    let from_version = if old_len == LEGACY_ZERO_COPY_SPACE {
+        migrate_zero_copy_vault(&vault_info)?
+    } else {
+        migrate_borsh_vault(&vault_info)?
+    };
+
+    emit!(AccountMigratedEvent {
+        account: vault_info.key(),
+        from_version,
+        to_version: VAULT_VERSION,
+    });
+
+    msg!("Migrated vault from v{} to v{}", from_version, VAULT_VERSION);
+    Ok(())
+}
+
+/// v1/v2 → current: rewrite the borsh fields into the zero-copy layout
+fn migrate_borsh_vault(vault_info: &AccountInfo) -> Result<u8> {
+    let legacy = LegacyVault::deserialize(&mut &vault_info.try_borrow_data()?[8..])
+        .map_err(|_| ErrorCode::AccountDidNotDeserialize)?;
+    require!(
+        legacy.allocations.len() <= MAX_ALLOCATIONS,
+        VaultError::TooManyAllocations
+    );
+
+    let mut data = vault_info.try_borrow_mut_data()?;
+    data[8..].fill(0);
+    let vault: &mut Vault = bytemuck::from_bytes_mut(&mut data[8..Vault::SPACE]);
+    vault.authority = legacy.authority;
+    vault.pending_authority = legacy.pending_authority;
+    vault.keeper = legacy.keeper;
+    vault.base_mint = legacy.base_mint;
+    vault.vault_token_account = legacy.vault_token_account;
+    vault.token_program = legacy.token_program;
+    vault.total_deposited = legacy.total_deposited;
+    vault.total_shares = legacy.total_shares;
+    vault.deposit_cap = legacy.deposit_cap;
+    vault.accrued_fees = legacy.accrued_fees;
+    vault.last_rebalance_ts = legacy.last_rebalance_ts;
+    vault.depositor_count = legacy.depositor_count;
+    vault.current_epoch = legacy.current_epoch;
+    vault.pending_deposits = legacy.pending_deposits;
+    vault.pending_redemptions = legacy.pending_redemptions;
+    vault.lst_value = legacy.lst_value;
+    vault.stake_value = legacy.stake_value;
+    vault.performance_fee_bps = legacy.performance_fee_bps;
+    vault.is_paused = legacy.is_paused as u8;
+    vault.async_mode = legacy.async_mode as u8;
+    vault.bump = legacy.bump;
+    for (slot, allocation) in vault.allocations.iter_mut().zip(&legacy.allocations) {
+        slot.protocol_id = allocation.protocol_id;
+        slot.target_bps = allocation.target_pct as u16 * 100;
+        slot.current_amount = allocation.current_amount;
+    }
+    vault.num_allocations = legacy.allocations.len() as u8;
+    vault.version = VAULT_VERSION;
+    Ok(legacy.version)
+}
+
+/// Size of v3/v4 vaults, whose allocation slots were `LegacyZeroCopyAllocation`
+const LEGACY_ZERO_COPY_SPACE: usize = Vault::SPACE
+    - MAX_ALLOCATIONS
+        * (std::mem::size_of::<Allocation>() - std::mem::size_of::<LegacyZeroCopyAllocation>());
+
+/// v3/v4 → current: the header is unchanged; allocation slots are widened with
+/// default targets and empty params, and v3 percentage targets become bps
+fn migrate_zero_copy_vault(vault_info: &AccountInfo) -> Result<u8> {
+    let mut data = vault_info.try_borrow_mut_data()?;
+    let start = 8 + std::mem::offset_of!(Vault, allocations);
+    let end = start + MAX_ALLOCATIONS * std::mem::size_of::<LegacyZeroCopyAllocation>();
+    let legacy: [LegacyZeroCopyAllocation; MAX_ALLOCATIONS] =
+        bytemuck::pod_read_unaligned(&data[start..end]);
+    data[start..].fill(0);
+
+    let vault: &mut Vault = bytemuck::from_bytes_mut(&mut data[8..Vault::SPACE]);
+    let from_version = vault.version;
+    for (slot, allocation) in vault.active_allocations_mut().iter_mut().zip(&legacy) {
+        slot.current_amount = allocation.current_amount;
+        slot.protocol_id = allocation.protocol_id;
+        slot.target_bps = if from_version < 4 {
+            allocation.target_pct as u16 * 100
+        } else {
+            allocation.target_bps
+        };
+    }
+    vault.version = VAULT_VERSION;
+    Ok(from_version)
+}
+
+#[derive(Accounts)]
+#[instruction(index: u16)]
+pub struct MigratePosition<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: may still hold an older layout that `Account<UserPosition>` cannot
+    /// load; program ownership, discriminator and owner are verified in the handler
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user.key().as_ref(), &index.to_le_bytes()],
+        bump,
+    )]
+    pub position: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn migrate_position_handler(ctx: Context<MigratePosition>, index: u16) -> Result<()> {
+    let position_info = ctx.accounts.position.to_account_info();
+
+    let stored_owner = read_owner_field(&position_info, UserPosition::DISCRIMINATOR)?;
+    require_keys_eq!(
+        stored_owner,
+        ctx.accounts.user.key(),
+        VaultError::Unauthorized
+    );
+
+    grow_account(
+        &position_info,
+        &ctx.accounts.user.to_account_info(),
+        &ctx.accounts.system_program,
+        8 + UserPosition::INIT_SPACE,
+    )?;
+
+    let mut position =
+        UserPosition::try_deserialize(&mut &position_info.try_borrow_data()?[..])?;
+    let from_version = position.version;
+    require!(from_version < POSITION_VERSION, VaultError::AlreadyMigrated);
+
+    // v1 → v2: the index is part of the seeds, so record it explicitly
+    position.index = index;
+    position.version = POSITION_VERSION;
+    position.try_serialize(&mut &mut position_info.try_borrow_mut_data()?[..])?;
+
+    emit!(AccountMigratedEvent {
+        account: position_info.key(),
+        from_version,
+        to_version: POSITION_VERSION,
+    });
+
+    msg!(
+        "Migrated position {} from v{} to v{}",
+        index,
+        from_version,
+        POSITION_VERSION
+    );
+    Ok(())
+}
+
+/// Check the account belongs to this program with the expected discriminator and
+/// return the pubkey stored right after the discriminator.
+fn read_owner_field(account: &AccountInfo, discriminator: &[u8]) -> Result<Pubkey> {
+    require_keys_eq!(
+        *account.owner,
+        crate::ID,
+        ErrorCode::AccountOwnedByWrongProgram
+    );
+    let data = account.try_borrow_data()?;
+    require!(
+        data.len() >= 8 + 32 && &data[..8] == discriminator,
+        ErrorCode::AccountDiscriminatorMismatch
+    );
+    let mut owner = [0u8; 32];
+    owner.copy_from_slice(&data[8..40]);
+    Ok(Pubkey::new_from_array(owner))
+}
+
+/// Zero-extend `account` to `new_len`, with `payer` covering the extra rent.
+/// The increase is charged in full because the vault's balance also holds deposits.
+fn grow_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+    new_len: usize,
+) -> Result<()> {
+    let old_len = account.data_len();
+    if old_len >= new_len {
+        return Ok(());
+    }
+
+    let rent = Rent::get()?;
+    let shortfall = rent
+        .minimum_balance(new_len)
+        .saturating_sub(rent.minimum_balance(old_len));
+    if shortfall > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                system_program::Transfer {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+            ),
+            shortfall,
+        )?;
+    }
+
+    account.resize(new_len)?;
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+use crate::errors::VaultError;
+use crate::state::*;
+
+// Positions created before sub-position indexes live at `[POSITION_SEED, owner]`.
+// This moves one into an indexed slot and closes the legacy account.
+
+#[derive(Accounts)]
+#[instruction(index: u16)]
+pub struct MigrateLegacyPosition<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: legacy index-less position; program ownership, discriminator and
+    /// owner field are verified in the handler
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user.key().as_ref()],
+        bump,
+    )]
+    pub legacy_position: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user.key().as_ref(), &index.to_le_bytes()],
+        bump,
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler(ctx: Context<MigrateLegacyPosition>, index: u16) -> Result<()> {
+    let legacy_info = ctx.accounts.legacy_position.to_account_info();
+
+    require!(
+        legacy_info.owner == &crate::ID,
+        VaultError::NotLegacyPosition
+    );
+
+    let legacy: LegacyUserPosition = {
+        let data = legacy_info.try_borrow_data()?;
+        require!(
+            data.len() > 8 && &data[..8] == UserPosition::DISCRIMINATOR,
+            VaultError::NotLegacyPosition
+        );
+        LegacyUserPosition::deserialize(&mut &data[8..])
+            .map_err(|_| VaultError::NotLegacyPosition)?
+    };
+    require!(
+        legacy.owner == ctx.accounts.user.key(),
+        VaultError::Unauthorized
+    );
+
+    let position = &mut ctx.accounts.position;
+    position.owner = legacy.owner;
+    position.index = index;
+    position.shares = legacy.shares;
+    position.deposited_amount = legacy.deposited_amount;
+    position.last_deposit_ts = legacy.last_deposit_ts;
+    position.bump = ctx.bumps.position;
+    position.delegate = Pubkey::default();
+    position.version = POSITION_VERSION;
+
+    // Close the legacy account: refund rent to the owner and hand it back to the system program
+    let user_info = ctx.accounts.user.to_account_info();
+    **user_info.try_borrow_mut_lamports()? = user_info
+        .lamports()
+        .checked_add(legacy_info.lamports())
+        .ok_or(VaultError::MathOverflow)?;
+    **legacy_info.try_borrow_mut_lamports()? = 0;
+    legacy_info.assign(&anchor_lang::system_program::ID);
+    legacy_info.resize(0)?;
+
+    emit!(PositionMigratedEvent {
+        owner: legacy.owner,
+        index,
+        shares: legacy.shares,
+    });
+
+    msg!(
+        "Migrated legacy position with {} shares to index {}",
+        legacy.shares,
+        index
+    );
+    Ok(())
+}
@@ -7,6 +7,21 @@ pub mod update_config;
 pub mod collect_fees;
 pub mod close_position;
 pub mod transfer_authority;
+pub mod epoch_batch;
+pub mod preview;
+pub mod position_transfer;
+pub mod migrate_legacy_position;
+pub mod asset;
+pub mod lst;
+pub mod in_kind;
+pub mod stake_deposit;
+pub mod migrate;
+pub mod registry;
+pub mod schedule;
+pub mod yield_feed;
+pub mod optimizer;
+pub mod rebalance_session;
+pub mod circuit_breaker;
 
 pub use initialize::*;
 pub use deposit::*;
@@ -17,3 +32,17 @@ pub use update_config::*;
 pub use collect_fees::*;
 pub use close_position::*;
 pub use transfer_authority::*;
+pub use epoch_batch::*;
+pub use preview::*;
+pub use position_transfer::*;
+pub use migrate_legacy_position::*;
+pub use lst::*;
+pub use in_kind::*;
+pub use stake_deposit::*;
+pub use migrate::*;
+pub use registry::*;
+pub use schedule::*;
+pub use yield_feed::*;
+pub use optimizer::*;
+pub use rebalance_session::*;
+pub use circuit_breaker::*;
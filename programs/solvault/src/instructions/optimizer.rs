@@ -0,0 +1,135 @@
+use anchor_lang::prelude::*;
+use crate::errors::VaultError;
+use crate::state::*;
+
+// ── Authority switches rebalance between hand-set and feed-derived targets ──
+
+#[derive(Accounts)]
+pub struct SetAutoAllocate<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+}
+
+pub fn set_auto_allocate_handler(
+    ctx: Context<SetAutoAllocate>,
+    enabled: bool,
+    max_turnover_bps: u16,
+) -> Result<()> {
+    require!(
+        max_turnover_bps as u64 <= BPS_DENOMINATOR,
+        VaultError::InvalidAllocations
+    );
+
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    vault.auto_allocate = enabled as u8;
+    vault.max_turnover_bps = max_turnover_bps;
+
+    emit!(AutoAllocateUpdatedEvent {
+        enabled,
+        max_turnover_bps,
+    });
+
+    msg!(
+        "Auto-allocate: {} (max turnover {} bps)",
+        enabled,
+        max_turnover_bps
+    );
+    Ok(())
+}
+
+/// Re-derive the targets of the active allocations from the yield feed.
+///
+/// Each allocation first gets its protocol's minimum target, then the rest of the
+/// vault goes to the best risk-adjusted APY (reported APY less
+/// `RISK_TIER_PENALTY_BPS` per risk tier) up to the protocol's cap, then the next
+/// best, and so on. Protocols that are not `Active` get nothing, and anything no
+/// protocol can take stays idle. If that would move more than `max_turnover_bps`
+/// of the vault, the move is scaled back along the line from the current targets.
+pub(crate) fn optimise_targets(
+    vault: &mut Vault,
+    registry: &ProtocolRegistry,
+    feed: &YieldFeed,
+    nav: u64,
+    now: i64,
+) -> Result<()> {
+    let n = vault.num_allocations as usize;
+    let mut scores = [0u32; MAX_ALLOCATIONS];
+    let mut optimal = [0u16; MAX_ALLOCATIONS];
+    // Caps are per protocol, shared by every allocation to it
+    let mut room = [0u16; 256];
+    let mut remaining = BPS_DENOMINATOR as u16;
+
+    for (i, alloc) in vault.active_allocations().iter().enumerate() {
+        let entry = registry
+            .find(alloc.protocol_id)
+            .ok_or(VaultError::ProtocolNotRegistered)?;
+        let report = feed.fresh_report(alloc.protocol_id, now)?;
+        scores[i] = report
+            .apy_bps
+            .saturating_sub(entry.risk_tier as u32 * RISK_TIER_PENALTY_BPS);
+
+        if entry.status != ProtocolStatus::Active {
+            continue;
+        }
+        let limits = &entry.limits;
+        let cap_bps = if nav == 0 {
+            limits.max_exposure_bps
+        } else {
+            (limits.exposure_cap(nav) as u128 * BPS_DENOMINATOR as u128 / nav as u128) as u16
+        };
+        room[alloc.protocol_id as usize] = cap_bps.min(limits.max_target_bps);
+        optimal[i] = limits.min_target_bps;
+    }
+
+    // Minimums first, then fill by score
+    for (i, alloc) in vault.active_allocations().iter().enumerate() {
+        let protocol_room = &mut room[alloc.protocol_id as usize];
+        optimal[i] = optimal[i].min(*protocol_room).min(remaining);
+        *protocol_room -= optimal[i];
+        remaining -= optimal[i];
+    }
+    let mut order: [u8; MAX_ALLOCATIONS] = std::array::from_fn(|i| i as u8);
+    let order = &mut order[..n];
+    order.sort_unstable_by_key(|&i| (std::cmp::Reverse(scores[i as usize]), i));
+    for &i in order.iter() {
+        let i = i as usize;
+        let protocol_room = &mut room[vault.allocations[i].protocol_id as usize];
+        let give = (*protocol_room).min(remaining);
+        optimal[i] += give;
+        *protocol_room -= give;
+        remaining -= give;
+    }
+
+    let allocations = vault.active_allocations();
+    let turnover: u64 = allocations
+        .iter()
+        .zip(&optimal)
+        .map(|(a, &o)| (a.target_bps as i64 - o as i64).unsigned_abs())
+        .sum::<u64>()
+        / 2;
+    let max_turnover = vault.max_turnover_bps as u64;
+    let targets = if max_turnover > 0 && turnover > max_turnover {
+        apportion(BPS_DENOMINATOR, n, BPS_DENOMINATOR as u128 * turnover as u128, |i| {
+            allocations[i].target_bps as u128 * (turnover - max_turnover) as u128
+                + optimal[i] as u128 * max_turnover as u128
+        })?
+    } else {
+        std::array::from_fn(|i| optimal[i] as u64)
+    };
+
+    for (alloc, &target) in vault.active_allocations_mut().iter_mut().zip(&targets) {
+        alloc.target_bps = target as u16;
+        alloc.start_bps = target as u16;
+    }
+    // Optimised targets apply at once and replace any manual ramp
+    vault.ramp_start_ts = 0;
+    vault.ramp_end_ts = 0;
+    Ok(())
+}
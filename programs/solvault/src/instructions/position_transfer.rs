@@ -0,0 +1,273 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+use crate::errors::VaultError;
+use crate::instructions::asset::token_transfer;
+use crate::instructions::withdraw::{
+    calculate_withdrawal_amount, execute_withdrawal, quote_withdrawal,
+};
+use crate::state::*;
+
+// ── Owner sets (or clears) the key allowed to withdraw on their behalf ──
+
+#[derive(Accounts)]
+#[instruction(index: u16)]
+pub struct SetDelegate<'info> {
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user.key().as_ref(), &index.to_le_bytes()],
+        bump = position.bump,
+        constraint = position.owner == user.key() @ VaultError::Unauthorized,
+    )]
+    pub position: Account<'info, UserPosition>,
+}
+
+pub fn set_delegate_handler(
+    ctx: Context<SetDelegate>,
+    index: u16,
+    delegate: Pubkey,
+) -> Result<()> {
+    let position = &mut ctx.accounts.position;
+    position.delegate = delegate;
+
+    emit!(DelegateUpdatedEvent {
+        owner: position.owner,
+        index,
+        delegate,
+    });
+
+    if delegate == Pubkey::default() {
+        msg!("Position delegate cleared");
+    } else {
+        msg!("Position delegate set to {}", delegate);
+    }
+    Ok(())
+}
+
+// ── Delegate withdraws; proceeds always go to the position owner ──
+
+#[derive(Accounts)]
+#[instruction(index: u16)]
+pub struct DelegateWithdraw<'info> {
+    pub delegate: Signer<'info>,
+
+    /// CHECK: only receives lamports; bound to the position owner below
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, owner.key().as_ref(), &index.to_le_bytes()],
+        bump = position.bump,
+        constraint = position.owner == owner.key() @ VaultError::Unauthorized,
+        constraint = position.delegate == delegate.key() @ VaultError::Unauthorized,
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    /// Base asset accounts; required for token vaults, omitted for native SOL
+    pub base_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub vault_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Must belong to the position owner
+    #[account(
+        mut,
+        constraint = owner_token_account.owner == owner.key() @ VaultError::InvalidTokenAccount,
+    )]
+    pub owner_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+pub fn delegate_withdraw_handler(
+    ctx: Context<DelegateWithdraw>,
+    _index: u16,
+    shares: u64,
+    min_lamports_out: u64,
+) -> Result<()> {
+    let vault = ctx.accounts.vault.load()?;
+
+    require!(vault.async_mode == 0, VaultError::AsyncModeEnabled);
+
+    let quote = quote_withdrawal(&vault, &ctx.accounts.position, shares)?;
+    require!(
+        quote.net_amount >= min_lamports_out,
+        VaultError::SlippageExceeded
+    );
+
+    let recipient = ctx.accounts.owner.to_account_info();
+    let token = token_transfer(
+        &vault,
+        &ctx.accounts.base_mint,
+        &ctx.accounts.vault_token_account,
+        &ctx.accounts.owner_token_account,
+        &ctx.accounts.token_program,
+    )?;
+    drop(vault);
+    execute_withdrawal(
+        &ctx.accounts.vault,
+        &mut ctx.accounts.position,
+        &recipient,
+        shares,
+        token,
+        quote,
+    )
+}
+
+// ── Move shares (and their cost basis) to another position, own or another wallet's ──
+
+#[derive(Accounts)]
+#[instruction(from_index: u16, to: Pubkey, to_index: u16)]
+pub struct TransferPositionShares<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user.key().as_ref(), &from_index.to_le_bytes()],
+        bump = from_position.bump,
+        constraint = from_position.owner == user.key() @ VaultError::Unauthorized,
+    )]
+    pub from_position: Account<'info, UserPosition>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, to.as_ref(), &to_index.to_le_bytes()],
+        bump,
+        constraint = to != user.key() || to_index != from_index
+            @ VaultError::InvalidTransferTarget,
+    )]
+    pub to_position: Account<'info, UserPosition>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn transfer_position_shares_handler(
+    ctx: Context<TransferPositionShares>,
+    from_index: u16,
+    to: Pubkey,
+    to_index: u16,
+    shares: u64,
+) -> Result<()> {
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    let from_position = &ctx.accounts.from_position;
+
+    require!(to != Pubkey::default(), VaultError::InvalidTransferTarget);
+    require!(shares > 0, VaultError::ZeroAmount);
+    require!(
+        from_position.shares >= shares,
+        VaultError::InsufficientShares
+    );
+
+    // Neither side of the transfer may be left holding dust
+    let transferred_value =
+        calculate_withdrawal_amount(shares, vault.total_deposited, vault.total_shares)?;
+    require!(
+        transferred_value >= MIN_DEPOSIT_LAMPORTS,
+        VaultError::DustWithdrawal
+    );
+    let remaining_shares = from_position
+        .shares
+        .checked_sub(shares)
+        .ok_or(VaultError::MathOverflow)?;
+    if remaining_shares > 0 {
+        let remaining_value = calculate_withdrawal_amount(
+            remaining_shares,
+            vault.total_deposited,
+            vault.total_shares,
+        )?;
+        require!(
+            remaining_value >= MIN_DEPOSIT_LAMPORTS,
+            VaultError::DustWithdrawal
+        );
+    }
+
+    // Cost basis moves proportionally so performance fees stay on yield only
+    let cost_basis: u64 = (from_position.deposited_amount as u128)
+        .checked_mul(shares as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_div(from_position.shares as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .try_into()
+        .map_err(|_| VaultError::MathOverflow)?;
+    let source_deposit_ts = from_position.last_deposit_ts;
+    let source_deposit_slot = from_position.last_deposit_slot;
+    // Locked shares move first, so fresh shares can't dodge the delay by changing position
+    let slot = Clock::get()?.slot;
+    let source_locked = vault.locked_shares(from_position, slot);
+    let moved_locked = source_locked.min(shares);
+
+    let from_position = &mut ctx.accounts.from_position;
+    from_position.shares = remaining_shares;
+    from_position.locked_shares = source_locked - moved_locked;
+    from_position.deposited_amount = from_position
+        .deposited_amount
+        .checked_sub(cost_basis)
+        .ok_or(VaultError::MathOverflow)?;
+
+    let to_position = &mut ctx.accounts.to_position;
+    let is_new_depositor = to_position.shares == 0;
+    to_position.owner = to;
+    to_position.index = to_index;
+    to_position.bump = ctx.bumps.to_position;
+    to_position.version = POSITION_VERSION;
+    to_position.shares = to_position
+        .shares
+        .checked_add(shares)
+        .ok_or(VaultError::MathOverflow)?;
+    to_position.deposited_amount = to_position
+        .deposited_amount
+        .checked_add(cost_basis)
+        .ok_or(VaultError::MathOverflow)?;
+    to_position.last_deposit_ts = to_position.last_deposit_ts.max(source_deposit_ts);
+    if moved_locked > 0 {
+        to_position.locked_shares = vault
+            .locked_shares(to_position, slot)
+            .checked_add(moved_locked)
+            .ok_or(VaultError::MathOverflow)?;
+        to_position.last_deposit_slot = to_position.last_deposit_slot.max(source_deposit_slot);
+    }
+
+    if is_new_depositor {
+        vault.depositor_count = vault
+            .depositor_count
+            .checked_add(1)
+            .ok_or(VaultError::MathOverflow)?;
+    }
+    if remaining_shares == 0 {
+        vault.depositor_count = vault
+            .depositor_count
+            .checked_sub(1)
+            .ok_or(VaultError::MathOverflow)?;
+    }
+
+    emit!(PositionTransferEvent {
+        from: ctx.accounts.user.key(),
+        from_index,
+        to,
+        to_index,
+        shares,
+        cost_basis,
+    });
+
+    msg!("Transferred {} shares to {} (position {})", shares, to, to_index);
+    Ok(())
+}
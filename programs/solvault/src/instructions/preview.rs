@@ -0,0 +1,241 @@
+use anchor_lang::prelude::*;
+use crate::instructions::deposit::quote_deposit;
+use crate::instructions::rebalance::{load_rebalance_inputs, plan_rebalance};
+use crate::errors::VaultError;
+use crate::instructions::withdraw::{
+    calculate_withdrawal_amount, check_withdrawal_limits, quote_withdrawal,
+};
+use crate::state::*;
+
+// Read-only instructions: results are returned via return data so clients can
+// simulate them and other programs can call them through CPI.
+
+#[derive(Accounts)]
+pub struct PreviewVault<'info> {
+    #[account(
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+}
+
+#[derive(Accounts)]
+pub struct PreviewPosition<'info> {
+    #[account(
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        seeds = [POSITION_SEED, position.owner.as_ref(), &position.index.to_le_bytes()],
+        bump = position.bump,
+    )]
+    pub position: Account<'info, UserPosition>,
+}
+
+#[derive(Accounts)]
+pub struct PreviewRebalance<'info> {
+    #[account(
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        seeds = [REGISTRY_SEED],
+        bump = registry.bump,
+    )]
+    pub registry: Account<'info, ProtocolRegistry>,
+
+    /// CHECK: pending `AllocationSchedule`, if any; only read when owned by this program
+    #[account(
+        seeds = [SCHEDULE_SEED],
+        bump,
+    )]
+    pub schedule: UncheckedAccount<'info>,
+
+    /// CHECK: `YieldFeed`, if one has been created; only read when owned by this program
+    #[account(
+        seeds = [FEED_SEED],
+        bump,
+    )]
+    pub feed: UncheckedAccount<'info>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct WithdrawPreview {
+    /// SOL value of the burned shares before fees
+    pub gross_amount: u64,
+    /// Performance fee charged on the yield portion
+    pub fee: u64,
+    /// SOL the user would receive
+    pub net_amount: u64,
+    /// Shares left in the position afterwards
+    pub remaining_shares: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SharePrice {
+    pub total_deposited: u64,
+    pub total_shares: u64,
+    /// Lamports redeemable for SHARES_PER_SOL shares
+    pub lamports_per_sol_shares: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct PositionValue {
+    pub owner: Pubkey,
+    pub index: u16,
+    pub shares: u64,
+    /// SOL value of all shares before fees
+    pub gross_value: u64,
+    /// Cost basis of the position
+    pub cost_basis: u64,
+    /// Performance fee a full exit would pay
+    pub fee: u64,
+    /// SOL a full exit would return
+    pub net_value: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AllocationMove {
+    pub protocol_id: u8,
+    /// Target the rebalance would set, in bps
+    pub target_bps: u16,
+    /// Lamports allocated now
+    pub current_amount: u64,
+    /// Lamports allocated after the rebalance
+    pub target_amount: u64,
+    /// `target_amount - current_amount`
+    pub delta: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct RebalancePreview {
+    pub total_deposited: u64,
+    /// Whether a due allocation schedule would be applied
+    pub schedule_applied: bool,
+    /// Whether the targets were derived by the optimiser
+    pub auto_allocated: bool,
+    /// Allocations after the targets are updated, from `first` on
+    pub allocations: Vec<AllocationMove>,
+    /// Fees the moves would pay (0 while rebalance only updates bookkeeping)
+    pub fees: u64,
+    /// Tip paid to the caller (0 while rebalance only updates bookkeeping)
+    pub keeper_tip: u64,
+}
+
+/// Shares a deposit of `amount` would mint; fails exactly where `deposit` would
+pub fn preview_deposit_handler(ctx: Context<PreviewVault>, amount: u64) -> Result<u64> {
+    let vault = ctx.accounts.vault.load()?;
+    require!(vault.async_mode == 0, VaultError::AsyncModeEnabled);
+    quote_deposit(&vault, amount)
+}
+
+/// Outcome of burning `shares` from the position; fails exactly where `withdraw` would
+pub fn preview_withdraw_handler(
+    ctx: Context<PreviewPosition>,
+    shares: u64,
+) -> Result<WithdrawPreview> {
+    let vault = ctx.accounts.vault.load()?;
+    require!(vault.async_mode == 0, VaultError::AsyncModeEnabled);
+    let quote = quote_withdrawal(&vault, &ctx.accounts.position, shares)?;
+    check_withdrawal_limits(&vault, &ctx.accounts.position, shares, &quote)?;
+    Ok(WithdrawPreview {
+        gross_amount: quote.gross_amount,
+        fee: quote.fee,
+        net_amount: quote.net_amount,
+        remaining_shares: quote.remaining_shares,
+    })
+}
+
+pub fn get_share_price_handler(ctx: Context<PreviewVault>) -> Result<SharePrice> {
+    let vault = ctx.accounts.vault.load()?;
+
+    // The virtual offset prices an empty vault at the initial ratio
+    let lamports_per_sol_shares =
+        calculate_withdrawal_amount(SHARES_PER_SOL, vault.total_deposited, vault.total_shares)?;
+
+    Ok(SharePrice {
+        total_deposited: vault.total_deposited,
+        total_shares: vault.total_shares,
+        lamports_per_sol_shares,
+    })
+}
+
+pub fn get_position_value_handler(ctx: Context<PreviewPosition>) -> Result<PositionValue> {
+    let position = &ctx.accounts.position;
+
+    let mut value = PositionValue {
+        owner: position.owner,
+        index: position.index,
+        shares: position.shares,
+        gross_value: 0,
+        cost_basis: position.deposited_amount,
+        fee: 0,
+        net_value: 0,
+    };
+
+    if position.shares > 0 {
+        // A full exit never trips the dust check, so this mirrors withdraw(all shares)
+        let quote = quote_withdrawal(&*ctx.accounts.vault.load()?, position, position.shares)?;
+        value.gross_value = quote.gross_amount;
+        value.fee = quote.fee;
+        value.net_value = quote.net_amount;
+    }
+
+    Ok(value)
+}
+
+/// What `rebalance` would do right now, starting at allocation `first`; fails
+/// exactly where `rebalance` would
+pub fn preview_rebalance_handler(
+    ctx: Context<PreviewRebalance>,
+    first: u8,
+) -> Result<RebalancePreview> {
+    // Plan on a heap copy of the vault; the allocation table is too large for the stack
+    let size = std::mem::size_of::<Vault>();
+    let mut buf = vec![0u64; size.div_ceil(8)];
+    let bytes = &mut bytemuck::cast_slice_mut::<u64, u8>(&mut buf)[..size];
+    bytes.copy_from_slice(&ctx.accounts.vault.as_ref().try_borrow_data()?[8..Vault::SPACE]);
+    let vault: &mut Vault = bytemuck::from_bytes_mut(bytes);
+
+    let (schedule, feed) = load_rebalance_inputs(
+        &ctx.accounts.schedule.to_account_info(),
+        &ctx.accounts.feed.to_account_info(),
+    )?;
+    let now = Clock::get()?.unix_timestamp;
+    let plan = plan_rebalance(
+        vault,
+        &ctx.accounts.registry,
+        schedule.as_ref(),
+        feed.as_ref(),
+        now,
+    )?;
+
+    // Return data is capped at 1 KiB, so larger tables are read in pages
+    let allocations = vault
+        .active_allocations()
+        .iter()
+        .zip(&plan.amounts)
+        .skip(first as usize)
+        .take(MAX_PREVIEW_ALLOCATIONS)
+        .map(|(alloc, &target_amount)| AllocationMove {
+            protocol_id: alloc.protocol_id,
+            target_bps: alloc.target_bps,
+            current_amount: alloc.current_amount,
+            target_amount,
+            delta: target_amount as i64 - alloc.current_amount as i64,
+        })
+        .collect();
+
+    Ok(RebalancePreview {
+        total_deposited: vault.total_deposited,
+        schedule_applied: plan.schedule_applied,
+        auto_allocated: plan.auto_allocated,
+        allocations,
+        fees: 0,
+        keeper_tip: 0,
+    })
+}
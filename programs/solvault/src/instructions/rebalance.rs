@@ -1,64 +1,261 @@
 use anchor_lang::prelude::*;
 use crate::errors::VaultError;
+use crate::instructions::optimizer::optimise_targets;
+use crate::instructions::registry::validate_targets;
 use crate::state::*;
 
 #[derive(Accounts)]
 pub struct Rebalance<'info> {
+    /// Receives the rent of an applied allocation schedule
+    #[account(mut)]
     pub authority: Signer<'info>,
 
     #[account(
         mut,
         seeds = [VAULT_SEED],
-        bump = vault.bump,
+        bump = vault.load()?.bump,
         has_one = authority @ VaultError::Unauthorized,
     )]
-    pub vault: Account<'info, Vault>,
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        seeds = [REGISTRY_SEED],
+        bump = registry.bump,
+    )]
+    pub registry: Account<'info, ProtocolRegistry>,
+
+    /// CHECK: pending `AllocationSchedule`, if any; only read when owned by this program
+    #[account(
+        mut,
+        seeds = [SCHEDULE_SEED],
+        bump,
+    )]
+    pub schedule: UncheckedAccount<'info>,
+
+    /// CHECK: `YieldFeed`, if one has been created; only read when owned by this program
+    #[account(
+        seeds = [FEED_SEED],
+        bump,
+    )]
+    pub feed: UncheckedAccount<'info>,
 }
 
-pub fn handler(ctx: Context<Rebalance>) -> Result<()> {
-    let vault = &mut ctx.accounts.vault;
+/// What a rebalance does to the vault, as computed by `plan_rebalance`
+pub(crate) struct RebalancePlan {
+    /// Whether a due allocation schedule was applied
+    pub schedule_applied: bool,
+    /// Whether the targets were derived by the optimiser
+    pub auto_allocated: bool,
+    /// New `current_amount` of each active allocation
+    pub amounts: [u64; MAX_ALLOCATIONS],
+}
+
+/// Read the `AllocationSchedule` and `YieldFeed` accounts, if they exist
+pub(crate) fn load_rebalance_inputs(
+    schedule_info: &AccountInfo,
+    feed_info: &AccountInfo,
+) -> Result<(Option<AllocationSchedule>, Option<YieldFeed>)> {
+    let schedule = if schedule_info.owner == &crate::ID {
+        Some(AllocationSchedule::try_deserialize(
+            &mut &schedule_info.try_borrow_data()?[..],
+        )?)
+    } else {
+        None
+    };
+    let feed = if feed_info.owner == &crate::ID {
+        Some(YieldFeed::try_deserialize(&mut &feed_info.try_borrow_data()?[..])?)
+    } else {
+        None
+    };
+    Ok((schedule, feed))
+}
+
+/// Apply a due schedule and any auto-allocation to `vault`'s targets, and work out
+/// where each allocation's funds should end up. Amounts are returned rather than
+/// written, so `preview_rebalance` can run this on a copy of the vault.
+pub(crate) fn plan_rebalance(
+    vault: &mut Vault,
+    registry: &ProtocolRegistry,
+    schedule: Option<&AllocationSchedule>,
+    feed: Option<&YieldFeed>,
+    now: i64,
+) -> Result<RebalancePlan> {
+    // v3 vaults still hold percentage targets until `migrate_vault` runs
+    require!(
+        vault.version == VAULT_VERSION,
+        VaultError::MigrationRequired
+    );
+    require!(!vault.breaker_paused(), VaultError::CircuitBreakerActive);
+    require!(vault.rebalancing == 0, VaultError::RebalanceInProgress);
     let total = vault.total_deposited;
 
+    // A scheduled change that has come due replaces the targets first. Statuses and
+    // risk limits may have moved since it was scheduled, so they are checked again.
+    let schedule_applied = match schedule {
+        Some(schedule) if schedule.activation_ts <= now => {
+            validate_targets(registry, &schedule.targets, total, None)?;
+            vault.set_allocations(
+                &schedule.targets,
+                now,
+                now + schedule.ramp_seconds,
+                now,
+            )?;
+            true
+        }
+        _ => false,
+    };
+
+    let auto_allocated = vault.auto_allocate != 0;
+    let mut plan = RebalancePlan {
+        schedule_applied,
+        auto_allocated,
+        amounts: [0u64; MAX_ALLOCATIONS],
+    };
     if total == 0 {
-        msg!("Nothing to rebalance, vault is empty");
-        return Ok(());
+        return Ok(plan);
     }
 
-    // Compute target amounts based on allocation percentages
-    // In production, this would involve CPI calls to Jito/Marinade/Sanctum
-    // to actually move funds. For now, we update the bookkeeping.
-    let mut allocated: u64 = 0;
-    let num_allocs = vault.allocations.len();
-
-    for (i, alloc) in vault.allocations.iter_mut().enumerate() {
-        if i == num_allocs - 1 {
-            // Last allocation gets the remainder to avoid rounding issues
-            alloc.current_amount = total
-                .checked_sub(allocated)
-                .ok_or(VaultError::MathOverflow)?;
-        } else {
-            let target_amount: u64 = (total as u128)
-                .checked_mul(alloc.target_pct as u128)
-                .ok_or(VaultError::MathOverflow)?
-                .checked_div(100)
-                .ok_or(VaultError::MathOverflow)?
-                .try_into()
-                .map_err(|_| VaultError::MathOverflow)?;
-            alloc.current_amount = target_amount;
-            allocated = allocated
-                .checked_add(target_amount)
-                .ok_or(VaultError::MathOverflow)?;
+    // Once a yield feed exists, every allocated protocol needs a fresh report
+    match feed {
+        Some(feed) => {
+            for alloc in vault.active_allocations() {
+                feed.fresh_report(alloc.protocol_id, now)?;
+            }
+            if auto_allocated {
+                optimise_targets(vault, registry, feed, total, now)?;
+            }
         }
+        None => require!(!auto_allocated, VaultError::StaleYieldData),
+    }
+
+    // Compute target amounts from the effective (possibly mid-ramp) targets.
+    // Anything above a protocol's cap stays idle in the vault rather than moving elsewhere.
+    let targets = vault.effective_targets(total, now)?;
+    for (i, alloc) in vault.active_allocations().iter().enumerate() {
+        let cap = registry
+            .find(alloc.protocol_id)
+            .ok_or(VaultError::ProtocolNotRegistered)?
+            .limits
+            .exposure_cap(total);
+        plan.amounts[i] = targets[i].min(cap);
+    }
+    Ok(plan)
+}
+
+/// Close an applied schedule: refund its rent to `authority` and hand it back to
+/// the system program
+pub(crate) fn close_applied_schedule(
+    authority_info: &AccountInfo,
+    schedule_info: &AccountInfo,
+    schedule: &AllocationSchedule,
+    num_allocations: u8,
+    now: i64,
+) -> Result<()> {
+    **authority_info.try_borrow_mut_lamports()? = authority_info
+        .lamports()
+        .checked_add(schedule_info.lamports())
+        .ok_or(VaultError::MathOverflow)?;
+    **schedule_info.try_borrow_mut_lamports()? = 0;
+    schedule_info.assign(&anchor_lang::system_program::ID);
+    schedule_info.resize(0)?;
+
+    emit!(AllocationScheduleActivatedEvent {
+        activation_ts: schedule.activation_ts,
+        activated_ts: now,
+        num_allocations,
+    });
+    msg!("Applied allocation change scheduled for {}", schedule.activation_ts);
+    Ok(())
+}
+
+/// Flag an allocation whose exposure has drifted past its protocol's cap at `nav`
+pub(crate) fn check_exposure(
+    registry: &ProtocolRegistry,
+    alloc: &Allocation,
+    nav: u64,
+) -> Result<()> {
+    let cap = registry
+        .find(alloc.protocol_id)
+        .ok_or(VaultError::ProtocolNotRegistered)?
+        .limits
+        .exposure_cap(nav);
+
+    // Market moves can push actual exposure past the cap between rebalances
+    if alloc.current_amount > cap {
+        emit!(RiskLimitBreachedEvent {
+            protocol_id: alloc.protocol_id,
+            exposure: alloc.current_amount,
+            cap,
+            nav,
+        });
+        msg!(
+            "Protocol {} over its cap ({} > {}), trimming",
+            alloc.protocol_id,
+            alloc.current_amount,
+            cap
+        );
     }
+    Ok(())
+}
 
-    vault.last_rebalance_ts = Clock::get()?.unix_timestamp;
+/// Wrap up a rebalance whose funds have all moved
+pub(crate) fn complete_rebalance(
+    vault: &mut Vault,
+    auto_allocated: bool,
+    total: u64,
+    now: i64,
+) {
+    // Auto mode keeps every slot so a protocol can win weight back as yields move
+    if !auto_allocated {
+        vault.prune_allocations(now);
+    }
+    vault.last_rebalance_ts = now;
+    let num_allocs = vault.num_allocations;
 
     emit!(RebalanceEvent {
         timestamp: vault.last_rebalance_ts,
         total_deposited: total,
-        num_allocations: num_allocs as u8,
+        num_allocations: num_allocs,
+        auto_allocated,
+        weights: vault.active_allocations().iter().map(|a| a.target_bps).collect(),
     });
 
     msg!("Rebalanced {} lamports across {} protocols", total, num_allocs);
+}
+
+pub fn handler(ctx: Context<Rebalance>) -> Result<()> {
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    let total = vault.total_deposited;
+    let now = Clock::get()?.unix_timestamp;
+    let registry = &ctx.accounts.registry;
+
+    let schedule_info = ctx.accounts.schedule.to_account_info();
+    let (schedule, feed) =
+        load_rebalance_inputs(&schedule_info, &ctx.accounts.feed.to_account_info())?;
+    let plan = plan_rebalance(&mut vault, registry, schedule.as_ref(), feed.as_ref(), now)?;
+
+    if let Some(schedule) = schedule.filter(|_| plan.schedule_applied) {
+        close_applied_schedule(
+            &ctx.accounts.authority.to_account_info(),
+            &schedule_info,
+            &schedule,
+            vault.num_allocations,
+            now,
+        )?;
+    }
+
+    if total == 0 {
+        msg!("Nothing to rebalance, vault is empty");
+        return Ok(());
+    }
+
+    // In production, this would involve CPI calls to Jito/Marinade/Sanctum
+    // to actually move funds. For now, we update the bookkeeping.
+    for (alloc, &amount) in vault.active_allocations_mut().iter_mut().zip(&plan.amounts) {
+        check_exposure(registry, alloc, total)?;
+        alloc.current_amount = amount;
+    }
+
+    complete_rebalance(&mut vault, plan.auto_allocated, total, now);
     Ok(())
 }
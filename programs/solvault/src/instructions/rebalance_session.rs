@@ -0,0 +1,295 @@
+use anchor_lang::prelude::*;
+use crate::errors::VaultError;
+use crate::instructions::rebalance::{
+    check_exposure, close_applied_schedule, complete_rebalance, load_rebalance_inputs,
+    plan_rebalance,
+};
+use crate::state::*;
+
+// ── Authority opens a rebalance that moves one allocation per transaction ──
+
+#[derive(Accounts)]
+pub struct BeginRebalance<'info> {
+    /// Pays for the session and receives the rent of an applied allocation schedule
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        seeds = [REGISTRY_SEED],
+        bump = registry.bump,
+    )]
+    pub registry: Account<'info, ProtocolRegistry>,
+
+    /// CHECK: pending `AllocationSchedule`, if any; only read when owned by this program
+    #[account(
+        mut,
+        seeds = [SCHEDULE_SEED],
+        bump,
+    )]
+    pub schedule: UncheckedAccount<'info>,
+
+    /// CHECK: `YieldFeed`, if one has been created; only read when owned by this program
+    #[account(
+        seeds = [FEED_SEED],
+        bump,
+    )]
+    pub feed: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + RebalanceSession::INIT_SPACE,
+        seeds = [REBALANCE_SESSION_SEED],
+        bump,
+    )]
+    pub session: Account<'info, RebalanceSession>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Plan the rebalance exactly as `rebalance` would and record each allocation's
+/// move. Until `finish_rebalance`, targets are frozen and withdrawals can only
+/// take funds deposited after this point.
+pub fn begin_rebalance_handler(ctx: Context<BeginRebalance>) -> Result<()> {
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    let total = vault.total_deposited;
+    let now = Clock::get()?.unix_timestamp;
+
+    let schedule_info = ctx.accounts.schedule.to_account_info();
+    let (schedule, feed) =
+        load_rebalance_inputs(&schedule_info, &ctx.accounts.feed.to_account_info())?;
+    let plan = plan_rebalance(
+        &mut vault,
+        &ctx.accounts.registry,
+        schedule.as_ref(),
+        feed.as_ref(),
+        now,
+    )?;
+
+    if let Some(schedule) = schedule.filter(|_| plan.schedule_applied) {
+        close_applied_schedule(
+            &ctx.accounts.authority.to_account_info(),
+            &schedule_info,
+            &schedule,
+            vault.num_allocations,
+            now,
+        )?;
+    }
+
+    let session = &mut ctx.accounts.session;
+    session.total_deposited = total;
+    session.deltas = vault
+        .active_allocations()
+        .iter()
+        .zip(&plan.amounts)
+        .map(|(alloc, &amount)| amount as i64 - alloc.current_amount as i64)
+        .collect();
+    session.completed = 0;
+    session.auto_allocated = plan.auto_allocated;
+    session.started_ts = now;
+    session.bump = ctx.bumps.session;
+
+    vault.rebalancing = 1;
+    vault.rebalance_nav = total;
+    vault.rebalance_deadline_slot = Clock::get()?
+        .slot
+        .checked_add(MAX_REBALANCE_SLOTS)
+        .ok_or(VaultError::MathOverflow)?;
+
+    emit!(RebalanceBegunEvent {
+        total_deposited: total,
+        num_allocations: vault.num_allocations,
+        auto_allocated: plan.auto_allocated,
+    });
+
+    msg!(
+        "Began rebalancing {} lamports across {} protocols",
+        total,
+        vault.num_allocations
+    );
+    Ok(())
+}
+
+// ── Authority moves one allocation of the open session ──
+
+#[derive(Accounts)]
+pub struct RebalanceStep<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        seeds = [REGISTRY_SEED],
+        bump = registry.bump,
+    )]
+    pub registry: Account<'info, ProtocolRegistry>,
+
+    #[account(
+        mut,
+        seeds = [REBALANCE_SESSION_SEED],
+        bump = session.bump,
+    )]
+    pub session: Account<'info, RebalanceSession>,
+}
+
+/// Moves are applied as deltas, so LST and stake deposits booked to an allocation
+/// mid-session are kept.
+pub fn rebalance_step_handler(ctx: Context<RebalanceStep>, allocation_index: u8) -> Result<()> {
+    let session = &mut ctx.accounts.session;
+    let i = allocation_index as usize;
+    require!(
+        i < session.deltas.len() && session.completed & (1 << i) == 0,
+        VaultError::InvalidRebalanceStep
+    );
+
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    require!(vault.rebalance_live()?, VaultError::RebalanceLapsed);
+    let alloc = &mut vault.allocations[i];
+    check_exposure(&ctx.accounts.registry, alloc, session.total_deposited)?;
+
+    // In production, this would CPI into the allocation's protocol to move the
+    // funds. For now, we update the bookkeeping.
+    let amount_before = alloc.current_amount;
+    let delta = session.deltas[i];
+    alloc.current_amount = if delta >= 0 {
+        amount_before
+            .checked_add(delta as u64)
+            .ok_or(VaultError::MathOverflow)?
+    } else {
+        amount_before.saturating_sub(delta.unsigned_abs())
+    };
+    session.completed |= 1 << i;
+
+    emit!(RebalanceStepEvent {
+        allocation_index,
+        protocol_id: alloc.protocol_id,
+        amount_before,
+        amount_after: alloc.current_amount,
+    });
+
+    msg!(
+        "Moved protocol {} from {} to {} lamports",
+        alloc.protocol_id,
+        amount_before,
+        alloc.current_amount
+    );
+    Ok(())
+}
+
+// ── Authority closes the session once every allocation has moved ──
+
+#[derive(Accounts)]
+pub struct FinishRebalance<'info> {
+    /// Receives the session rent
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [REBALANCE_SESSION_SEED],
+        bump = session.bump,
+        close = authority,
+    )]
+    pub session: Account<'info, RebalanceSession>,
+}
+
+pub fn finish_rebalance_handler(ctx: Context<FinishRebalance>) -> Result<()> {
+    let session = &ctx.accounts.session;
+    require!(session.is_complete(), VaultError::RebalanceIncomplete);
+
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    require!(vault.rebalance_live()?, VaultError::RebalanceLapsed);
+    let allocated = vault
+        .active_allocations()
+        .iter()
+        .try_fold(0u64, |sum, a| sum.checked_add(a.current_amount))
+        .ok_or(VaultError::MathOverflow)?;
+    require!(
+        allocated <= vault.total_deposited,
+        VaultError::RebalanceTotalsMismatch
+    );
+
+    vault.rebalancing = 0;
+    vault.rebalance_nav = 0;
+    vault.rebalance_deadline_slot = 0;
+    let now = Clock::get()?.unix_timestamp;
+    complete_rebalance(&mut vault, session.auto_allocated, session.total_deposited, now);
+    Ok(())
+}
+
+// ── Abandon the open session: the authority at any time, anyone once it lapses ──
+
+#[derive(Accounts)]
+pub struct CancelRebalance<'info> {
+    pub caller: Signer<'info>,
+
+    /// CHECK: receives the session rent; must be the vault authority
+    #[account(mut, address = vault.load()?.authority @ VaultError::Unauthorized)]
+    pub authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [REBALANCE_SESSION_SEED],
+        bump = session.bump,
+        close = authority,
+    )]
+    pub session: Account<'info, RebalanceSession>,
+}
+
+/// Close the session without finishing it. Moves already made stay; the next
+/// rebalance plans from wherever the allocations are.
+pub fn cancel_rebalance_handler(ctx: Context<CancelRebalance>) -> Result<()> {
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    let lapsed = !vault.rebalance_live()?;
+    require!(
+        lapsed || ctx.accounts.caller.key() == vault.authority,
+        VaultError::Unauthorized
+    );
+
+    vault.rebalancing = 0;
+    vault.rebalance_nav = 0;
+    vault.rebalance_deadline_slot = 0;
+
+    let session = &ctx.accounts.session;
+    emit!(RebalanceCancelledEvent {
+        steps_completed: session.completed.count_ones() as u8,
+        num_allocations: session.deltas.len() as u8,
+        lapsed,
+    });
+
+    msg!(
+        "Cancelled rebalance after {} of {} moves",
+        session.completed.count_ones(),
+        session.deltas.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,265 @@
+use anchor_lang::prelude::*;
+use crate::errors::VaultError;
+use crate::state::*;
+
+// ── Create the protocol registry; the creator manages it ──
+
+#[derive(Accounts)]
+pub struct InitializeRegistry<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ProtocolRegistry::INIT_SPACE,
+        seeds = [REGISTRY_SEED],
+        bump,
+    )]
+    pub registry: Account<'info, ProtocolRegistry>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn initialize_registry_handler(ctx: Context<InitializeRegistry>) -> Result<()> {
+    let registry = &mut ctx.accounts.registry;
+    registry.authority = ctx.accounts.authority.key();
+    registry.protocols = Vec::new();
+    registry.bump = ctx.bumps.registry;
+
+    msg!("Protocol registry initialized");
+    Ok(())
+}
+
+// ── Registry authority lists a new protocol ──
+
+#[derive(Accounts)]
+pub struct RegisterProtocol<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [REGISTRY_SEED],
+        bump = registry.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub registry: Account<'info, ProtocolRegistry>,
+}
+
+pub fn register_protocol_handler(
+    ctx: Context<RegisterProtocol>,
+    protocol_id: u8,
+    program_id: Pubkey,
+    adapter_kind: AdapterKind,
+    state_account: Pubkey,
+    risk_tier: u8,
+    params_len: u8,
+) -> Result<()> {
+    let registry = &mut ctx.accounts.registry;
+
+    require!(
+        registry.find(protocol_id).is_none(),
+        VaultError::DuplicateProtocol
+    );
+    require!(
+        registry.protocols.len() < MAX_PROTOCOLS,
+        VaultError::RegistryFull
+    );
+    require!(
+        params_len as usize <= ALLOCATION_PARAMS_LEN,
+        VaultError::InvalidAllocationParams
+    );
+
+    registry.protocols.push(ProtocolEntry {
+        protocol_id,
+        program_id,
+        adapter_kind,
+        state_account,
+        status: ProtocolStatus::Active,
+        risk_tier,
+        limits: RiskLimits::UNLIMITED,
+        params_len,
+    });
+
+    emit!(ProtocolUpdatedEvent {
+        protocol_id,
+        program_id,
+        adapter_kind,
+        status: ProtocolStatus::Active,
+        risk_tier,
+    });
+
+    msg!("Registered protocol {} ({})", protocol_id, program_id);
+    Ok(())
+}
+
+// ── Registry authority changes a protocol's status or risk tier ──
+
+#[derive(Accounts)]
+pub struct UpdateProtocol<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [REGISTRY_SEED],
+        bump = registry.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub registry: Account<'info, ProtocolRegistry>,
+}
+
+/// Existing allocations keep their targets when a protocol leaves `Active`;
+/// only new targets are refused.
+pub fn update_protocol_handler(
+    ctx: Context<UpdateProtocol>,
+    protocol_id: u8,
+    status: Option<ProtocolStatus>,
+    risk_tier: Option<u8>,
+) -> Result<()> {
+    let entry = ctx
+        .accounts
+        .registry
+        .protocols
+        .iter_mut()
+        .find(|p| p.protocol_id == protocol_id)
+        .ok_or(VaultError::ProtocolNotRegistered)?;
+
+    if let Some(status) = status {
+        entry.status = status;
+    }
+    if let Some(risk_tier) = risk_tier {
+        entry.risk_tier = risk_tier;
+    }
+
+    emit!(ProtocolUpdatedEvent {
+        protocol_id,
+        program_id: entry.program_id,
+        adapter_kind: entry.adapter_kind,
+        status: entry.status,
+        risk_tier: entry.risk_tier,
+    });
+
+    msg!("Updated protocol {}", protocol_id);
+    Ok(())
+}
+
+// ── Registry authority sets a protocol's exposure caps and target bounds ──
+
+#[derive(Accounts)]
+pub struct SetRiskLimits<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [REGISTRY_SEED],
+        bump = registry.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub registry: Account<'info, ProtocolRegistry>,
+}
+
+/// Tightened caps apply to existing allocations at the next rebalance.
+pub fn set_risk_limits_handler(
+    ctx: Context<SetRiskLimits>,
+    protocol_id: u8,
+    limits: RiskLimits,
+) -> Result<()> {
+    let max_bps = BPS_DENOMINATOR as u16;
+    require!(
+        limits.max_exposure_bps <= max_bps
+            && limits.max_target_bps <= max_bps
+            && limits.min_target_bps <= limits.max_target_bps,
+        VaultError::InvalidRiskLimits
+    );
+
+    let entry = ctx
+        .accounts
+        .registry
+        .protocols
+        .iter_mut()
+        .find(|p| p.protocol_id == protocol_id)
+        .ok_or(VaultError::ProtocolNotRegistered)?;
+    entry.limits = limits;
+
+    emit!(RiskLimitsUpdatedEvent {
+        protocol_id,
+        limits,
+    });
+
+    msg!("Updated risk limits for protocol {}", protocol_id);
+    Ok(())
+}
+
+/// Every target must name a registered, active protocol and respect its target
+/// bounds and exposure caps at the current NAV. Each explicit target account is
+/// passed in `target_accounts`, in allocation order, so its owner can be checked;
+/// `None` skips that check for targets already verified when they were scheduled.
+pub(crate) fn validate_targets(
+    registry: &ProtocolRegistry,
+    targets: &[AllocationTarget],
+    nav: u64,
+    target_accounts: Option<&[AccountInfo]>,
+) -> Result<()> {
+    // The same protocol may appear twice, but only for different target accounts
+    for (i, a) in targets.iter().enumerate() {
+        for b in &targets[i + 1..] {
+            if a.protocol_id == b.protocol_id && a.target == b.target {
+                return err!(VaultError::InvalidAllocations);
+            }
+        }
+    }
+
+    let mut target_accounts = target_accounts.map(|accounts| accounts.iter());
+    for target in targets {
+        let entry = registry
+            .find(target.protocol_id)
+            .ok_or(VaultError::ProtocolNotRegistered)?;
+        require!(
+            entry.status == ProtocolStatus::Active,
+            VaultError::ProtocolNotActive
+        );
+        require!(
+            target.params.len() <= entry.params_len as usize,
+            VaultError::InvalidAllocationParams
+        );
+
+        let explicit_target = target.target != Pubkey::default();
+        if let Some(accounts) = target_accounts.as_mut().filter(|_| explicit_target) {
+            let account = accounts
+                .next()
+                .ok_or(VaultError::InvalidAllocationTarget)?;
+            require_keys_eq!(
+                account.key(),
+                target.target,
+                VaultError::InvalidAllocationTarget
+            );
+            // Validators are vote accounts; every other adapter targets accounts
+            // owned by the protocol's program
+            let expected_owner = match entry.adapter_kind {
+                AdapterKind::NativeStake => VOTE_PROGRAM_ID,
+                _ => entry.program_id,
+            };
+            require_keys_eq!(
+                *account.owner,
+                expected_owner,
+                VaultError::InvalidAllocationTarget
+            );
+        }
+
+        let limits = &entry.limits;
+        let target_bps = target.target_bps;
+        require!(
+            target_bps >= limits.min_target_bps
+                && target_bps <= limits.max_target_bps
+                && target_bps <= limits.max_exposure_bps,
+            VaultError::RiskLimitBreached
+        );
+        let target_amount =
+            (nav as u128 * target_bps as u128 / BPS_DENOMINATOR as u128) as u64;
+        require!(
+            target_amount <= limits.exposure_cap(nav),
+            VaultError::RiskLimitBreached
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,121 @@
+use anchor_lang::prelude::*;
+use crate::errors::VaultError;
+use crate::instructions::registry::validate_targets;
+use crate::state::*;
+
+// ── Authority commits an allocation change that activates at a future time ──
+
+#[derive(Accounts)]
+pub struct ScheduleAllocations<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        seeds = [REGISTRY_SEED],
+        bump = registry.bump,
+    )]
+    pub registry: Account<'info, ProtocolRegistry>,
+
+    /// One pending schedule at a time; cancel it to replace it
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + AllocationSchedule::INIT_SPACE,
+        seeds = [SCHEDULE_SEED],
+        bump,
+    )]
+    pub schedule: Account<'info, AllocationSchedule>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Targets are checked now, with explicit target accounts passed as remaining
+/// accounts; statuses and risk limits are checked again when `rebalance` applies them.
+pub fn schedule_allocations_handler(
+    ctx: Context<ScheduleAllocations>,
+    targets: Vec<AllocationTarget>,
+    activation_ts: i64,
+    ramp_seconds: i64,
+) -> Result<()> {
+    require!(
+        targets.len() <= MAX_ALLOCATIONS,
+        VaultError::TooManyAllocations
+    );
+    require!(!targets.is_empty(), VaultError::InvalidAllocations);
+
+    let total_bps: u64 = targets.iter().map(|a| a.target_bps as u64).sum();
+    require!(total_bps == BPS_DENOMINATOR, VaultError::InvalidAllocations);
+
+    let now = Clock::get()?.unix_timestamp;
+    require!(activation_ts > now, VaultError::InvalidSchedule);
+    require!(
+        (0..=MAX_RAMP_SECONDS).contains(&ramp_seconds),
+        VaultError::InvalidRamp
+    );
+
+    validate_targets(
+        &ctx.accounts.registry,
+        &targets,
+        ctx.accounts.vault.load()?.total_deposited,
+        Some(ctx.remaining_accounts),
+    )?;
+
+    let schedule = &mut ctx.accounts.schedule;
+    schedule.targets = targets;
+    schedule.activation_ts = activation_ts;
+    schedule.ramp_seconds = ramp_seconds;
+    schedule.scheduled_ts = now;
+    schedule.bump = ctx.bumps.schedule;
+
+    emit!(AllocationScheduledEvent {
+        activation_ts,
+        ramp_seconds,
+        num_allocations: schedule.targets.len() as u8,
+    });
+
+    msg!(
+        "Scheduled {} allocations to activate at {}",
+        schedule.targets.len(),
+        activation_ts
+    );
+    Ok(())
+}
+
+// ── Authority withdraws a pending allocation change ──
+
+#[derive(Accounts)]
+pub struct CancelAllocationSchedule<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [SCHEDULE_SEED],
+        bump = schedule.bump,
+        close = authority,
+    )]
+    pub schedule: Account<'info, AllocationSchedule>,
+}
+
+pub fn cancel_allocation_schedule_handler(ctx: Context<CancelAllocationSchedule>) -> Result<()> {
+    let activation_ts = ctx.accounts.schedule.activation_ts;
+
+    emit!(AllocationScheduleCancelledEvent { activation_ts });
+
+    msg!("Cancelled allocation change scheduled for {}", activation_ts);
+    Ok(())
+}
@@ -0,0 +1,318 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program::invoke;
+use anchor_lang::solana_program::sysvar::stake_history;
+use solana_stake_interface::{
+    self as stake,
+    stake_history::{Epoch, StakeHistoryEntry, StakeHistoryGetEntry},
+    state::{StakeAuthorize, StakeStateV2},
+};
+use crate::errors::VaultError;
+use crate::instructions::deposit::{credit_deposit, quote_deposit};
+use crate::state::*;
+
+// ── Authority approves a validator for stake account deposits ──
+
+#[derive(Accounts)]
+pub struct RegisterValidator<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    /// CHECK: only its address and owner are used; must be a vote account
+    #[account(owner = VOTE_PROGRAM_ID @ VaultError::InvalidStakeAccount)]
+    pub vote_account: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ValidatorConfig::INIT_SPACE,
+        seeds = [VALIDATOR_SEED, vote_account.key().as_ref()],
+        bump,
+    )]
+    pub validator_config: Account<'info, ValidatorConfig>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn register_validator_handler(ctx: Context<RegisterValidator>, protocol_id: u8) -> Result<()> {
+    require!(
+        ctx.accounts
+            .vault
+            .load()?
+            .active_allocations()
+            .iter()
+            .any(|a| a.protocol_id == protocol_id),
+        VaultError::InvalidAllocations
+    );
+
+    let vote_account = ctx.accounts.vote_account.key();
+    let validator_config = &mut ctx.accounts.validator_config;
+    validator_config.vote_account = vote_account;
+    validator_config.protocol_id = protocol_id;
+    validator_config.stake_lamports = 0;
+    validator_config.stake_accounts = 0;
+    validator_config.is_enabled = true;
+    validator_config.bump = ctx.bumps.validator_config;
+
+    emit!(ValidatorConfigUpdatedEvent {
+        vote_account,
+        protocol_id,
+        is_enabled: true,
+    });
+
+    msg!("Approved validator {} for allocation {}", vote_account, protocol_id);
+    Ok(())
+}
+
+// ── Authority pauses or resumes stake deposits for a validator ──
+
+#[derive(Accounts)]
+pub struct UpdateValidator<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [VALIDATOR_SEED, validator_config.vote_account.as_ref()],
+        bump = validator_config.bump,
+    )]
+    pub validator_config: Account<'info, ValidatorConfig>,
+}
+
+pub fn update_validator_handler(ctx: Context<UpdateValidator>, is_enabled: bool) -> Result<()> {
+    let validator_config = &mut ctx.accounts.validator_config;
+    validator_config.is_enabled = is_enabled;
+
+    emit!(ValidatorConfigUpdatedEvent {
+        vote_account: validator_config.vote_account,
+        protocol_id: validator_config.protocol_id,
+        is_enabled,
+    });
+
+    msg!(
+        "Validator {} stake deposits enabled: {}",
+        validator_config.vote_account,
+        is_enabled
+    );
+    Ok(())
+}
+
+// ── Deposit an active stake account; the vault PDA takes over both authorities ──
+
+#[derive(Accounts)]
+#[instruction(index: u16)]
+pub struct DepositStake<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user.key().as_ref(), &index.to_le_bytes()],
+        bump,
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    /// CHECK: parsed as stake state in the handler
+    #[account(mut, owner = stake::program::ID @ VaultError::InvalidStakeAccount)]
+    pub stake_account: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [VALIDATOR_SEED, validator_config.vote_account.as_ref()],
+        bump = validator_config.bump,
+    )]
+    pub validator_config: Account<'info, ValidatorConfig>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    /// CHECK: the StakeHistory sysvar; read in place by `StakeHistoryAccount`
+    #[account(address = stake_history::ID)]
+    pub stake_history: UncheckedAccount<'info>,
+
+    /// CHECK: stake program, invoked to move the authorities
+    #[account(address = stake::program::ID)]
+    pub stake_program: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn deposit_stake_handler(
+    ctx: Context<DepositStake>,
+    index: u16,
+    min_shares_out: u64,
+) -> Result<()> {
+    let vault = ctx.accounts.vault.load()?;
+    let validator_config = &ctx.accounts.validator_config;
+    let clock = &ctx.accounts.clock;
+    let user_key = ctx.accounts.user.key();
+
+    require!(vault.async_mode == 0, VaultError::AsyncModeEnabled);
+    // Staked lamports only back shares of a SOL-denominated vault
+    require!(vault.is_native(), VaultError::NativeVaultOnly);
+    require!(validator_config.is_enabled, VaultError::ValidatorNotApproved);
+
+    let (meta, delegation) = {
+        let data = ctx.accounts.stake_account.try_borrow_data()?;
+        match StakeStateV2::deserialize(&mut &data[..])
+            .map_err(|_| VaultError::InvalidStakeAccount)?
+        {
+            StakeStateV2::Stake(meta, stake, _) => (meta, stake.delegation),
+            _ => return err!(VaultError::InvalidStakeAccount),
+        }
+    };
+
+    require_keys_eq!(
+        delegation.voter_pubkey,
+        validator_config.vote_account,
+        VaultError::ValidatorNotApproved
+    );
+    // Fully warmed up and not cooling down: warmup is throttled cluster-wide, so an
+    // account activated epochs ago can still be partly activating
+    require!(delegation.deactivation_epoch == u64::MAX, VaultError::StakeNotActive);
+    let effective_stake = delegation.stake(
+        clock.epoch,
+        &StakeHistoryAccount(&ctx.accounts.stake_history.try_borrow_data()?),
+        // The slower post-SIMD-0093 rate, so partly active stake is never counted as
+        // effective whichever rate the cluster is on
+        Some(0),
+    );
+    require!(effective_stake == delegation.stake, VaultError::StakeNotActive);
+    require_keys_eq!(meta.authorized.staker, user_key, VaultError::Unauthorized);
+    require_keys_eq!(meta.authorized.withdrawer, user_key, VaultError::Unauthorized);
+    require!(!meta.lockup.is_in_force(clock, None), VaultError::StakeLocked);
+
+    // Delegated stake plus the rent-exempt reserve that comes with the account
+    let sol_value = delegation
+        .stake
+        .checked_add(meta.rent_exempt_reserve)
+        .ok_or(VaultError::MathOverflow)?;
+    let shares_to_mint = quote_deposit(&vault, sol_value)?;
+    require!(shares_to_mint >= min_shares_out, VaultError::SlippageExceeded);
+    drop(vault);
+
+    let vault_key = ctx.accounts.vault.key();
+    for stake_authorize in [StakeAuthorize::Staker, StakeAuthorize::Withdrawer] {
+        let ix = stake::instruction::authorize(
+            &ctx.accounts.stake_account.key(),
+            &user_key,
+            &vault_key,
+            stake_authorize,
+            None,
+        );
+        invoke(
+            &ix,
+            &[
+                ctx.accounts.stake_account.to_account_info(),
+                ctx.accounts.clock.to_account_info(),
+                ctx.accounts.user.to_account_info(),
+            ],
+        )?;
+    }
+
+    let protocol_id = validator_config.protocol_id;
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    credit_deposit(
+        &mut vault,
+        &mut ctx.accounts.position,
+        user_key,
+        index,
+        ctx.bumps.position,
+        sol_value,
+        shares_to_mint,
+    )?;
+
+    // Book the stake straight into the native staking allocation
+    let allocation = vault
+        .active_allocations_mut()
+        .iter_mut()
+        .find(|a| a.protocol_id == protocol_id)
+        .ok_or(VaultError::InvalidAllocations)?;
+    allocation.current_amount = allocation
+        .current_amount
+        .checked_add(sol_value)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.stake_value = vault
+        .stake_value
+        .checked_add(sol_value)
+        .ok_or(VaultError::MathOverflow)?;
+
+    let validator_config = &mut ctx.accounts.validator_config;
+    validator_config.stake_lamports = validator_config
+        .stake_lamports
+        .checked_add(sol_value)
+        .ok_or(VaultError::MathOverflow)?;
+    validator_config.stake_accounts = validator_config
+        .stake_accounts
+        .checked_add(1)
+        .ok_or(VaultError::MathOverflow)?;
+
+    emit!(StakeDepositEvent {
+        user: user_key,
+        index,
+        stake_account: ctx.accounts.stake_account.key(),
+        vote_account: validator_config.vote_account,
+        sol_value,
+        shares_minted: shares_to_mint,
+        total_deposited: vault.total_deposited,
+        total_shares: vault.total_shares,
+    });
+
+    msg!(
+        "Deposited stake account worth {} lamports, minted {} shares",
+        sol_value,
+        shares_to_mint
+    );
+    Ok(())
+}
+
+/// The StakeHistory sysvar read straight from its account: a u64 entry count, then
+/// 32-byte `(epoch, effective, activating, deactivating)` entries, newest epoch first
+/// and one per epoch. Deserializing all 512 entries would cost far more.
+struct StakeHistoryAccount<'a>(&'a [u8]);
+
+impl StakeHistoryAccount<'_> {
+    fn read_u64(&self, offset: usize) -> Option<u64> {
+        let bytes = self.0.get(offset..offset + 8)?;
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+impl StakeHistoryGetEntry for StakeHistoryAccount<'_> {
+    fn get_entry(&self, epoch: Epoch) -> Option<StakeHistoryEntry> {
+        let len = self.read_u64(0)?;
+        let index = self.read_u64(8)?.checked_sub(epoch)?;
+        if index >= len {
+            return None;
+        }
+        let offset = 8 + (index as usize) * 32;
+        if self.read_u64(offset)? != epoch {
+            return None;
+        }
+        Some(StakeHistoryEntry {
+            effective: self.read_u64(offset + 8)?,
+            activating: self.read_u64(offset + 16)?,
+            deactivating: self.read_u64(offset + 24)?,
+        })
+    }
+}
@@ -11,14 +11,14 @@ pub struct ProposeAuthority<'info> {
     #[account(
         mut,
         seeds = [VAULT_SEED],
-        bump = vault.bump,
+        bump = vault.load()?.bump,
         has_one = authority @ VaultError::Unauthorized,
     )]
-    pub vault: Account<'info, Vault>,
+    pub vault: AccountLoader<'info, Vault>,
 }
 
 pub fn propose_handler(ctx: Context<ProposeAuthority>, new_authority: Pubkey) -> Result<()> {
-    let vault = &mut ctx.accounts.vault;
+    let mut vault = ctx.accounts.vault.load_mut()?;
     vault.pending_authority = new_authority;
 
     emit!(AuthorityProposedEvent {
@@ -43,14 +43,14 @@ pub struct AcceptAuthority<'info> {
     #[account(
         mut,
         seeds = [VAULT_SEED],
-        bump = vault.bump,
-        constraint = vault.pending_authority == new_authority.key() @ VaultError::Unauthorized,
+        bump = vault.load()?.bump,
+        constraint = vault.load()?.pending_authority == new_authority.key() @ VaultError::Unauthorized,
     )]
-    pub vault: Account<'info, Vault>,
+    pub vault: AccountLoader<'info, Vault>,
 }
 
 pub fn accept_handler(ctx: Context<AcceptAuthority>) -> Result<()> {
-    let vault = &mut ctx.accounts.vault;
+    let mut vault = ctx.accounts.vault.load_mut()?;
 
     require!(
         vault.pending_authority != Pubkey::default(),
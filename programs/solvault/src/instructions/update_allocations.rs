@@ -1,5 +1,6 @@
 use anchor_lang::prelude::*;
 use crate::errors::VaultError;
+use crate::instructions::registry::validate_targets;
 use crate::state::*;
 
 #[derive(Accounts)]
@@ -9,15 +10,23 @@ pub struct UpdateAllocations<'info> {
     #[account(
         mut,
         seeds = [VAULT_SEED],
-        bump = vault.bump,
+        bump = vault.load()?.bump,
         has_one = authority @ VaultError::Unauthorized,
     )]
-    pub vault: Account<'info, Vault>,
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        seeds = [REGISTRY_SEED],
+        bump = registry.bump,
+    )]
+    pub registry: Account<'info, ProtocolRegistry>,
 }
 
 pub fn handler(
     ctx: Context<UpdateAllocations>,
-    new_allocations: Vec<Allocation>,
+    new_allocations: Vec<AllocationTarget>,
+    ramp_start_ts: i64,
+    ramp_end_ts: i64,
 ) -> Result<()> {
     require!(
         new_allocations.len() <= MAX_ALLOCATIONS,
@@ -25,28 +34,36 @@ pub fn handler(
     );
     require!(!new_allocations.is_empty(), VaultError::InvalidAllocations);
 
-    let total_pct: u16 = new_allocations.iter().map(|a| a.target_pct as u16).sum();
-    require!(total_pct == 100, VaultError::InvalidAllocations);
-
-    // Validate no duplicate protocol IDs
-    for (i, a) in new_allocations.iter().enumerate() {
-        for (j, b) in new_allocations.iter().enumerate() {
-            if i != j && a.protocol_id == b.protocol_id {
-                return err!(VaultError::InvalidAllocations);
-            }
-        }
-    }
+    let total_bps: u64 = new_allocations.iter().map(|a| a.target_bps as u64).sum();
+    require!(total_bps == BPS_DENOMINATOR, VaultError::InvalidAllocations);
+    require!(
+        ramp_end_ts >= ramp_start_ts && ramp_end_ts - ramp_start_ts <= MAX_RAMP_SECONDS,
+        VaultError::InvalidRamp
+    );
 
-    // Zero out current_amount to prevent stale bookkeeping
-    let mut cleaned_allocations = new_allocations;
-    for alloc in cleaned_allocations.iter_mut() {
-        alloc.current_amount = 0;
-    }
+    let mut vault = ctx.accounts.vault.load_mut()?;
+    // An open rebalance session moves allocations by slot
+    require!(vault.rebalancing == 0, VaultError::RebalanceInProgress);
+    validate_targets(
+        &ctx.accounts.registry,
+        &new_allocations,
+        vault.total_deposited,
+        Some(ctx.remaining_accounts),
+    )?;
 
-    let vault = &mut ctx.accounts.vault;
-    vault.num_allocations = cleaned_allocations.len() as u8;
-    vault.allocations = cleaned_allocations;
+    // Continuing allocations keep their current_amount; new ones start at zero
+    let now = Clock::get()?.unix_timestamp;
+    vault.set_allocations(&new_allocations, ramp_start_ts, ramp_end_ts, now)?;
 
-    msg!("Updated allocations to {} protocols", vault.num_allocations);
+    if ramp_end_ts > now {
+        msg!(
+            "Ramping to {} protocols between {} and {}",
+            new_allocations.len(),
+            ramp_start_ts,
+            ramp_end_ts
+        );
+    } else {
+        msg!("Updated allocations to {} protocols", vault.num_allocations);
+    }
     Ok(())
 }
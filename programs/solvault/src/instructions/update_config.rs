@@ -9,10 +9,10 @@ pub struct UpdateConfig<'info> {
     #[account(
         mut,
         seeds = [VAULT_SEED],
-        bump = vault.bump,
+        bump = vault.load()?.bump,
         has_one = authority @ VaultError::Unauthorized,
     )]
-    pub vault: Account<'info, Vault>,
+    pub vault: AccountLoader<'info, Vault>,
 }
 
 pub fn handler(
@@ -20,8 +20,9 @@ pub fn handler(
     new_fee_bps: Option<u16>,
     new_deposit_cap: Option<u64>,
     new_paused: Option<bool>,
+    new_withdraw_delay_slots: Option<u64>,
 ) -> Result<()> {
-    let vault = &mut ctx.accounts.vault;
+    let mut vault = ctx.accounts.vault.load_mut()?;
 
     if let Some(fee) = new_fee_bps {
         require!(fee <= MAX_FEE_BPS, VaultError::FeeTooHigh);
@@ -35,14 +36,20 @@ pub fn handler(
     }
 
     if let Some(paused) = new_paused {
-        vault.is_paused = paused;
+        vault.is_paused = paused as u8;
         msg!("Vault paused: {}", paused);
     }
 
+    if let Some(delay) = new_withdraw_delay_slots {
+        vault.withdraw_delay_slots = delay;
+        msg!("Withdrawals wait {} slots after a deposit", delay);
+    }
+
     emit!(ConfigUpdatedEvent {
         fee_bps: new_fee_bps,
         deposit_cap: new_deposit_cap,
         paused: new_paused,
+        withdraw_delay_slots: new_withdraw_delay_slots,
     });
 
     Ok(())
@@ -1,8 +1,13 @@
 use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 use crate::errors::VaultError;
+use crate::instructions::asset::{token_transfer, transfer_out, TokenTransfer};
+use crate::instructions::circuit_breaker::{admit_outflow, check_outflow};
+use crate::instructions::deposit::{virtual_assets, virtual_shares};
 use crate::state::*;
 
 #[derive(Accounts)]
+#[instruction(index: u16)]
 pub struct Withdraw<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
@@ -10,123 +15,201 @@ pub struct Withdraw<'info> {
     #[account(
         mut,
         seeds = [VAULT_SEED],
-        bump = vault.bump,
+        bump = vault.load()?.bump,
     )]
-    pub vault: Account<'info, Vault>,
+    pub vault: AccountLoader<'info, Vault>,
 
     #[account(
         mut,
-        seeds = [POSITION_SEED, user.key().as_ref()],
+        seeds = [POSITION_SEED, user.key().as_ref(), &index.to_le_bytes()],
         bump = position.bump,
         constraint = position.owner == user.key() @ VaultError::Unauthorized,
     )]
     pub position: Account<'info, UserPosition>,
 
+    /// Base asset accounts; required for token vaults, omitted for native SOL
+    pub base_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub vault_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+
     pub system_program: Program<'info, System>,
 }
 
-pub fn handler(ctx: Context<Withdraw>, shares_to_burn: u64) -> Result<()> {
-    let vault = &ctx.accounts.vault;
+/// Burn `shares_to_burn` with no bound on the SOL received
+pub fn handler(ctx: Context<Withdraw>, _index: u16, shares_to_burn: u64) -> Result<()> {
+    let vault = ctx.accounts.vault.load()?;
 
-    // Withdrawals are always allowed — even when paused — so users can always exit
-    require!(shares_to_burn > 0, VaultError::ZeroAmount);
+    // Pausing never blocks withdrawals, so users can exit a paused vault.
+    // In async mode the exit goes through request_withdraw instead.
+    require!(vault.async_mode == 0, VaultError::AsyncModeEnabled);
+
+    let quote = quote_withdrawal(&vault, &ctx.accounts.position, shares_to_burn)?;
+    let recipient = ctx.accounts.user.to_account_info();
+    let token = token_transfer(
+        &vault,
+        &ctx.accounts.base_mint,
+        &ctx.accounts.vault_token_account,
+        &ctx.accounts.user_token_account,
+        &ctx.accounts.token_program,
+    )?;
+    drop(vault);
+    execute_withdrawal(
+        &ctx.accounts.vault,
+        &mut ctx.accounts.position,
+        &recipient,
+        shares_to_burn,
+        token,
+        quote,
+    )
+}
+
+/// Exact-in: burn `shares`, receiving at least `min_lamports_out` after fees
+pub fn redeem_handler(
+    ctx: Context<Withdraw>,
+    _index: u16,
+    shares: u64,
+    min_lamports_out: u64,
+) -> Result<()> {
+    let vault = ctx.accounts.vault.load()?;
+
+    require!(vault.async_mode == 0, VaultError::AsyncModeEnabled);
+
+    let quote = quote_withdrawal(&vault, &ctx.accounts.position, shares)?;
     require!(
-        ctx.accounts.position.shares >= shares_to_burn,
-        VaultError::InsufficientShares
+        quote.net_amount >= min_lamports_out,
+        VaultError::SlippageExceeded
     );
+    let recipient = ctx.accounts.user.to_account_info();
+    let token = token_transfer(
+        &vault,
+        &ctx.accounts.base_mint,
+        &ctx.accounts.vault_token_account,
+        &ctx.accounts.user_token_account,
+        &ctx.accounts.token_program,
+    )?;
+    drop(vault);
+    execute_withdrawal(
+        &ctx.accounts.vault,
+        &mut ctx.accounts.position,
+        &recipient,
+        shares,
+        token,
+        quote,
+    )
+}
+
+/// Exact-out: receive `lamports` after fees, burning at most `max_shares_burned`.
+/// Shares burned round UP, so the payout may exceed `lamports` by a rounding lamport.
+pub fn withdraw_exact_handler(
+    ctx: Context<Withdraw>,
+    _index: u16,
+    lamports: u64,
+    max_shares_burned: u64,
+) -> Result<()> {
+    let vault = ctx.accounts.vault.load()?;
+    let position = &ctx.accounts.position;
+
+    require!(vault.async_mode == 0, VaultError::AsyncModeEnabled);
+    require!(lamports > 0, VaultError::ZeroAmount);
     require!(vault.total_shares > 0, VaultError::NoSharesOutstanding);
 
-    // Calculate SOL to return: amount = shares_to_burn * total_deposited / total_shares
-    let withdraw_amount = calculate_withdrawal_amount(
-        shares_to_burn,
-        vault.total_deposited,
-        vault.total_shares,
+    let shares = shares_for_net_amount(&vault, position, lamports)?;
+    require!(shares <= max_shares_burned, VaultError::SlippageExceeded);
+
+    let quote = quote_withdrawal(&vault, position, shares)?;
+    let recipient = ctx.accounts.user.to_account_info();
+    let token = token_transfer(
+        &vault,
+        &ctx.accounts.base_mint,
+        &ctx.accounts.vault_token_account,
+        &ctx.accounts.user_token_account,
+        &ctx.accounts.token_program,
     )?;
+    drop(vault);
+    execute_withdrawal(
+        &ctx.accounts.vault,
+        &mut ctx.accounts.position,
+        &recipient,
+        shares,
+        token,
+        quote,
+    )
+}
 
-    // Calculate performance fee on any yield
-    let position = &ctx.accounts.position;
-    let proportional_deposit: u64 = (position.deposited_amount as u128)
-        .checked_mul(shares_to_burn as u128)
-        .ok_or(VaultError::MathOverflow)?
-        .checked_div(position.shares as u128)
-        .ok_or(VaultError::MathOverflow)?
-        .try_into()
-        .map_err(|_| VaultError::MathOverflow)?;
+/// Pay out a quoted withdrawal to `recipient`, burn the shares and update bookkeeping.
+pub(crate) fn execute_withdrawal<'info>(
+    vault: &AccountLoader<'info, Vault>,
+    position: &mut Account<'info, UserPosition>,
+    recipient: &AccountInfo<'info>,
+    shares_to_burn: u64,
+    token: Option<TokenTransfer<'_, 'info>>,
+    quote: WithdrawalQuote,
+) -> Result<()> {
+    let fee = quote.fee;
+    let net_amount = quote.net_amount;
 
-    let fee = if withdraw_amount > proportional_deposit {
-        let yield_amount = withdraw_amount
-            .checked_sub(proportional_deposit)
-            .ok_or(VaultError::MathOverflow)?;
-        let fee: u64 = (yield_amount as u128)
-            .checked_mul(vault.performance_fee_bps as u128)
-            .ok_or(VaultError::MathOverflow)?
-            .checked_div(BPS_DENOMINATOR as u128)
-            .ok_or(VaultError::MathOverflow)?
-            .try_into()
-            .map_err(|_| VaultError::MathOverflow)?;
-        fee
-    } else {
-        0
-    };
+    admit_outflow(&mut *vault.load_mut()?, net_amount)?;
 
-    let net_amount = withdraw_amount
-        .checked_sub(fee)
-        .ok_or(VaultError::MathOverflow)?;
+    // Pay the base asset out to the position owner (or their chosen recipient)
+    transfer_out(vault, recipient, token, net_amount)?;
 
-    // Check remaining shares won't leave dust (use post-withdrawal values)
-    let remaining_shares = position
-        .shares
-        .checked_sub(shares_to_burn)
-        .ok_or(VaultError::MathOverflow)?;
-    if remaining_shares > 0 {
-        let post_total_deposited = vault
-            .total_deposited
-            .checked_sub(withdraw_amount)
-            .ok_or(VaultError::MathOverflow)?;
-        let post_total_shares = vault
-            .total_shares
-            .checked_sub(shares_to_burn)
-            .ok_or(VaultError::MathOverflow)?;
-        let remaining_value: u64 = (remaining_shares as u128)
-            .checked_mul(post_total_deposited as u128)
-            .ok_or(VaultError::MathOverflow)?
-            .checked_div(post_total_shares as u128)
-            .ok_or(VaultError::MathOverflow)?
-            .try_into()
-            .map_err(|_| VaultError::MathOverflow)?;
-        require!(
-            remaining_value >= MIN_DEPOSIT_LAMPORTS,
-            VaultError::DustWithdrawal
-        );
-    }
+    let mut vault = vault.load_mut()?;
+    settle_withdrawal(&mut vault, position, shares_to_burn, &quote)?;
 
-    // Ensure vault stays above rent-exempt minimum after transfer
-    let vault_account_info = ctx.accounts.vault.to_account_info();
-    let rent = Rent::get()?;
-    let min_balance = rent.minimum_balance(vault_account_info.data_len());
-    let vault_lamports_after = vault_account_info
-        .lamports()
-        .checked_sub(net_amount)
-        .ok_or(VaultError::MathOverflow)?;
-    require!(
-        vault_lamports_after >= min_balance,
-        VaultError::BelowRentExemption
+    emit!(WithdrawEvent {
+        user: position.owner,
+        index: position.index,
+        shares_burned: shares_to_burn,
+        amount_returned: net_amount,
+        fee_charged: fee,
+        total_deposited: vault.total_deposited,
+        total_shares: vault.total_shares,
+    });
+
+    msg!(
+        "Withdrew {} lamports (fee: {}), burned {} shares",
+        net_amount,
+        fee,
+        shares_to_burn
     );
+    Ok(())
+}
 
-    // Transfer SOL from vault PDA to user
-    **vault_account_info.try_borrow_mut_lamports()? = vault_lamports_after;
-    **ctx.accounts.user.try_borrow_mut_lamports()? = ctx
-        .accounts
-        .user
-        .lamports()
-        .checked_add(net_amount)
-        .ok_or(VaultError::MathOverflow)?;
+/// Checks a quoted instant withdrawal meets once priced, in the order `execute_withdrawal`
+/// applies them: the outflow limit, the withdrawal delay and the funds a live rebalance
+/// holds back. `preview_withdraw` runs these so it fails where `withdraw` would.
+pub(crate) fn check_withdrawal_limits(
+    vault: &Vault,
+    position: &UserPosition,
+    shares_to_burn: u64,
+    quote: &WithdrawalQuote,
+) -> Result<()> {
+    check_outflow(vault, quote.net_amount)?;
+    vault.check_withdraw_delay(position, shares_to_burn)?;
+    vault.check_rebalance_liquidity(quote.gross_amount)
+}
+
+/// Burn the shares of a paid-out withdrawal and update vault and position bookkeeping.
+pub(crate) fn settle_withdrawal(
+    vault: &mut Vault,
+    position: &mut UserPosition,
+    shares_to_burn: u64,
+    quote: &WithdrawalQuote,
+) -> Result<()> {
+    let remaining_shares = quote.remaining_shares;
+    vault.check_withdraw_delay(position, shares_to_burn)?;
+    vault.check_rebalance_liquidity(quote.gross_amount)?;
 
     // Update vault state
-    let vault = &mut ctx.accounts.vault;
     vault.total_deposited = vault
         .total_deposited
-        .checked_sub(withdraw_amount)
+        .checked_sub(quote.gross_amount)
         .ok_or(VaultError::MathOverflow)?;
     vault.total_shares = vault
         .total_shares
@@ -134,28 +217,15 @@ pub fn handler(ctx: Context<Withdraw>, shares_to_burn: u64) -> Result<()> {
         .ok_or(VaultError::MathOverflow)?;
     vault.accrued_fees = vault
         .accrued_fees
-        .checked_add(fee)
+        .checked_add(quote.fee)
         .ok_or(VaultError::MathOverflow)?;
 
     // Update user position
-    let position = &mut ctx.accounts.position;
     position.shares = remaining_shares;
     // Reduce deposited_amount proportionally
-    let deposit_reduction: u64 = (position.deposited_amount as u128)
-        .checked_mul(shares_to_burn as u128)
-        .ok_or(VaultError::MathOverflow)?
-        .checked_div(
-            position
-                .shares
-                .checked_add(shares_to_burn)
-                .ok_or(VaultError::MathOverflow)? as u128,
-        )
-        .ok_or(VaultError::MathOverflow)?
-        .try_into()
-        .map_err(|_| VaultError::MathOverflow)?;
     position.deposited_amount = position
         .deposited_amount
-        .checked_sub(deposit_reduction)
+        .checked_sub(quote.cost_basis)
         .ok_or(VaultError::MathOverflow)?;
 
     if remaining_shares == 0 {
@@ -165,38 +235,203 @@ pub fn handler(ctx: Context<Withdraw>, shares_to_burn: u64) -> Result<()> {
             .ok_or(VaultError::MathOverflow)?;
     }
 
-    emit!(WithdrawEvent {
-        user: ctx.accounts.user.key(),
-        shares_burned: shares_to_burn,
-        amount_returned: net_amount,
-        fee_charged: fee,
-        total_deposited: vault.total_deposited,
-        total_shares: vault.total_shares,
-    });
+    Ok(())
+}
 
-    msg!(
-        "Withdrew {} lamports (fee: {}), burned {} shares",
-        net_amount,
-        fee,
-        shares_to_burn
+/// Result of pricing a withdrawal against the current vault and position state.
+pub(crate) struct WithdrawalQuote {
+    /// SOL value of the burned shares before fees
+    pub gross_amount: u64,
+    /// Performance fee charged on the yield portion
+    pub fee: u64,
+    /// SOL paid out to the user
+    pub net_amount: u64,
+    /// Cost basis removed from the position with the burned shares
+    pub cost_basis: u64,
+    /// Shares left in the position afterwards
+    pub remaining_shares: u64,
+}
+
+/// Price a withdrawal with the same checks the withdraw instruction enforces:
+/// share balance, performance fee on yield, and the post-withdrawal dust rule.
+pub(crate) fn quote_withdrawal(
+    vault: &Vault,
+    position: &UserPosition,
+    shares_to_burn: u64,
+) -> Result<WithdrawalQuote> {
+    require!(shares_to_burn > 0, VaultError::ZeroAmount);
+    require!(
+        position.shares >= shares_to_burn,
+        VaultError::InsufficientShares
     );
-    Ok(())
+    require!(vault.total_shares > 0, VaultError::NoSharesOutstanding);
+
+    let quote = price_withdrawal(vault, position, shares_to_burn)?;
+
+    // Check remaining shares won't leave dust (use post-withdrawal values)
+    if quote.remaining_shares > 0 {
+        let post_total_deposited = vault
+            .total_deposited
+            .checked_sub(quote.gross_amount)
+            .ok_or(VaultError::MathOverflow)?;
+        let post_total_shares = vault
+            .total_shares
+            .checked_sub(shares_to_burn)
+            .ok_or(VaultError::MathOverflow)?;
+        let remaining_value = calculate_withdrawal_amount(
+            quote.remaining_shares,
+            post_total_deposited,
+            post_total_shares,
+        )?;
+        require!(
+            remaining_value >= MIN_DEPOSIT_LAMPORTS,
+            VaultError::DustWithdrawal
+        );
+    }
+
+    Ok(quote)
 }
 
-/// Calculate the SOL amount for a given number of shares to burn.
+/// Gross value, fee and net payout of burning `shares_to_burn`, without the
+/// dust rule. Callers must ensure the position holds at least that many shares.
+fn price_withdrawal(
+    vault: &Vault,
+    position: &UserPosition,
+    shares_to_burn: u64,
+) -> Result<WithdrawalQuote> {
+    // Calculate SOL to return: amount = shares_to_burn * total_deposited / total_shares
+    let withdraw_amount = calculate_withdrawal_amount(
+        shares_to_burn,
+        vault.total_deposited,
+        vault.total_shares,
+    )?;
+
+    // Calculate performance fee on any yield
+    let proportional_deposit: u64 = (position.deposited_amount as u128)
+        .checked_mul(shares_to_burn as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_div(position.shares as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .try_into()
+        .map_err(|_| VaultError::MathOverflow)?;
+
+    let fee = calculate_performance_fee(
+        withdraw_amount,
+        proportional_deposit,
+        vault.performance_fee_bps,
+    )?;
+
+    let net_amount = withdraw_amount
+        .checked_sub(fee)
+        .ok_or(VaultError::MathOverflow)?;
+
+    let remaining_shares = position
+        .shares
+        .checked_sub(shares_to_burn)
+        .ok_or(VaultError::MathOverflow)?;
+
+    Ok(WithdrawalQuote {
+        gross_amount: withdraw_amount,
+        fee,
+        net_amount,
+        cost_basis: proportional_deposit,
+        remaining_shares,
+    })
+}
+
+/// Upper bound on refinement rounds in `shares_for_net_amount`. Each round closes
+/// at least (1 - MAX_FEE_BPS) of the remaining shortfall, so this is never reached
+/// for u64 amounts.
+const MAX_EXACT_OUT_ROUNDS: usize = 64;
+
+/// Smallest number of shares whose net payout (after performance fee) covers `lamports`.
+/// Starts from the fee-free share count (rounded UP) and tops up until the net
+/// payout is reached, so the withdrawer never receives less than requested.
+fn shares_for_net_amount(vault: &Vault, position: &UserPosition, lamports: u64) -> Result<u64> {
+    let mut shares =
+        calculate_shares_for_withdrawal(lamports, vault.total_deposited, vault.total_shares)?;
+
+    for _ in 0..MAX_EXACT_OUT_ROUNDS {
+        require!(shares <= position.shares, VaultError::InsufficientShares);
+        let quote = price_withdrawal(vault, position, shares)?;
+        if quote.net_amount >= lamports {
+            return Ok(shares);
+        }
+        let shortfall = lamports
+            .checked_sub(quote.net_amount)
+            .ok_or(VaultError::MathOverflow)?;
+        shares = shares
+            .checked_add(calculate_shares_for_withdrawal(
+                shortfall,
+                vault.total_deposited,
+                vault.total_shares,
+            )?)
+            .ok_or(VaultError::MathOverflow)?;
+    }
+
+    err!(VaultError::MathOverflow)
+}
+
+/// Calculate the SOL amount for a given number of shares to burn:
+/// amount = shares * (total_deposited + VIRTUAL_ASSETS) / (total_shares + VIRTUAL_SHARES).
 /// Rounding: integer division truncates DOWN, which favors the vault
 /// (withdrawer receives slightly less), protecting remaining share holders.
-fn calculate_withdrawal_amount(
+pub(crate) fn calculate_withdrawal_amount(
     shares_to_burn: u64,
     total_deposited: u64,
     total_shares: u64,
 ) -> Result<u64> {
     let amount: u64 = (shares_to_burn as u128)
-        .checked_mul(total_deposited as u128)
+        .checked_mul(virtual_assets(total_deposited))
         .ok_or(VaultError::MathOverflow)?
-        .checked_div(total_shares as u128)
+        .checked_div(virtual_shares(total_shares))
         .ok_or(VaultError::MathOverflow)?
         .try_into()
         .map_err(|_| VaultError::MathOverflow)?;
     Ok(amount)
 }
+
+/// Calculate the shares to burn for a given SOL amount:
+/// shares = lamports * (total_shares + VIRTUAL_SHARES) / (total_deposited + VIRTUAL_ASSETS).
+/// Rounding: rounds UP, so the withdrawer burns any fractional share,
+/// protecting remaining share holders.
+pub(crate) fn calculate_shares_for_withdrawal(
+    lamports: u64,
+    total_deposited: u64,
+    total_shares: u64,
+) -> Result<u64> {
+    let denominator = virtual_assets(total_deposited);
+    let shares: u64 = (lamports as u128)
+        .checked_mul(virtual_shares(total_shares))
+        .ok_or(VaultError::MathOverflow)?
+        .checked_add(denominator - 1)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_div(denominator)
+        .ok_or(VaultError::MathOverflow)?
+        .try_into()
+        .map_err(|_| VaultError::MathOverflow)?;
+    Ok(shares)
+}
+
+/// Calculate the performance fee owed on the yield portion of a withdrawal.
+/// Only the amount above the withdrawn cost basis is charged; principal is never fee'd.
+pub(crate) fn calculate_performance_fee(
+    withdraw_amount: u64,
+    cost_basis: u64,
+    performance_fee_bps: u16,
+) -> Result<u64> {
+    if withdraw_amount <= cost_basis {
+        return Ok(0);
+    }
+    let yield_amount = withdraw_amount
+        .checked_sub(cost_basis)
+        .ok_or(VaultError::MathOverflow)?;
+    let fee: u64 = (yield_amount as u128)
+        .checked_mul(performance_fee_bps as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_div(BPS_DENOMINATOR as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .try_into()
+        .map_err(|_| VaultError::MathOverflow)?;
+    Ok(fee)
+}
@@ -0,0 +1,156 @@
+use anchor_lang::prelude::*;
+use crate::errors::VaultError;
+use crate::state::*;
+
+// ── Authority creates the yield feed and names its reporter ──
+
+#[derive(Accounts)]
+pub struct InitializeYieldFeed<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + YieldFeed::INIT_SPACE,
+        seeds = [FEED_SEED],
+        bump,
+    )]
+    pub feed: Account<'info, YieldFeed>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn initialize_yield_feed_handler(
+    ctx: Context<InitializeYieldFeed>,
+    reporter: Pubkey,
+    max_staleness_secs: i64,
+) -> Result<()> {
+    require!(max_staleness_secs > 0, VaultError::InvalidFeedConfig);
+
+    let feed = &mut ctx.accounts.feed;
+    feed.reporter = reporter;
+    feed.max_staleness_secs = max_staleness_secs;
+    feed.reports = Vec::new();
+    feed.bump = ctx.bumps.feed;
+
+    emit!(YieldFeedConfigUpdatedEvent {
+        reporter,
+        max_staleness_secs,
+    });
+
+    msg!("Yield feed initialized, reporter {}", reporter);
+    Ok(())
+}
+
+// ── Authority rotates the reporter or changes the staleness threshold ──
+
+#[derive(Accounts)]
+pub struct UpdateYieldFeed<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [VAULT_SEED],
+        bump = vault.load()?.bump,
+        has_one = authority @ VaultError::Unauthorized,
+    )]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [FEED_SEED],
+        bump = feed.bump,
+    )]
+    pub feed: Account<'info, YieldFeed>,
+}
+
+pub fn update_yield_feed_handler(
+    ctx: Context<UpdateYieldFeed>,
+    reporter: Option<Pubkey>,
+    max_staleness_secs: Option<i64>,
+) -> Result<()> {
+    let feed = &mut ctx.accounts.feed;
+
+    if let Some(reporter) = reporter {
+        feed.reporter = reporter;
+    }
+    if let Some(max_staleness_secs) = max_staleness_secs {
+        require!(max_staleness_secs > 0, VaultError::InvalidFeedConfig);
+        feed.max_staleness_secs = max_staleness_secs;
+    }
+
+    emit!(YieldFeedConfigUpdatedEvent {
+        reporter: feed.reporter,
+        max_staleness_secs: feed.max_staleness_secs,
+    });
+
+    msg!("Yield feed config updated");
+    Ok(())
+}
+
+// ── Reporter publishes APY and TVL for registered protocols ──
+
+#[derive(Accounts)]
+pub struct ReportYields<'info> {
+    pub reporter: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [FEED_SEED],
+        bump = feed.bump,
+        has_one = reporter @ VaultError::Unauthorized,
+    )]
+    pub feed: Account<'info, YieldFeed>,
+
+    #[account(
+        seeds = [REGISTRY_SEED],
+        bump = registry.bump,
+    )]
+    pub registry: Account<'info, ProtocolRegistry>,
+}
+
+pub fn report_yields_handler(ctx: Context<ReportYields>, updates: Vec<YieldUpdate>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let registry = &ctx.accounts.registry;
+    let feed = &mut ctx.accounts.feed;
+
+    for update in &updates {
+        require!(
+            registry.find(update.protocol_id).is_some(),
+            VaultError::ProtocolNotRegistered
+        );
+
+        let report = YieldReport {
+            protocol_id: update.protocol_id,
+            apy_bps: update.apy_bps,
+            tvl: update.tvl,
+            updated_ts: now,
+        };
+        match feed
+            .reports
+            .iter_mut()
+            .find(|r| r.protocol_id == update.protocol_id)
+        {
+            Some(existing) => *existing = report,
+            // Registry ids are unique and capped at MAX_PROTOCOLS, so this always fits
+            None => feed.reports.push(report),
+        }
+
+        emit!(YieldReportedEvent {
+            protocol_id: update.protocol_id,
+            apy_bps: update.apy_bps,
+            tvl: update.tvl,
+            updated_ts: now,
+        });
+    }
+
+    msg!("Reported yields for {} protocols", updates.len());
+    Ok(())
+}
@@ -5,7 +5,10 @@ pub mod instructions;
 pub mod state;
 
 use instructions::*;
-use state::Allocation;
+use state::{
+    AdapterKind, AllocationTarget, BreakerAction, LstRateSource, ProtocolStatus, RiskLimits,
+    YieldUpdate,
+};
 
 declare_id!("HjFqznCR9NYr3mxYYyhYqYLrm3xNiu71EAz5qHARjWrd");
 
@@ -13,24 +16,115 @@ declare_id!("HjFqznCR9NYr3mxYYyhYqYLrm3xNiu71EAz5qHARjWrd");
 pub mod solvault {
     use super::*;
 
-    /// Initialize the vault with allocation targets and fee config
+    /// Initialize the vault with allocation targets, fee config and base asset
     pub fn initialize(
         ctx: Context<Initialize>,
         performance_fee_bps: u16,
         deposit_cap: u64,
-        allocations: Vec<Allocation>,
+        allocations: Vec<AllocationTarget>,
     ) -> Result<()> {
         instructions::initialize::handler(ctx, performance_fee_bps, deposit_cap, allocations)
     }
 
-    /// Deposit SOL into the vault and receive proportional shares
-    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
-        instructions::deposit::handler(ctx, amount)
+    /// Deposit the base asset and receive at least `min_shares_out` shares
+    pub fn deposit(
+        ctx: Context<Deposit>,
+        index: u16,
+        amount: u64,
+        min_shares_out: u64,
+    ) -> Result<()> {
+        instructions::deposit::handler(ctx, index, amount, min_shares_out)
+    }
+
+    /// Deposit on behalf of `beneficiary`, optionally sponsoring their position rent
+    pub fn deposit_for(
+        ctx: Context<DepositFor>,
+        beneficiary: Pubkey,
+        index: u16,
+        amount: u64,
+        min_shares_out: u64,
+        sponsor_rent: bool,
+    ) -> Result<()> {
+        instructions::deposit::deposit_for_handler(
+            ctx,
+            beneficiary,
+            index,
+            amount,
+            min_shares_out,
+            sponsor_rent,
+        )
+    }
+
+    /// Deposit an approved LST, minting shares on its SOL value at the on-chain exchange rate
+    pub fn deposit_lst(
+        ctx: Context<DepositLst>,
+        index: u16,
+        mint: Pubkey,
+        amount: u64,
+        min_shares_out: u64,
+    ) -> Result<()> {
+        instructions::lst::deposit_lst_handler(ctx, index, mint, amount, min_shares_out)
     }
 
-    /// Withdraw SOL by burning vault shares
-    pub fn withdraw(ctx: Context<Withdraw>, shares_to_burn: u64) -> Result<()> {
-        instructions::withdraw::handler(ctx, shares_to_burn)
+    /// Deposit an active stake account delegated to an approved validator
+    pub fn deposit_stake(ctx: Context<DepositStake>, index: u16, min_shares_out: u64) -> Result<()> {
+        instructions::stake_deposit::deposit_stake_handler(ctx, index, min_shares_out)
+    }
+
+    /// Mint an exact number of shares, paying at most `max_lamports`
+    pub fn mint(
+        ctx: Context<Deposit>,
+        index: u16,
+        exact_shares: u64,
+        max_lamports: u64,
+    ) -> Result<()> {
+        instructions::deposit::mint_handler(ctx, index, exact_shares, max_lamports)
+    }
+
+    /// Withdraw the base asset by burning vault shares
+    pub fn withdraw(ctx: Context<Withdraw>, index: u16, shares_to_burn: u64) -> Result<()> {
+        instructions::withdraw::handler(ctx, index, shares_to_burn)
+    }
+
+    /// Burn shares, receiving at least `min_lamports_out` after fees
+    pub fn redeem(
+        ctx: Context<Withdraw>,
+        index: u16,
+        shares: u64,
+        min_lamports_out: u64,
+    ) -> Result<()> {
+        instructions::withdraw::redeem_handler(ctx, index, shares, min_lamports_out)
+    }
+
+    /// Withdraw an exact SOL amount after fees, burning at most `max_shares_burned`
+    pub fn withdraw_exact(
+        ctx: Context<Withdraw>,
+        index: u16,
+        lamports: u64,
+        max_shares_burned: u64,
+    ) -> Result<()> {
+        instructions::withdraw::withdraw_exact_handler(ctx, index, lamports, max_shares_burned)
+    }
+
+    /// Burn shares and receive their value after fees in an approved LST
+    pub fn withdraw_as(
+        ctx: Context<WithdrawAs>,
+        index: u16,
+        mint: Pubkey,
+        shares: u64,
+        min_lst_out: u64,
+    ) -> Result<()> {
+        instructions::in_kind::withdraw_as_handler(ctx, index, mint, shares, min_lst_out)
+    }
+
+    /// Burn shares and receive a pro-rata basket of idle SOL and the LSTs passed
+    /// in remaining accounts
+    pub fn withdraw_in_kind<'info>(
+        ctx: Context<'_, '_, 'info, 'info, WithdrawInKind<'info>>,
+        index: u16,
+        shares: u64,
+    ) -> Result<()> {
+        instructions::in_kind::withdraw_in_kind_handler(ctx, index, shares)
     }
 
     /// Rebalance vault allocations toward target percentages
@@ -38,22 +132,51 @@ pub mod solvault {
         instructions::rebalance::handler(ctx)
     }
 
-    /// Update the target allocation percentages
+    /// Plan a rebalance and open a session that moves one allocation per transaction
+    pub fn begin_rebalance(ctx: Context<BeginRebalance>) -> Result<()> {
+        instructions::rebalance_session::begin_rebalance_handler(ctx)
+    }
+
+    /// Move the allocation at `allocation_index` as planned by `begin_rebalance`
+    pub fn rebalance_step(ctx: Context<RebalanceStep>, allocation_index: u8) -> Result<()> {
+        instructions::rebalance_session::rebalance_step_handler(ctx, allocation_index)
+    }
+
+    /// Check every allocation has moved and close the rebalance session
+    pub fn finish_rebalance(ctx: Context<FinishRebalance>) -> Result<()> {
+        instructions::rebalance_session::finish_rebalance_handler(ctx)
+    }
+
+    /// Close the open rebalance session unfinished (authority, or anyone once it lapses)
+    pub fn cancel_rebalance(ctx: Context<CancelRebalance>) -> Result<()> {
+        instructions::rebalance_session::cancel_rebalance_handler(ctx)
+    }
+
+    /// Move to new allocation targets, ramping linearly between the timestamps
     pub fn update_allocations(
         ctx: Context<UpdateAllocations>,
-        new_allocations: Vec<Allocation>,
+        new_allocations: Vec<AllocationTarget>,
+        ramp_start_ts: i64,
+        ramp_end_ts: i64,
     ) -> Result<()> {
-        instructions::update_allocations::handler(ctx, new_allocations)
+        instructions::update_allocations::handler(ctx, new_allocations, ramp_start_ts, ramp_end_ts)
     }
 
-    /// Update vault configuration (fee, cap, pause state)
+    /// Update vault configuration (fee, cap, pause state, withdrawal delay)
     pub fn update_config(
         ctx: Context<UpdateConfig>,
         new_fee_bps: Option<u16>,
         new_deposit_cap: Option<u64>,
         new_paused: Option<bool>,
+        new_withdraw_delay_slots: Option<u64>,
     ) -> Result<()> {
-        instructions::update_config::handler(ctx, new_fee_bps, new_deposit_cap, new_paused)
+        instructions::update_config::handler(
+            ctx,
+            new_fee_bps,
+            new_deposit_cap,
+            new_paused,
+            new_withdraw_delay_slots,
+        )
     }
 
     /// Collect accrued performance fees to authority
@@ -61,9 +184,178 @@ pub mod solvault {
         instructions::collect_fees::handler(ctx)
     }
 
+    /// Approve an LST for deposits, priced from its stake pool or Marinade state
+    pub fn register_lst(
+        ctx: Context<RegisterLst>,
+        protocol_id: u8,
+        rate_source: LstRateSource,
+    ) -> Result<()> {
+        instructions::lst::register_lst_handler(ctx, protocol_id, rate_source)
+    }
+
+    /// Pause or resume deposits of an approved LST
+    pub fn update_lst(ctx: Context<UpdateLst>, is_enabled: bool) -> Result<()> {
+        instructions::lst::update_lst_handler(ctx, is_enabled)
+    }
+
+    /// Approve a validator for stake account deposits
+    pub fn register_validator(ctx: Context<RegisterValidator>, protocol_id: u8) -> Result<()> {
+        instructions::stake_deposit::register_validator_handler(ctx, protocol_id)
+    }
+
+    /// Pause or resume stake deposits for an approved validator
+    pub fn update_validator(ctx: Context<UpdateValidator>, is_enabled: bool) -> Result<()> {
+        instructions::stake_deposit::update_validator_handler(ctx, is_enabled)
+    }
+
+    /// Create the protocol registry, managed by the signer
+    pub fn initialize_registry(ctx: Context<InitializeRegistry>) -> Result<()> {
+        instructions::registry::initialize_registry_handler(ctx)
+    }
+
+    /// List a protocol allocations may target
+    pub fn register_protocol(
+        ctx: Context<RegisterProtocol>,
+        protocol_id: u8,
+        program_id: Pubkey,
+        adapter_kind: AdapterKind,
+        state_account: Pubkey,
+        risk_tier: u8,
+        params_len: u8,
+    ) -> Result<()> {
+        instructions::registry::register_protocol_handler(
+            ctx,
+            protocol_id,
+            program_id,
+            adapter_kind,
+            state_account,
+            risk_tier,
+            params_len,
+        )
+    }
+
+    /// Change a registered protocol's status or risk tier
+    pub fn update_protocol(
+        ctx: Context<UpdateProtocol>,
+        protocol_id: u8,
+        status: Option<ProtocolStatus>,
+        risk_tier: Option<u8>,
+    ) -> Result<()> {
+        instructions::registry::update_protocol_handler(ctx, protocol_id, status, risk_tier)
+    }
+
+    /// Set a protocol's exposure caps and target bounds (registry authority only)
+    pub fn set_risk_limits(
+        ctx: Context<SetRiskLimits>,
+        protocol_id: u8,
+        limits: RiskLimits,
+    ) -> Result<()> {
+        instructions::registry::set_risk_limits_handler(ctx, protocol_id, limits)
+    }
+
+    /// Commit an allocation change that `rebalance` applies once `activation_ts` passes
+    pub fn schedule_allocations(
+        ctx: Context<ScheduleAllocations>,
+        targets: Vec<AllocationTarget>,
+        activation_ts: i64,
+        ramp_seconds: i64,
+    ) -> Result<()> {
+        instructions::schedule::schedule_allocations_handler(
+            ctx,
+            targets,
+            activation_ts,
+            ramp_seconds,
+        )
+    }
+
+    /// Drop the pending allocation change
+    pub fn cancel_allocation_schedule(ctx: Context<CancelAllocationSchedule>) -> Result<()> {
+        instructions::schedule::cancel_allocation_schedule_handler(ctx)
+    }
+
+    /// Create the yield feed and name the key allowed to publish to it
+    pub fn initialize_yield_feed(
+        ctx: Context<InitializeYieldFeed>,
+        reporter: Pubkey,
+        max_staleness_secs: i64,
+    ) -> Result<()> {
+        instructions::yield_feed::initialize_yield_feed_handler(ctx, reporter, max_staleness_secs)
+    }
+
+    /// Rotate the yield reporter or change the staleness threshold
+    pub fn update_yield_feed(
+        ctx: Context<UpdateYieldFeed>,
+        reporter: Option<Pubkey>,
+        max_staleness_secs: Option<i64>,
+    ) -> Result<()> {
+        instructions::yield_feed::update_yield_feed_handler(ctx, reporter, max_staleness_secs)
+    }
+
+    /// Publish per-protocol APY and TVL (reporter only)
+    pub fn report_yields(ctx: Context<ReportYields>, updates: Vec<YieldUpdate>) -> Result<()> {
+        instructions::yield_feed::report_yields_handler(ctx, updates)
+    }
+
+    /// Let rebalance derive targets from reported yields, within a turnover limit
+    pub fn set_auto_allocate(
+        ctx: Context<SetAutoAllocate>,
+        enabled: bool,
+        max_turnover_bps: u16,
+    ) -> Result<()> {
+        instructions::optimizer::set_auto_allocate_handler(ctx, enabled, max_turnover_bps)
+    }
+
+    /// Set or clear the key allowed to withdraw to the position owner
+    pub fn set_delegate(ctx: Context<SetDelegate>, index: u16, delegate: Pubkey) -> Result<()> {
+        instructions::position_transfer::set_delegate_handler(ctx, index, delegate)
+    }
+
+    /// Withdraw as the position's delegate; proceeds go to the owner
+    pub fn delegate_withdraw(
+        ctx: Context<DelegateWithdraw>,
+        index: u16,
+        shares: u64,
+        min_lamports_out: u64,
+    ) -> Result<()> {
+        instructions::position_transfer::delegate_withdraw_handler(
+            ctx,
+            index,
+            shares,
+            min_lamports_out,
+        )
+    }
+
+    /// Move shares and their cost basis to another position (own or another wallet's)
+    pub fn transfer_position_shares(
+        ctx: Context<TransferPositionShares>,
+        from_index: u16,
+        to: Pubkey,
+        to_index: u16,
+        shares: u64,
+    ) -> Result<()> {
+        instructions::position_transfer::transfer_position_shares_handler(
+            ctx, from_index, to, to_index, shares,
+        )
+    }
+
     /// Close an empty user position and reclaim rent
-    pub fn close_position(ctx: Context<ClosePosition>) -> Result<()> {
-        instructions::close_position::handler(ctx)
+    pub fn close_position(ctx: Context<ClosePosition>, index: u16) -> Result<()> {
+        instructions::close_position::handler(ctx, index)
+    }
+
+    /// Upgrade the vault account to the current layout version in place
+    pub fn migrate_vault(ctx: Context<MigrateVault>) -> Result<()> {
+        instructions::migrate::migrate_vault_handler(ctx)
+    }
+
+    /// Upgrade a position account to the current layout version in place
+    pub fn migrate_position(ctx: Context<MigratePosition>, index: u16) -> Result<()> {
+        instructions::migrate::migrate_position_handler(ctx, index)
+    }
+
+    /// Move a legacy index-less position into sub-position `index`
+    pub fn migrate_legacy_position(ctx: Context<MigrateLegacyPosition>, index: u16) -> Result<()> {
+        instructions::migrate_legacy_position::handler(ctx, index)
     }
 
     /// Propose a new authority (step 1 of two-step transfer)
@@ -75,4 +367,81 @@ pub mod solvault {
     pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
         instructions::transfer_authority::accept_handler(ctx)
     }
+
+    /// Enable or disable epoch-batched async mode and set the epoch keeper
+    pub fn update_epoch_config(
+        ctx: Context<UpdateEpochConfig>,
+        async_mode: Option<bool>,
+        keeper: Option<Pubkey>,
+    ) -> Result<()> {
+        instructions::epoch_batch::update_epoch_config_handler(ctx, async_mode, keeper)
+    }
+
+    /// Queue a SOL deposit into the current epoch batch
+    pub fn request_deposit(ctx: Context<RequestDeposit>, amount: u64) -> Result<()> {
+        instructions::epoch_batch::request_deposit_handler(ctx, amount)
+    }
+
+    /// Escrow shares for redemption in the current epoch batch
+    pub fn request_withdraw(ctx: Context<RequestWithdraw>, index: u16, shares: u64) -> Result<()> {
+        instructions::epoch_batch::request_withdraw_handler(ctx, index, shares)
+    }
+
+    /// Settle the current epoch batch at a single share price (authority or keeper)
+    pub fn close_epoch(ctx: Context<CloseEpoch>) -> Result<()> {
+        instructions::epoch_batch::close_epoch_handler(ctx)
+    }
+
+    /// Claim shares and/or SOL from a settled epoch batch
+    pub fn claim_batch(ctx: Context<ClaimBatch>, epoch: u64, index: u16) -> Result<()> {
+        instructions::epoch_batch::claim_handler(ctx, epoch, index)
+    }
+
+    /// Preview shares minted for a deposit (read-only, via return data)
+    pub fn preview_deposit(ctx: Context<PreviewVault>, amount: u64) -> Result<u64> {
+        instructions::preview::preview_deposit_handler(ctx, amount)
+    }
+
+    /// Preview SOL returned and fee charged for burning shares (read-only, via return data)
+    pub fn preview_withdraw(ctx: Context<PreviewPosition>, shares: u64) -> Result<WithdrawPreview> {
+        instructions::preview::preview_withdraw_handler(ctx, shares)
+    }
+
+    /// Current share price (read-only, via return data)
+    pub fn get_share_price(ctx: Context<PreviewVault>) -> Result<SharePrice> {
+        instructions::preview::get_share_price_handler(ctx)
+    }
+
+    /// Current value of a position net of fees (read-only, via return data)
+    pub fn get_position_value(ctx: Context<PreviewPosition>) -> Result<PositionValue> {
+        instructions::preview::get_position_value_handler(ctx)
+    }
+
+    /// Per-allocation moves `rebalance` would make now, from allocation `first` on (read-only, via return data)
+    pub fn preview_rebalance(
+        ctx: Context<PreviewRebalance>,
+        first: u8,
+    ) -> Result<RebalancePreview> {
+        instructions::preview::preview_rebalance_handler(ctx, first)
+    }
+
+    /// Limit instant withdrawals to `outflow_limit_bps` of NAV per rolling window (0 = no limit)
+    pub fn set_circuit_breaker(
+        ctx: Context<UpdateCircuitBreaker>,
+        outflow_limit_bps: u16,
+        outflow_window_secs: i64,
+        action: BreakerAction,
+    ) -> Result<()> {
+        instructions::circuit_breaker::set_circuit_breaker_handler(
+            ctx,
+            outflow_limit_bps,
+            outflow_window_secs,
+            action,
+        )
+    }
+
+    /// Clear a tripped circuit breaker and its outflow window
+    pub fn reset_circuit_breaker(ctx: Context<UpdateCircuitBreaker>) -> Result<()> {
+        instructions::circuit_breaker::reset_circuit_breaker_handler(ctx)
+    }
 }
@@ -1,8 +1,37 @@
 use anchor_lang::prelude::*;
+use crate::errors::VaultError;
 
-pub const MAX_ALLOCATIONS: usize = 10;
+/// Allocation slots in the zero-copy vault account
+pub const MAX_ALLOCATIONS: usize = 64;
+/// Protocols the registry can list
+pub const MAX_PROTOCOLS: usize = 64;
+/// Size of the opaque per-allocation params blob
+pub const ALLOCATION_PARAMS_LEN: usize = 16;
 pub const VAULT_SEED: &[u8] = b"vault";
 pub const POSITION_SEED: &[u8] = b"position";
+pub const BATCH_SEED: &[u8] = b"batch";
+pub const TICKET_SEED: &[u8] = b"ticket";
+pub const LST_SEED: &[u8] = b"lst";
+pub const VALIDATOR_SEED: &[u8] = b"validator";
+pub const REGISTRY_SEED: &[u8] = b"registry";
+pub const SCHEDULE_SEED: &[u8] = b"schedule";
+pub const FEED_SEED: &[u8] = b"yield_feed";
+pub const REBALANCE_SESSION_SEED: &[u8] = b"rebalance_session";
+
+/// SPL stake pool program (jitoSOL and most other LSTs)
+pub const SPL_STAKE_POOL_PROGRAM_ID: Pubkey = pubkey!("SPoo1Ku8WFXoNDMHPsrGSTSG1Y47rzgn41SLUNakuHy");
+/// Marinade liquid staking program (mSOL)
+pub const MARINADE_PROGRAM_ID: Pubkey = pubkey!("MarBmsSgKXdrN1egZf5sqe1TMai9K1rChYNDJgjq7aD");
+/// Native vote program (owner of validator vote accounts)
+pub const VOTE_PROGRAM_ID: Pubkey = pubkey!("Vote111111111111111111111111111111111111111");
+
+/// Current `Vault` layout version. v1 (unversioned) and v2 are borsh layouts;
+/// v3 is the zero-copy layout with percentage targets, v4 moves targets to bps
+/// and v5 widens allocations with a target account and params.
+/// `migrate_vault` converts older vaults in place.
+pub const VAULT_VERSION: u8 = 5;
+/// Current `UserPosition` layout version; unversioned (v1) positions read as 0.
+pub const POSITION_VERSION: u8 = 2;
 
 /// Minimum deposit to prevent share price manipulation (0.01 SOL)
 pub const MIN_DEPOSIT_LAMPORTS: u64 = 10_000_000;
@@ -10,49 +39,701 @@ pub const MIN_DEPOSIT_LAMPORTS: u64 = 10_000_000;
 pub const MAX_FEE_BPS: u16 = 3000;
 /// Basis points denominator
 pub const BPS_DENOMINATOR: u64 = 10_000;
+/// Longest allowed allocation ramp (365 days)
+pub const MAX_RAMP_SECONDS: i64 = 365 * 24 * 60 * 60;
+/// APY haircut per risk tier when auto-allocating, in bps
+pub const RISK_TIER_PENALTY_BPS: u32 = 100;
+/// Slots a rebalance session may stay open (~1 hour) before it lapses: withdrawals
+/// may then take the funds it was moving, and anyone may cancel it
+pub const MAX_REBALANCE_SLOTS: u64 = 9_000;
+/// Smallest redemption, in bps of NAV, whose request may trip the circuit breaker.
+/// Smaller ones over the outflow limit wait for the window to drain instead, so no
+/// small holder can pause or queue the whole vault.
+pub const MIN_BREAKER_TRIP_BPS: u64 = 100;
+/// Most allocations one `preview_rebalance` call returns (return data is capped at 1 KiB)
+pub const MAX_PREVIEW_ALLOCATIONS: usize = 32;
 /// Initial share ratio: 1 SOL = 1_000_000_000 shares (high precision to prevent inflation attacks)
 pub const SHARES_PER_SOL: u64 = 1_000_000_000;
+/// Virtual shares and assets added to both sides of every share-price calculation.
+/// Equal, so an empty vault still mints 1 share per lamport; large enough that a
+/// donation to an almost empty vault mostly accrues to the virtual shares.
+pub const VIRTUAL_SHARES: u64 = 1_000_000;
+pub const VIRTUAL_ASSETS: u64 = 1_000_000;
+/// Shares minted at initialisation to no position, backed 1:1 by base asset from the authority
+pub const DEAD_SHARES: u64 = 1_000;
 
-#[account]
-#[derive(InitSpace)]
+/// Vault state, loaded zero-copy so deposits and withdrawals don't pay to
+/// (de)serialize the allocation table. Fields are ordered to avoid padding.
+#[account(zero_copy)]
 pub struct Vault {
     /// Admin authority who can update config and rebalance
     pub authority: Pubkey,
+    /// Pending authority for two-step transfer (default = no pending transfer)
+    pub pending_authority: Pubkey,
+    /// Keeper allowed to close epochs alongside the authority (default = none)
+    pub keeper: Pubkey,
+    /// Mint of the base asset (default = native SOL held as lamports)
+    pub base_mint: Pubkey,
+    /// Token account owned by the vault PDA holding the base asset (default for native)
+    pub vault_token_account: Pubkey,
+    /// SPL Token or Token-2022 program owning the base mint (default for native)
+    pub token_program: Pubkey,
     /// Total SOL deposited into the vault (lamports)
     pub total_deposited: u64,
     /// Total shares issued to depositors
     pub total_shares: u64,
-    /// Performance fee in basis points (e.g. 500 = 5%)
-    pub performance_fee_bps: u16,
     /// Maximum total deposit allowed (lamports), 0 = unlimited
     pub deposit_cap: u64,
-    /// Whether deposits and withdrawals are paused
-    pub is_paused: bool,
-    /// Number of active allocations
-    pub num_allocations: u8,
-    /// Target allocations across protocols
-    #[max_len(MAX_ALLOCATIONS)]
-    pub allocations: Vec<Allocation>,
-    /// Bump for the vault PDA
-    pub bump: u8,
     /// Accumulated fees available for collection (lamports)
     pub accrued_fees: u64,
     /// Timestamp of last rebalance
     pub last_rebalance_ts: i64,
     /// Total number of depositors (for stats)
     pub depositor_count: u64,
-    /// Pending authority for two-step transfer (default = no pending transfer)
-    pub pending_authority: Pubkey,
+    /// Epoch currently accepting batch requests
+    pub current_epoch: u64,
+    /// Lamports held for deposit requests not yet settled
+    pub pending_deposits: u64,
+    /// Lamports owed to settled redemption requests not yet claimed
+    pub pending_redemptions: u64,
+    /// SOL value of LST held from `deposit_lst`, booked at deposit-time exchange rates
+    pub lst_value: u64,
+    /// Lamports held in stake accounts from `deposit_stake` (delegated + rent reserve)
+    pub stake_value: u64,
+    /// Performance fee in basis points (e.g. 500 = 5%)
+    pub performance_fee_bps: u16,
+    /// Whether deposits are paused (0 = no, 1 = yes)
+    pub is_paused: u8,
+    /// Whether deposits and withdrawals go through epoch batches (0 = no, 1 = yes)
+    pub async_mode: u8,
+    /// Number of active allocations; only `allocations[..num_allocations]` is in use
+    pub num_allocations: u8,
+    /// Bump for the vault PDA
+    pub bump: u8,
+    /// Layout version (see `VAULT_VERSION`)
+    pub version: u8,
+    /// Whether `rebalance` derives targets from the yield feed (0 = no, 1 = yes)
+    pub auto_allocate: u8,
+    /// Target allocations across protocols
+    pub allocations: [Allocation; MAX_ALLOCATIONS],
+    /// Allocations move from `start_bps` to `target_bps` between these timestamps
+    pub ramp_start_ts: i64,
+    pub ramp_end_ts: i64,
+    /// Most of the vault auto-allocation may move per rebalance, in bps (0 = unlimited)
+    pub max_turnover_bps: u16,
+    /// Whether an incremental rebalance session is open (0 = no, 1 = yes)
+    pub rebalancing: u8,
+    pub padding: [u8; 5],
+    /// NAV the open rebalance session planned its moves at; withdrawals can't go below it
+    pub rebalance_nav: u64,
+    /// Slots newly minted shares must wait before they can be withdrawn (0 = next slot)
+    pub withdraw_delay_slots: u64,
+    /// Window over which recent outflows drain away for the circuit breaker
+    pub outflow_window_secs: i64,
+    /// When `recent_outflow` was last updated
+    pub outflow_updated_ts: i64,
+    /// Lamports withdrawn recently, as of `outflow_updated_ts`
+    pub recent_outflow: u64,
+    /// Most of NAV that may leave within the window, in bps (0 = no limit)
+    pub outflow_limit_bps: u16,
+    /// What tripping the breaker does (see `BreakerAction`)
+    pub breaker_action: u8,
+    /// Whether the circuit breaker has tripped (0 = no, 1 = yes)
+    pub breaker_tripped: u8,
+    pub breaker_padding: [u8; 4],
+    /// Slot after which an open rebalance session lapses
+    pub rebalance_deadline_slot: u64,
+    /// Zeroed space for future fields, so they can be added without a realloc
+    pub reserved: [u8; 176],
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+impl Vault {
+    /// Account size including the discriminator
+    pub const SPACE: usize = 8 + std::mem::size_of::<Vault>();
+
+    /// Whether the base asset is native SOL held directly in the vault PDA
+    pub fn is_native(&self) -> bool {
+        self.base_mint == Pubkey::default()
+    }
+
+    /// Allocations in use
+    pub fn active_allocations(&self) -> &[Allocation] {
+        &self.allocations[..self.num_allocations as usize]
+    }
+
+    /// Allocations in use, mutably
+    pub fn active_allocations_mut(&mut self) -> &mut [Allocation] {
+        &mut self.allocations[..self.num_allocations as usize]
+    }
+
+    /// Progress through the allocation ramp at `now`, as `(elapsed, duration)`
+    pub fn ramp_progress(&self, now: i64) -> (u64, u64) {
+        if now >= self.ramp_end_ts {
+            (1, 1)
+        } else if now <= self.ramp_start_ts {
+            (0, 1)
+        } else {
+            (
+                (now - self.ramp_start_ts) as u64,
+                (self.ramp_end_ts - self.ramp_start_ts) as u64,
+            )
+        }
+    }
+
+    /// Split `total` across the active allocations by their effective targets at
+    /// `now`, interpolated linearly from `start_bps` to `target_bps` over the ramp
+    pub fn effective_targets(&self, total: u64, now: i64) -> Result<[u64; MAX_ALLOCATIONS]> {
+        let (elapsed, duration) = self.ramp_progress(now);
+        let allocations = self.active_allocations();
+        apportion(
+            total,
+            allocations.len(),
+            BPS_DENOMINATOR as u128 * duration as u128,
+            |i| {
+                allocations[i].start_bps as u128 * (duration - elapsed) as u128
+                    + allocations[i].target_bps as u128 * elapsed as u128
+            },
+        )
+    }
+
+    /// Switch to `targets`, ramping from today's effective targets between
+    /// `ramp_start_ts` and `ramp_end_ts`; a ramp ending by `now` switches instantly.
+    /// Allocations that stay keep their slot and `current_amount`, new ones are
+    /// appended, and dropped ones ramp down to zero before `rebalance` prunes them.
+    pub fn set_allocations(
+        &mut self,
+        targets: &[AllocationTarget],
+        ramp_start_ts: i64,
+        ramp_end_ts: i64,
+        now: i64,
+    ) -> Result<()> {
+        let ramping = ramp_end_ts > now;
+        let start = self.effective_targets(BPS_DENOMINATOR, now)?;
+
+        let mut placed = [false; MAX_ALLOCATIONS];
+        let mut len = 0;
+        for (i, &start_bps) in start[..self.num_allocations as usize].iter().enumerate() {
+            let mut slot = self.allocations[i];
+            slot.start_bps = start_bps as u16;
+            match targets
+                .iter()
+                .position(|t| t.protocol_id == slot.protocol_id && t.target == slot.target)
+            {
+                Some(j) => {
+                    placed[j] = true;
+                    slot.set_target(&targets[j]);
+                }
+                None if ramping && slot.start_bps > 0 => slot.target_bps = 0,
+                None => continue,
+            }
+            self.allocations[len] = slot;
+            len += 1;
+        }
+
+        let added = placed[..targets.len()].iter().filter(|p| !**p).count();
+        require!(
+            len + added <= MAX_ALLOCATIONS,
+            VaultError::TooManyAllocations
+        );
+        for (target, _) in targets.iter().zip(&placed).filter(|(_, p)| !**p) {
+            let mut slot = Allocation {
+                protocol_id: target.protocol_id,
+                target: target.target,
+                ..Allocation::default()
+            };
+            slot.set_target(target);
+            self.allocations[len] = slot;
+            len += 1;
+        }
+
+        for slot in &mut self.allocations[len..] {
+            *slot = Allocation::default();
+        }
+        self.num_allocations = len as u8;
+        self.ramp_start_ts = ramp_start_ts;
+        self.ramp_end_ts = ramp_end_ts;
+        Ok(())
+    }
+
+    /// Whether a rebalance session is open and has not yet lapsed
+    pub fn rebalance_live(&self) -> Result<bool> {
+        Ok(self.rebalancing != 0 && Clock::get()?.slot <= self.rebalance_deadline_slot)
+    }
+
+    /// While a rebalance session is live, funds it planned to move can't leave the
+    /// vault; only what was deposited since it began can be withdrawn. `outflow` is
+    /// about to leave on top of what `total_deposited` already reflects.
+    pub fn check_rebalance_liquidity(&self, outflow: u64) -> Result<()> {
+        require!(
+            !self.rebalance_live()?
+                || self.total_deposited.saturating_sub(outflow) >= self.rebalance_nav,
+            VaultError::RebalanceInProgress
+        );
+        Ok(())
+    }
+
+    /// Shares of `position` still inside the withdrawal delay at `slot`
+    pub fn locked_shares(&self, position: &UserPosition, slot: u64) -> u64 {
+        if slot > position.last_deposit_slot.saturating_add(self.withdraw_delay_slots) {
+            0
+        } else {
+            position.locked_shares
+        }
+    }
+
+    /// Hold back `shares` just credited to `position` until the delay after this slot
+    pub fn lock_deposit(&self, position: &mut UserPosition, shares: u64) -> Result<()> {
+        let slot = Clock::get()?.slot;
+        position.locked_shares = self
+            .locked_shares(position, slot)
+            .checked_add(shares)
+            .ok_or(VaultError::MathOverflow)?;
+        position.last_deposit_slot = slot;
+        Ok(())
+    }
+
+    /// Shares minted in the slot of a position's last deposit, or within
+    /// `withdraw_delay_slots` after it, can't be burned yet, so they can't be
+    /// flashed around a harvest. Older shares stay free, so a deposit made for
+    /// someone else can't lock their existing balance.
+    pub fn check_withdraw_delay(&self, position: &UserPosition, shares: u64) -> Result<()> {
+        let slot = Clock::get()?.slot;
+        let unlocked = position
+            .shares
+            .saturating_sub(self.locked_shares(position, slot));
+        require!(shares <= unlocked, VaultError::WithdrawalTooSoon);
+        Ok(())
+    }
+
+    /// Recent outflow at `now`: each withdrawal drains away linearly over the window,
+    /// so the breaker tracks a rolling window without storing every withdrawal
+    pub fn recent_outflow_at(&self, now: i64) -> u64 {
+        let elapsed = now.saturating_sub(self.outflow_updated_ts);
+        if self.outflow_window_secs <= 0 || elapsed >= self.outflow_window_secs {
+            return 0;
+        }
+        let remaining = (self.outflow_window_secs - elapsed) as u128;
+        (self.recent_outflow as u128 * remaining / self.outflow_window_secs as u128) as u64
+    }
+
+    /// Whether a tripped circuit breaker has paused rebalancing and fee collection
+    pub fn breaker_paused(&self) -> bool {
+        self.breaker_tripped != 0 && self.breaker_action == BreakerAction::Pause as u8
+    }
+
+    /// Add `amount` to the recent outflow at `now`
+    pub fn record_outflow(&mut self, amount: u64, now: i64) {
+        self.recent_outflow = self.recent_outflow_at(now).saturating_add(amount);
+        self.outflow_updated_ts = now;
+    }
+
+    /// Drop allocations that have ramped down to nothing once the ramp is over
+    pub fn prune_allocations(&mut self, now: i64) {
+        if now < self.ramp_end_ts {
+            return;
+        }
+        let mut len = 0;
+        for i in 0..self.num_allocations as usize {
+            let slot = self.allocations[i];
+            if slot.target_bps > 0 || slot.current_amount > 0 {
+                self.allocations[len] = slot;
+                len += 1;
+            }
+        }
+        for slot in &mut self.allocations[len..] {
+            *slot = Allocation::default();
+        }
+        self.num_allocations = len as u8;
+    }
+}
+
+/// Split `total` across `n` slots in proportion to `weight(i) / denominator`.
+/// Each share is floored, then the leftover units go out one each by largest
+/// remainder (ties to the earlier slot) until the floored total of all weights is
+/// reached, so the split is deterministic and, when the weights sum to
+/// `denominator`, adds up to exactly `total`.
+pub fn apportion(
+    total: u64,
+    n: usize,
+    denominator: u128,
+    weight: impl Fn(usize) -> u128,
+) -> Result<[u64; MAX_ALLOCATIONS]> {
+    let mut amounts = [0u64; MAX_ALLOCATIONS];
+    let mut remainders = [0u64; MAX_ALLOCATIONS];
+    let mut allocated: u64 = 0;
+    let mut weight_sum: u128 = 0;
+    for i in 0..n {
+        weight_sum = weight_sum
+            .checked_add(weight(i))
+            .ok_or(VaultError::MathOverflow)?;
+        let scaled = (total as u128)
+            .checked_mul(weight(i))
+            .ok_or(VaultError::MathOverflow)?;
+        amounts[i] = (scaled / denominator)
+            .try_into()
+            .map_err(|_| VaultError::MathOverflow)?;
+        remainders[i] = (scaled % denominator) as u64;
+        allocated = allocated
+            .checked_add(amounts[i])
+            .ok_or(VaultError::MathOverflow)?;
+    }
+
+    // Fewer than `n` units are left over; weights short of the denominator leave the rest idle
+    let whole: u64 = ((total as u128)
+        .checked_mul(weight_sum)
+        .ok_or(VaultError::MathOverflow)?
+        / denominator)
+        .try_into()
+        .map_err(|_| VaultError::MathOverflow)?;
+    let dust = whole.saturating_sub(allocated);
+    let mut order: [u8; MAX_ALLOCATIONS] = std::array::from_fn(|i| i as u8);
+    let order = &mut order[..n];
+    order.sort_unstable_by_key(|&i| (std::cmp::Reverse(remainders[i as usize]), i));
+    for &i in order.iter().take(dust as usize) {
+        amounts[i as usize] += 1;
+    }
+    Ok(amounts)
+}
+
+#[zero_copy]
+#[derive(Default)]
 pub struct Allocation {
-    /// Protocol identifier (0=Jito, 1=Marinade, 2=Sanctum, 3=Marginfi, 4=Kamino)
-    pub protocol_id: u8,
-    /// Target percentage (0-100)
-    pub target_pct: u8,
     /// Current amount allocated to this protocol (lamports)
     pub current_amount: u64,
+    /// Account the allocation targets (stake pool, vote account, lending bank);
+    /// default = the protocol's registered state account
+    pub target: Pubkey,
+    /// Protocol-specific settings (max slippage, reserve index, ...)
+    pub params: [u8; ALLOCATION_PARAMS_LEN],
+    /// Target share of the vault in bps (0-10_000)
+    pub target_bps: u16,
+    /// Protocol identifier (0=Jito, 1=Marinade, 2=Sanctum, 3=Marginfi, 4=Kamino)
+    pub protocol_id: u8,
+    /// Bytes of `params` in use
+    pub params_len: u8,
+    /// Target at the start of the current ramp, in bps
+    pub start_bps: u16,
+    pub padding: [u8; 2],
+}
+
+impl Allocation {
+    fn set_target(&mut self, target: &AllocationTarget) {
+        self.target_bps = target.target_bps;
+        self.params = [0u8; ALLOCATION_PARAMS_LEN];
+        self.params[..target.params.len()].copy_from_slice(&target.params);
+        self.params_len = target.params.len() as u8;
+    }
+}
+
+/// Target for one allocation, as passed to `initialize` and `update_allocations`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct AllocationTarget {
+    /// Protocol identifier (0=Jito, 1=Marinade, 2=Sanctum, 3=Marginfi, 4=Kamino)
+    pub protocol_id: u8,
+    /// Target share of the vault in bps; targets sum to 10_000
+    pub target_bps: u16,
+    /// Account to allocate to; default = the protocol's registered state account
+    pub target: Pubkey,
+    /// Protocol-specific settings, at most the registry entry's `params_len` bytes
+    #[max_len(ALLOCATION_PARAMS_LEN)]
+    pub params: Vec<u8>,
+}
+
+/// How the vault integrates with a registered protocol
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum AdapterKind {
+    /// SPL stake pool (Jito, Sanctum LSTs)
+    StakePool,
+    /// Marinade liquid staking
+    Marinade,
+    /// Native stake accounts delegated to approved validators
+    NativeStake,
+    /// Lending market (marginfi)
+    Lending,
+    /// Concentrated liquidity vault (Kamino)
+    Liquidity,
+}
+
+/// What the outflow circuit breaker does when it trips
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerAction {
+    /// Switch to async mode, so every withdrawal queues for epoch settlement
+    Queue,
+    /// Pause deposits, rebalances and fee collection; withdrawals over the
+    /// limit still queue
+    Pause,
+}
+
+/// Lifecycle of a registered protocol
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum ProtocolStatus {
+    /// May be targeted by allocations
+    Active,
+    /// Being wound down; no new allocation targets
+    Deprecated,
+    /// Emergency stop; no new allocation targets
+    Frozen,
+}
+
+/// Exposure limits for one protocol, checked against allocation targets and
+/// against actual exposure when rebalancing
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct RiskLimits {
+    /// Largest share of NAV the protocol may hold, in bps
+    pub max_exposure_bps: u16,
+    /// Largest amount the protocol may hold (lamports), 0 = unlimited
+    pub max_lamports: u64,
+    /// Smallest allowed allocation target, in bps
+    pub min_target_bps: u16,
+    /// Largest allowed allocation target, in bps
+    pub max_target_bps: u16,
+}
+
+impl RiskLimits {
+    /// No limits beyond the target summing to 100%
+    pub const UNLIMITED: RiskLimits = RiskLimits {
+        max_exposure_bps: BPS_DENOMINATOR as u16,
+        max_lamports: 0,
+        min_target_bps: 0,
+        max_target_bps: BPS_DENOMINATOR as u16,
+    };
+
+    /// Most the protocol may hold out of `nav`
+    pub fn exposure_cap(&self, nav: u64) -> u64 {
+        let nav_cap =
+            (nav as u128 * self.max_exposure_bps as u128 / BPS_DENOMINATOR as u128) as u64;
+        if self.max_lamports > 0 {
+            nav_cap.min(self.max_lamports)
+        } else {
+            nav_cap
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct ProtocolEntry {
+    /// Identifier referenced by `Allocation.protocol_id`
+    pub protocol_id: u8,
+    /// Program the adapter talks to
+    pub program_id: Pubkey,
+    /// Integration used to move funds in and out
+    pub adapter_kind: AdapterKind,
+    /// Protocol state account (stake pool, Marinade state, lending group, ...)
+    pub state_account: Pubkey,
+    pub status: ProtocolStatus,
+    /// Risk tier assigned by the authority (lower = safer)
+    pub risk_tier: u8,
+    pub limits: RiskLimits,
+    /// Largest params blob allocations to this protocol may carry
+    pub params_len: u8,
+}
+
+/// Protocols the vault may allocate to, managed by the registry authority
+#[account]
+#[derive(InitSpace)]
+pub struct ProtocolRegistry {
+    /// Key allowed to register and update protocols
+    pub authority: Pubkey,
+    #[max_len(MAX_PROTOCOLS)]
+    pub protocols: Vec<ProtocolEntry>,
+    /// Bump for this PDA
+    pub bump: u8,
+}
+
+impl ProtocolRegistry {
+    pub fn find(&self, protocol_id: u8) -> Option<&ProtocolEntry> {
+        self.protocols.iter().find(|p| p.protocol_id == protocol_id)
+    }
+}
+
+/// Allocation change committed ahead of time; `rebalance` applies it once
+/// `activation_ts` passes and closes the account. At most one is pending.
+#[account]
+#[derive(InitSpace)]
+pub struct AllocationSchedule {
+    /// Targets to switch to
+    #[max_len(MAX_ALLOCATIONS)]
+    pub targets: Vec<AllocationTarget>,
+    /// Earliest time `rebalance` applies the change
+    pub activation_ts: i64,
+    /// Length of the ramp that starts at activation (0 = switch at once)
+    pub ramp_seconds: i64,
+    /// When the change was scheduled
+    pub scheduled_ts: i64,
+    /// Bump for this PDA
+    pub bump: u8,
+}
+
+/// An incremental rebalance opened by `begin_rebalance`. Each allocation moves in
+/// its own `rebalance_step`, and `finish_rebalance` checks the result and closes it.
+#[account]
+#[derive(InitSpace)]
+pub struct RebalanceSession {
+    /// NAV the moves were planned at
+    pub total_deposited: u64,
+    /// Lamports each active allocation moves by, indexed by slot
+    #[max_len(MAX_ALLOCATIONS)]
+    pub deltas: Vec<i64>,
+    /// Bit `i` is set once allocation `i` has moved
+    pub completed: u64,
+    /// Whether the targets were derived by the optimiser
+    pub auto_allocated: bool,
+    /// When the session began
+    pub started_ts: i64,
+    /// Bump for this PDA
+    pub bump: u8,
+}
+
+impl RebalanceSession {
+    /// Whether every planned move has been made
+    pub fn is_complete(&self) -> bool {
+        self.completed.count_ones() as usize == self.deltas.len()
+    }
+}
+
+/// Where an approved LST's SOL exchange rate is read from
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum LstRateSource {
+    /// SPL stake pool state: `total_lamports / pool_token_supply`
+    SplStakePool,
+    /// Marinade state: `msol_price / 2^32`
+    Marinade,
+}
+
+/// One protocol's figures, as passed to `report_yields`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct YieldUpdate {
+    pub protocol_id: u8,
+    pub apy_bps: u32,
+    pub tvl: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct YieldReport {
+    pub protocol_id: u8,
+    /// Annualised yield in bps
+    pub apy_bps: u32,
+    /// Total value locked in the protocol (lamports)
+    pub tvl: u64,
+    /// When the reporter last published this protocol
+    pub updated_ts: i64,
+}
+
+/// Per-protocol APY and TVL published by the reporter, the single on-chain
+/// source for strategies and dashboards
+#[account]
+#[derive(InitSpace)]
+pub struct YieldFeed {
+    /// Key allowed to publish reports
+    pub reporter: Pubkey,
+    /// Reports older than this (seconds) are refused
+    pub max_staleness_secs: i64,
+    #[max_len(MAX_PROTOCOLS)]
+    pub reports: Vec<YieldReport>,
+    /// Bump for this PDA
+    pub bump: u8,
+}
+
+impl YieldFeed {
+    /// Report for `protocol_id`, refusing missing or stale data
+    pub fn fresh_report(&self, protocol_id: u8, now: i64) -> Result<&YieldReport> {
+        let report = self
+            .reports
+            .iter()
+            .find(|r| r.protocol_id == protocol_id)
+            .ok_or(VaultError::StaleYieldData)?;
+        require!(
+            now.saturating_sub(report.updated_ts) <= self.max_staleness_secs,
+            VaultError::StaleYieldData
+        );
+        Ok(report)
+    }
+}
+
+/// An LST approved for `deposit_lst`, booked into the allocation with `protocol_id`
+#[account]
+#[derive(InitSpace)]
+pub struct LstConfig {
+    /// LST mint
+    pub mint: Pubkey,
+    /// Program whose state account prices the LST
+    pub rate_source: LstRateSource,
+    /// Stake pool or Marinade state account read for the exchange rate
+    pub rate_state: Pubkey,
+    /// Token account owned by the vault PDA that custodies deposited LST
+    pub vault_lst_account: Pubkey,
+    /// Token program owning the LST mint
+    pub token_program: Pubkey,
+    /// Allocation credited with the SOL value of deposits
+    pub protocol_id: u8,
+    /// LST tokens held from deposits
+    pub lst_balance: u64,
+    /// SOL value of `lst_balance` at deposit-time exchange rates, this LST's part
+    /// of the vault's `lst_value`
+    pub booked_value: u64,
+    /// Whether new deposits of this LST are accepted
+    pub is_enabled: bool,
+    /// Bump for this PDA
+    pub bump: u8,
+}
+
+/// A validator whose active stake accounts are accepted by `deposit_stake`
+#[account]
+#[derive(InitSpace)]
+pub struct ValidatorConfig {
+    /// Validator vote account stake must be delegated to
+    pub vote_account: Pubkey,
+    /// Allocation credited with the value of deposited stake
+    pub protocol_id: u8,
+    /// Lamports deposited as stake accounts delegated to this validator
+    pub stake_lamports: u64,
+    /// Number of stake accounts deposited
+    pub stake_accounts: u64,
+    /// Whether new stake deposits are accepted
+    pub is_enabled: bool,
+    /// Bump for this PDA
+    pub bump: u8,
+}
+
+/// Requests accumulated during one epoch, settled together at a single share price.
+/// Each claim takes its ticket off the totals; the claim that empties the batch closes it.
+#[account]
+#[derive(InitSpace)]
+pub struct EpochBatch {
+    /// Epoch this batch belongs to
+    pub epoch: u64,
+    /// Total lamports requested for deposit (once settled, not yet claimed)
+    pub deposit_lamports: u64,
+    /// Total shares requested for redemption (once settled, not yet claimed)
+    pub redeem_shares: u64,
+    /// Shares minted for the deposit side at settlement, less those claimed
+    pub shares_minted: u64,
+    /// Lamports set aside for the redemption side at settlement, less those claimed
+    pub lamports_owed: u64,
+    /// Whether the epoch has been closed and settled
+    pub is_settled: bool,
+    /// Timestamp of settlement
+    pub settled_ts: i64,
+    /// Bump for this PDA
+    pub bump: u8,
+}
+
+/// A user's requests within one epoch batch
+#[account]
+#[derive(InitSpace)]
+pub struct BatchTicket {
+    /// Owner of this ticket
+    pub owner: Pubkey,
+    /// Epoch the requests were made in
+    pub epoch: u64,
+    /// Lamports requested for deposit
+    pub deposit_lamports: u64,
+    /// Shares escrowed for redemption
+    pub redeem_shares: u64,
+    /// Cost basis carried over with the escrowed shares (for fee calculation)
+    pub redeem_cost_basis: u64,
+    /// Bump for this PDA
+    pub bump: u8,
 }
 
 // ─── Events ──────────────────────────────────────────────────
@@ -60,15 +741,29 @@ pub struct Allocation {
 #[event]
 pub struct DepositEvent {
     pub user: Pubkey,
+    pub index: u16,
     pub amount: u64,
     pub shares_minted: u64,
     pub total_deposited: u64,
     pub total_shares: u64,
 }
 
+#[event]
+pub struct DepositForEvent {
+    pub payer: Pubkey,
+    pub beneficiary: Pubkey,
+    pub index: u16,
+    pub amount: u64,
+    pub shares_minted: u64,
+    pub rent_sponsored: bool,
+    pub total_deposited: u64,
+    pub total_shares: u64,
+}
+
 #[event]
 pub struct WithdrawEvent {
     pub user: Pubkey,
+    pub index: u16,
     pub shares_burned: u64,
     pub amount_returned: u64,
     pub fee_charged: u64,
@@ -81,6 +776,10 @@ pub struct RebalanceEvent {
     pub timestamp: i64,
     pub total_deposited: u64,
     pub num_allocations: u8,
+    /// Whether the targets were derived by the optimiser
+    pub auto_allocated: bool,
+    /// Target of each active allocation, in bps
+    pub weights: Vec<u16>,
 }
 
 #[event]
@@ -88,6 +787,7 @@ pub struct ConfigUpdatedEvent {
     pub fee_bps: Option<u16>,
     pub deposit_cap: Option<u64>,
     pub paused: Option<bool>,
+    pub withdraw_delay_slots: Option<u64>,
 }
 
 #[event]
@@ -111,6 +811,245 @@ pub struct AuthorityAcceptedEvent {
 #[event]
 pub struct PositionClosedEvent {
     pub user: Pubkey,
+    pub index: u16,
+}
+
+#[event]
+pub struct EpochConfigUpdatedEvent {
+    pub async_mode: Option<bool>,
+    pub keeper: Option<Pubkey>,
+}
+
+#[event]
+pub struct BatchRequestEvent {
+    pub user: Pubkey,
+    pub epoch: u64,
+    pub deposit_lamports: u64,
+    pub redeem_shares: u64,
+}
+
+#[event]
+pub struct EpochSettledEvent {
+    pub epoch: u64,
+    pub deposit_lamports: u64,
+    pub shares_minted: u64,
+    pub redeem_shares: u64,
+    pub lamports_owed: u64,
+    pub total_deposited: u64,
+    pub total_shares: u64,
+}
+
+#[event]
+pub struct BatchClaimedEvent {
+    pub user: Pubkey,
+    pub epoch: u64,
+    pub shares_received: u64,
+    pub amount_returned: u64,
+    pub fee_charged: u64,
+}
+
+#[event]
+pub struct LstConfigUpdatedEvent {
+    pub mint: Pubkey,
+    pub protocol_id: u8,
+    pub is_enabled: bool,
+}
+
+#[event]
+pub struct LstDepositEvent {
+    pub user: Pubkey,
+    pub index: u16,
+    pub mint: Pubkey,
+    pub lst_amount: u64,
+    pub sol_value: u64,
+    pub shares_minted: u64,
+    pub total_deposited: u64,
+    pub total_shares: u64,
+}
+
+#[event]
+pub struct ValidatorConfigUpdatedEvent {
+    pub vote_account: Pubkey,
+    pub protocol_id: u8,
+    pub is_enabled: bool,
+}
+
+#[event]
+pub struct StakeDepositEvent {
+    pub user: Pubkey,
+    pub index: u16,
+    pub stake_account: Pubkey,
+    pub vote_account: Pubkey,
+    pub sol_value: u64,
+    pub shares_minted: u64,
+    pub total_deposited: u64,
+    pub total_shares: u64,
+}
+
+#[event]
+pub struct LstWithdrawEvent {
+    pub user: Pubkey,
+    pub index: u16,
+    pub mint: Pubkey,
+    pub shares_burned: u64,
+    pub sol_value: u64,
+    pub lst_amount: u64,
+    pub fee_charged: u64,
+    pub total_deposited: u64,
+    pub total_shares: u64,
+}
+
+#[event]
+pub struct InKindWithdrawEvent {
+    pub user: Pubkey,
+    pub index: u16,
+    pub shares_burned: u64,
+    pub lamports_returned: u64,
+    pub lst_mints_paid: u8,
+    pub fee_charged: u64,
+    pub total_deposited: u64,
+    pub total_shares: u64,
+}
+
+#[event]
+pub struct PositionMigratedEvent {
+    pub owner: Pubkey,
+    pub index: u16,
+    pub shares: u64,
+}
+
+#[event]
+pub struct AccountMigratedEvent {
+    pub account: Pubkey,
+    pub from_version: u8,
+    pub to_version: u8,
+}
+
+#[event]
+pub struct ProtocolUpdatedEvent {
+    pub protocol_id: u8,
+    pub program_id: Pubkey,
+    pub adapter_kind: AdapterKind,
+    pub status: ProtocolStatus,
+    pub risk_tier: u8,
+}
+
+#[event]
+pub struct RiskLimitsUpdatedEvent {
+    pub protocol_id: u8,
+    pub limits: RiskLimits,
+}
+
+/// Actual exposure found above a protocol's cap; `rebalance` trims it back
+#[event]
+pub struct RiskLimitBreachedEvent {
+    pub protocol_id: u8,
+    pub exposure: u64,
+    pub cap: u64,
+    pub nav: u64,
+}
+
+#[event]
+pub struct CircuitBreakerConfigUpdatedEvent {
+    pub outflow_limit_bps: u16,
+    pub outflow_window_secs: i64,
+    pub action: BreakerAction,
+}
+
+#[event]
+pub struct CircuitBreakerTrippedEvent {
+    /// Outflow within the window before the withdrawal that tripped it
+    pub recent_outflow: u64,
+    /// Withdrawal that would have exceeded the limit
+    pub attempted: u64,
+    pub limit: u64,
+    pub action: BreakerAction,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CircuitBreakerResetEvent {
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RebalanceBegunEvent {
+    pub total_deposited: u64,
+    pub num_allocations: u8,
+    pub auto_allocated: bool,
+}
+
+#[event]
+pub struct RebalanceStepEvent {
+    pub allocation_index: u8,
+    pub protocol_id: u8,
+    pub amount_before: u64,
+    pub amount_after: u64,
+}
+
+#[event]
+pub struct RebalanceCancelledEvent {
+    /// Allocations moved before the session was cancelled
+    pub steps_completed: u8,
+    pub num_allocations: u8,
+    /// Whether the session had lapsed
+    pub lapsed: bool,
+}
+
+#[event]
+pub struct AllocationScheduledEvent {
+    pub activation_ts: i64,
+    pub ramp_seconds: i64,
+    pub num_allocations: u8,
+}
+
+#[event]
+pub struct AllocationScheduleCancelledEvent {
+    pub activation_ts: i64,
+}
+
+#[event]
+pub struct AllocationScheduleActivatedEvent {
+    pub activation_ts: i64,
+    pub activated_ts: i64,
+    pub num_allocations: u8,
+}
+
+#[event]
+pub struct YieldFeedConfigUpdatedEvent {
+    pub reporter: Pubkey,
+    pub max_staleness_secs: i64,
+}
+
+#[event]
+pub struct YieldReportedEvent {
+    pub protocol_id: u8,
+    pub apy_bps: u32,
+    pub tvl: u64,
+    pub updated_ts: i64,
+}
+
+#[event]
+pub struct AutoAllocateUpdatedEvent {
+    pub enabled: bool,
+    pub max_turnover_bps: u16,
+}
+
+#[event]
+pub struct DelegateUpdatedEvent {
+    pub owner: Pubkey,
+    pub index: u16,
+    pub delegate: Pubkey,
+}
+
+#[event]
+pub struct PositionTransferEvent {
+    pub from: Pubkey,
+    pub from_index: u16,
+    pub to: Pubkey,
+    pub to_index: u16,
+    pub shares: u64,
+    pub cost_basis: u64,
 }
 
 #[account]
@@ -126,4 +1065,79 @@ pub struct UserPosition {
     pub last_deposit_ts: i64,
     /// Bump for this PDA
     pub bump: u8,
+    /// Key allowed to withdraw to the owner on their behalf (default = none)
+    pub delegate: Pubkey,
+    /// Sub-position index within the owner's wallet (part of the PDA seeds)
+    pub index: u16,
+    /// Layout version (see `POSITION_VERSION`)
+    pub version: u8,
+    /// Slot of the last deposit; withdrawals wait out `withdraw_delay_slots` after it
+    pub last_deposit_slot: u64,
+    /// Shares minted since the delay after an earlier deposit ran out; only these
+    /// are held back until `last_deposit_slot + withdraw_delay_slots`
+    pub locked_shares: u64,
+    /// Zeroed space for future fields, so they can be added without a realloc
+    pub reserved: [u8; 16],
+}
+
+/// Layout of positions created before sub-position indexes, derived from
+/// `[POSITION_SEED, owner]`. Only read by `migrate_legacy_position`.
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyUserPosition {
+    pub owner: Pubkey,
+    pub shares: u64,
+    pub deposited_amount: u64,
+    pub last_deposit_ts: i64,
+    pub bump: u8,
+}
+
+/// Borsh layout of vaults before the zero-copy conversion (v1 and v2). v1
+/// accounts end at `stake_value` and read `version` as 0 once zero-extended.
+/// Only read by `migrate_vault`.
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyVault {
+    pub authority: Pubkey,
+    pub total_deposited: u64,
+    pub total_shares: u64,
+    pub performance_fee_bps: u16,
+    pub deposit_cap: u64,
+    pub is_paused: bool,
+    pub num_allocations: u8,
+    pub allocations: Vec<LegacyAllocation>,
+    pub bump: u8,
+    pub accrued_fees: u64,
+    pub last_rebalance_ts: i64,
+    pub depositor_count: u64,
+    pub pending_authority: Pubkey,
+    pub async_mode: bool,
+    pub keeper: Pubkey,
+    pub current_epoch: u64,
+    pub pending_deposits: u64,
+    pub pending_redemptions: u64,
+    pub base_mint: Pubkey,
+    pub vault_token_account: Pubkey,
+    pub token_program: Pubkey,
+    pub lst_value: u64,
+    pub stake_value: u64,
+    pub version: u8,
+}
+
+/// Borsh allocation entry of `LegacyVault`
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyAllocation {
+    pub protocol_id: u8,
+    pub target_pct: u8,
+    pub current_amount: u64,
+}
+
+/// Allocation slot of the v3/v4 zero-copy `Vault`. Only read by `migrate_vault`.
+#[zero_copy]
+pub struct LegacyZeroCopyAllocation {
+    pub current_amount: u64,
+    pub protocol_id: u8,
+    /// v3 target percentage
+    pub target_pct: u8,
+    /// v4 target in bps
+    pub target_bps: u16,
+    pub padding: [u8; 4],
 }
@@ -2,10 +2,19 @@ import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { Solvault } from "../target/types/solvault";
 import { expect } from "chai";
+import { Clock, startAnchor } from "solana-bankrun";
+import { BankrunProvider } from "anchor-bankrun";
+import v1PositionOwner from "./fixtures/v1-position-owner.json";
+import legacyVaultAuthority from "./fixtures/legacy-vault-authority.json";
+import v1Vault from "./fixtures/v1-vault.json";
+import v2Vault from "./fixtures/v2-vault.json";
+import v3Vault from "./fixtures/v3-vault.json";
+import v4Vault from "./fixtures/v4-vault.json";
 import {
   Keypair,
   PublicKey,
   SystemProgram,
+  Transaction,
   LAMPORTS_PER_SOL,
 } from "@solana/web3.js";
 
@@ -17,16 +26,32 @@ describe("solvault", () => {
   const authority = provider.wallet;
 
   let vaultPda: PublicKey;
+  let registryPda: PublicKey;
 
   // SHARES_PER_SOL = 1_000_000_000 (1e9)
   const SHARES_PER_SOL = 1_000_000_000;
+  // Offset added to both sides of every share price, and shares locked at initialisation
+  const VIRTUAL_OFFSET = 1_000_000;
+  const DEAD_SHARES = 1_000;
+
+  // Share math as the program does it: totals plus the virtual offset
+  const virtualShares = (vault) => vault.totalShares.add(new anchor.BN(VIRTUAL_OFFSET));
+  const virtualAssets = (vault) => vault.totalDeposited.add(new anchor.BN(VIRTUAL_OFFSET));
+
+  // update_allocations ramp bounds that switch targets immediately
+  const noRamp = new anchor.BN(0);
+
+  // Allocation to the protocol's registered state account, without params
+  function allocation(protocolId: number, targetBps: number) {
+    return { protocolId, targetBps, target: PublicKey.default, params: Buffer.alloc(0) };
+  }
 
   const defaultAllocations = [
-    { protocolId: 0, targetPct: 35, currentAmount: new anchor.BN(0) }, // Jito
-    { protocolId: 1, targetPct: 25, currentAmount: new anchor.BN(0) }, // Marinade
-    { protocolId: 2, targetPct: 20, currentAmount: new anchor.BN(0) }, // Sanctum
-    { protocolId: 3, targetPct: 12, currentAmount: new anchor.BN(0) }, // marginfi
-    { protocolId: 4, targetPct: 8, currentAmount: new anchor.BN(0) },  // Kamino
+    allocation(0, 3500), // Jito
+    allocation(1, 2500), // Marinade
+    allocation(2, 2000), // Sanctum
+    allocation(3, 1200), // marginfi
+    allocation(4, 800),  // Kamino
   ];
 
   before(async () => {
@@ -34,11 +59,33 @@ describe("solvault", () => {
       [Buffer.from("vault")],
       program.programId
     );
+    [registryPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("registry")],
+      program.programId
+    );
   });
 
-  function getPositionPda(user: PublicKey): [PublicKey, number] {
+  function getPositionPda(user: PublicKey, index = 0): [PublicKey, number] {
+    return PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("position"),
+        user.toBuffer(),
+        new anchor.BN(index).toArrayLike(Buffer, "le", 2),
+      ],
+      program.programId
+    );
+  }
+
+  function getBatchPda(epoch: anchor.BN): [PublicKey, number] {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("batch"), epoch.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+  }
+
+  function getTicketPda(epoch: anchor.BN, user: PublicKey): [PublicKey, number] {
     return PublicKey.findProgramAddressSync(
-      [Buffer.from("position"), user.toBuffer()],
+      [Buffer.from("ticket"), epoch.toArrayLike(Buffer, "le", 8), user.toBuffer()],
       program.programId
     );
   }
@@ -51,10 +98,296 @@ describe("solvault", () => {
     await provider.connection.confirmTransaction(sig);
   }
 
+  // ─────────────────────────────────────────────────
+  // BANKRUN BANKS
+  // ─────────────────────────────────────────────────
+  // Flows that need hand-built accounts (mints, token accounts, stake pool or
+  // Marinade state) or control of the clock run in their own bankrun bank, with
+  // a registry and vault of their own at the usual PDAs.
+  const TOKEN_PROGRAM_ID = new PublicKey("TokenkegQfeZyiNwAJbNbGNPDzAsLv6y3AgFAtfnpS");
+  const TOKEN_2022_PROGRAM_ID = new PublicKey("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+  const STAKE_POOL_PROGRAM_ID = new PublicKey("SPoo1Ku8WFXoNDMHPsrGSTSG1Y47rzgn41SLUNakuHy");
+  const MARINADE_PROGRAM_ID = new PublicKey("MarBmsSgKXdrN1egZf5sqe1TMai9K1rChYNDJgjq7aD");
+  // Marinade prices mSOL in lamports scaled by 2^32
+  const MARINADE_PRICE_DENOMINATOR = new anchor.BN(2).pow(new anchor.BN(32));
+
+  type BankAccount = {
+    address: PublicKey;
+    info: { lamports: number; data: Buffer; owner: PublicKey; executable: boolean };
+  };
+
+  function bankAccount(
+    address: PublicKey,
+    owner: PublicKey,
+    data: Buffer,
+    lamports = LAMPORTS_PER_SOL
+  ): BankAccount {
+    return { address, info: { lamports, data, owner, executable: false } };
+  }
+
+  function u64(value: anchor.BN | number): Buffer {
+    return new anchor.BN(value).toArrayLike(Buffer, "le", 8);
+  }
+
+  // SPL Token mint: no mint or freeze authority
+  function mintData(decimals: number, supply: anchor.BN | number): Buffer {
+    const data = Buffer.alloc(82);
+    u64(supply).copy(data, 36);
+    data[44] = decimals;
+    data[45] = 1; // is_initialized
+    return data;
+  }
+
+  // SPL Token account, initialized and not frozen
+  function tokenAccountData(
+    mint: PublicKey,
+    owner: PublicKey,
+    amount: anchor.BN | number
+  ): Buffer {
+    const data = Buffer.alloc(165);
+    mint.toBuffer().copy(data, 0);
+    owner.toBuffer().copy(data, 32);
+    u64(amount).copy(data, 64);
+    data[108] = 1; // AccountState::Initialized
+    return data;
+  }
+
+  // SPL stake pool state: account_type, pool_mint and the pool totals
+  function stakePoolData(
+    mint: PublicKey,
+    totalLamports: anchor.BN | number,
+    poolTokenSupply: anchor.BN | number,
+    lastUpdateEpoch: anchor.BN | number
+  ): Buffer {
+    const data = Buffer.alloc(300);
+    data[0] = 1; // AccountType::StakePool
+    mint.toBuffer().copy(data, 162);
+    u64(totalLamports).copy(data, 258);
+    u64(poolTokenSupply).copy(data, 266);
+    u64(lastUpdateEpoch).copy(data, 274);
+    return data;
+  }
+
+  // Marinade state: msol_mint after the discriminator, msol_price at 512
+  function marinadeStateData(mint: PublicKey, msolPrice: anchor.BN): Buffer {
+    const data = Buffer.alloc(600);
+    mint.toBuffer().copy(data, 8);
+    u64(msolPrice).copy(data, 512);
+    return data;
+  }
+
+  async function tokenBalance(context, address: PublicKey): Promise<anchor.BN> {
+    const account = await context.banksClient.getAccount(address);
+    return new anchor.BN(Buffer.from(account.data).subarray(64, 72), "le");
+  }
+
+  // Shares minted in a slot can't be withdrawn in it; move the bank on one slot
+  async function nextSlot(context): Promise<void> {
+    const clock = await context.banksClient.getClock();
+    context.warpToSlot(clock.slot + BigInt(1));
+  }
+
+  // Build and sign `method` for a bank; the bank's payer pays the fee
+  async function bankTransaction(context, method, signers: Keypair[]): Promise<Transaction> {
+    const tx: Transaction = await method.transaction();
+    [tx.recentBlockhash] = await context.banksClient.getLatestBlockhash();
+    tx.feePayer = context.payer.publicKey;
+    tx.sign(context.payer, ...signers);
+    return tx;
+  }
+
+  // Send `method` expecting it to fail; returns the program logs to check the error in
+  async function bankFailure(context, method, signers: Keypair[] = []): Promise<string> {
+    const tx = await bankTransaction(context, method, signers);
+    const { result, meta } = await context.banksClient.tryProcessTransaction(tx);
+    expect(result, "transaction should have failed").to.not.equal(null);
+    return meta.logMessages.join("\n");
+  }
+
+  // Start a bank holding `accounts`, with the default protocols registered.
+  // The bank's payer is the registry and vault authority.
+  async function startBank(accounts: BankAccount[] = []) {
+    const context = await startAnchor(".", [], accounts);
+    const bank = new Program<Solvault>(program.idl, new BankrunProvider(context));
+    const kinds = [
+      { stakePool: {} },
+      { marinade: {} },
+      { stakePool: {} },
+      { lending: {} },
+      { liquidity: {} },
+    ];
+
+    await bank.methods
+      .initializeRegistry()
+      .accounts({
+        authority: context.payer.publicKey,
+        registry: registryPda,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    for (const [id, kind] of kinds.entries()) {
+      await bank.methods
+        .registerProtocol(id, Keypair.generate().publicKey, kind, Keypair.generate().publicKey, 1, 0)
+        .accounts({ authority: context.payer.publicKey, registry: registryPda })
+        .rpc();
+    }
+    return { context, bank };
+  }
+
+  // Native SOL vault in a bank, with the default allocations
+  async function initializeBankVault(context, bank: Program<Solvault>, feeBps = 500) {
+    await bank.methods
+      .initialize(feeBps, new anchor.BN(0), defaultAllocations)
+      .accounts({
+        authority: context.payer.publicKey,
+        vault: vaultPda,
+        registry: registryPda,
+        baseMint: null,
+        vaultTokenAccount: null,
+        authorityTokenAccount: null,
+        tokenProgram: null,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+  }
+
+  // A funded wallet for a bank
+  function bankWallet(lamports = 10 * LAMPORTS_PER_SOL): [Keypair, BankAccount] {
+    const wallet = Keypair.generate();
+    return [wallet, bankAccount(wallet.publicKey, SystemProgram.programId, Buffer.alloc(0), lamports)];
+  }
+
+  // An LST for a bank: its mint, rate account and vault-owned custody account.
+  // `rateData` builds the stake pool or Marinade state pricing the mint.
+  function lstFixture(rateOwner: PublicKey, rateData: (mint: PublicKey) => Buffer) {
+    const mint = Keypair.generate().publicKey;
+    const rateState = Keypair.generate().publicKey;
+    const vaultLstAccount = Keypair.generate().publicKey;
+    const [lstConfig] = PublicKey.findProgramAddressSync(
+      [Buffer.from("lst"), mint.toBuffer()],
+      program.programId
+    );
+    return {
+      mint,
+      rateState,
+      vaultLstAccount,
+      lstConfig,
+      accounts: [
+        bankAccount(mint, TOKEN_PROGRAM_ID, mintData(9, 1_000 * LAMPORTS_PER_SOL)),
+        bankAccount(rateState, rateOwner, rateData(mint)),
+        bankAccount(vaultLstAccount, TOKEN_PROGRAM_ID, tokenAccountData(mint, vaultPda, 0)),
+      ],
+      // A token account of this LST held by `owner`
+      holder(owner: PublicKey, amount: number): [PublicKey, BankAccount] {
+        const address = Keypair.generate().publicKey;
+        return [
+          address,
+          bankAccount(address, TOKEN_PROGRAM_ID, tokenAccountData(mint, owner, amount)),
+        ];
+      },
+    };
+  }
+
+  async function registerBankLst(context, bank: Program<Solvault>, lst, protocolId: number, rateSource) {
+    await bank.methods
+      .registerLst(protocolId, rateSource)
+      .accounts({
+        authority: context.payer.publicKey,
+        vault: vaultPda,
+        mint: lst.mint,
+        rateState: lst.rateState,
+        vaultLstAccount: lst.vaultLstAccount,
+        lstConfig: lst.lstConfig,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+  }
+
+  function lstAccounts(user: Keypair, lst, userLstAccount: PublicKey, index = 0) {
+    const [position] = getPositionPda(user.publicKey, index);
+    return {
+      user: user.publicKey,
+      vault: vaultPda,
+      position,
+      lstConfig: lst.lstConfig,
+      lstMint: lst.mint,
+      rateState: lst.rateState,
+      userLstAccount,
+      vaultLstAccount: lst.vaultLstAccount,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    };
+  }
+
   // ─────────────────────────────────────────────────
   // INITIALIZE
   // ─────────────────────────────────────────────────
   describe("initialize", () => {
+    it("registers the default protocols", async () => {
+      await program.methods
+        .initializeRegistry()
+        .accounts({
+          authority: authority.publicKey,
+          registry: registryPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+
+      const protocols = [
+        { id: 0, kind: { stakePool: {} }, tier: 1 },  // Jito
+        { id: 1, kind: { marinade: {} }, tier: 1 },   // Marinade
+        { id: 2, kind: { stakePool: {} }, tier: 2 },  // Sanctum
+        { id: 3, kind: { lending: {} }, tier: 3 },    // marginfi
+        { id: 4, kind: { liquidity: {} }, tier: 3 },  // Kamino
+      ];
+      for (const p of protocols) {
+        await program.methods
+          .registerProtocol(
+            p.id,
+            Keypair.generate().publicKey,
+            p.kind,
+            Keypair.generate().publicKey,
+            p.tier,
+            0
+          )
+          .accounts({
+            authority: authority.publicKey,
+            registry: registryPda,
+          })
+          .rpc();
+      }
+
+      const registry = await program.account.protocolRegistry.fetch(registryPda);
+      expect(registry.authority.toBase58()).to.equal(authority.publicKey.toBase58());
+      expect(registry.protocols.length).to.equal(5);
+      expect(registry.protocols[3].adapterKind).to.deep.equal({ lending: {} });
+      expect(registry.protocols[3].status).to.deep.equal({ active: {} });
+      expect(registry.protocols[3].riskTier).to.equal(3);
+    });
+
+    it("rejects registering the same protocol twice", async () => {
+      try {
+        await program.methods
+          .registerProtocol(
+            0,
+            Keypair.generate().publicKey,
+            { stakePool: {} },
+            Keypair.generate().publicKey,
+            1,
+            0
+          )
+          .accounts({
+            authority: authority.publicKey,
+            registry: registryPda,
+          })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("DuplicateProtocol");
+      }
+    });
+
     it("initializes the vault with correct parameters", async () => {
       const feeBps = 500; // 5%
       const depositCap = new anchor.BN(1000 * LAMPORTS_PER_SOL);
@@ -64,6 +397,12 @@ describe("solvault", () => {
         .accounts({
           authority: authority.publicKey,
           vault: vaultPda,
+          registry: registryPda,
+          // Native SOL vault: no base mint or token accounts
+          baseMint: null,
+          vaultTokenAccount: null,
+          authorityTokenAccount: null,
+          tokenProgram: null,
           systemProgram: SystemProgram.programId,
         })
         .rpc();
@@ -72,28 +411,121 @@ describe("solvault", () => {
       expect(vault.authority.toBase58()).to.equal(authority.publicKey.toBase58());
       expect(vault.performanceFeeBps).to.equal(feeBps);
       expect(vault.depositCap.toNumber()).to.equal(depositCap.toNumber());
-      expect(vault.totalDeposited.toNumber()).to.equal(0);
-      expect(vault.totalShares.toNumber()).to.equal(0);
-      expect(vault.isPaused).to.equal(false);
-      expect(vault.allocations.length).to.equal(5);
-      expect(vault.allocations[0].targetPct).to.equal(35);
-      expect(vault.allocations[1].targetPct).to.equal(25);
-      expect(vault.allocations[2].targetPct).to.equal(20);
-      expect(vault.allocations[3].targetPct).to.equal(12);
-      expect(vault.allocations[4].targetPct).to.equal(8);
+      // Dead shares, paid for by the authority, are the only ones outstanding
+      expect(vault.totalDeposited.toNumber()).to.equal(DEAD_SHARES);
+      expect(vault.totalShares.toNumber()).to.equal(DEAD_SHARES);
+      expect(vault.isPaused).to.equal(0);
+      // Fixed-size zero-copy table; only the first num_allocations slots are in use
+      expect(vault.allocations.length).to.equal(64);
+      expect(vault.allocations[0].targetBps).to.equal(3500);
+      expect(vault.allocations[1].targetBps).to.equal(2500);
+      expect(vault.allocations[2].targetBps).to.equal(2000);
+      expect(vault.allocations[3].targetBps).to.equal(1200);
+      expect(vault.allocations[4].targetBps).to.equal(800);
       expect(vault.depositorCount.toNumber()).to.equal(0);
       expect(vault.accruedFees.toNumber()).to.equal(0);
       expect(vault.numAllocations).to.equal(5);
       // pending_authority should be default (zeroed)
       expect(vault.pendingAuthority.toBase58()).to.equal(PublicKey.default.toBase58());
+      // base asset is native SOL
+      expect(vault.baseMint.toBase58()).to.equal(PublicKey.default.toBase58());
+      expect(vault.vaultTokenAccount.toBase58()).to.equal(PublicKey.default.toBase58());
+      expect(vault.tokenProgram.toBase58()).to.equal(PublicKey.default.toBase58());
     });
 
-    it("zeroes current_amount even if provided with non-zero values", async () => {
+    it("starts every allocation slot with current_amount zeroed", async () => {
       // This test just verifies the init already happened correctly above
       const vault = await program.account.vault.fetch(vaultPda);
       for (const alloc of vault.allocations) {
         expect(alloc.currentAmount.toNumber()).to.equal(0);
       }
+      expect(vault.allocations[5].protocolId).to.equal(0);
+      expect(vault.allocations[5].targetBps).to.equal(0);
+    });
+
+    it("defeats the first-depositor donation attack", async () => {
+      // Only the dead shares are outstanding. The attacker raises NAV without
+      // minting: they deposit 10 SOL of mSOL, then exit in kind without passing
+      // it, so its booked value stays behind the dead shares
+      const [attacker, attackerWallet] = bankWallet();
+      const [victim, victimWallet] = bankWallet();
+      const msol = lstFixture(MARINADE_PROGRAM_ID, (mint) =>
+        marinadeStateData(mint, MARINADE_PRICE_DENOMINATOR)
+      );
+      const [attackerLst, attackerLstAccount] = msol.holder(
+        attacker.publicKey,
+        10 * LAMPORTS_PER_SOL
+      );
+      const { context, bank } = await startBank([
+        attackerWallet,
+        victimWallet,
+        ...msol.accounts,
+        attackerLstAccount,
+      ]);
+      await initializeBankVault(context, bank);
+      await registerBankLst(context, bank, msol, 1, { marinade: {} });
+
+      const accounts = lstAccounts(attacker, msol, attackerLst);
+      await bank.methods
+        .depositLst(0, msol.mint, new anchor.BN(10 * LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts(accounts)
+        .signers([attacker])
+        .rpc();
+      await nextSlot(context);
+      const attackerPosition = await bank.account.userPosition.fetch(accounts.position);
+      await bank.methods
+        .withdrawInKind(0, attackerPosition.shares)
+        .accounts({
+          user: attacker.publicKey,
+          vault: vaultPda,
+          position: accounts.position,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([attacker])
+        .rpc();
+
+      const vaultBefore = await bank.account.vault.fetch(vaultPda);
+      expect(vaultBefore.totalShares.toNumber()).to.equal(DEAD_SHARES);
+      expect(vaultBefore.totalDeposited.toNumber()).to.be.greaterThan(9.99 * LAMPORTS_PER_SOL);
+
+      // The victim deposits less than one dead share is now worth
+      const deposit = new anchor.BN(0.015 * LAMPORTS_PER_SOL);
+      const [positionPda] = getPositionPda(victim.publicKey);
+      await bank.methods
+        .deposit(0, deposit, new anchor.BN(0))
+        .accounts({
+          user: victim.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([victim])
+        .rpc();
+
+      // Shares are priced with the virtual offset on both sides
+      const expectedShares = deposit
+        .mul(virtualShares(vaultBefore))
+        .div(virtualAssets(vaultBefore));
+      const position = await bank.account.userPosition.fetch(positionPda);
+      expect(position.shares.toString()).to.equal(expectedShares.toString());
+      expect(position.shares.toNumber()).to.be.greaterThan(0);
+
+      // Rounding costs the victim less than one share
+      const vault = await bank.account.vault.fetch(vaultPda);
+      const value = position.shares.mul(virtualAssets(vault)).div(virtualShares(vault));
+      const sharePrice = virtualAssets(vault).div(virtualShares(vault)).addn(1);
+      const loss = deposit.sub(value);
+      expect(loss.lte(sharePrice)).to.equal(true);
+
+      // Without the offset, one dead share is worth 10_000_000 lamports and the
+      // victim's deposit buys a single share, losing a third of it
+      const naiveShares = deposit.mul(vaultBefore.totalShares).div(vaultBefore.totalDeposited);
+      const naiveValue = naiveShares
+        .mul(vaultBefore.totalDeposited.add(deposit))
+        .div(vaultBefore.totalShares.add(naiveShares));
+      const naiveLoss = deposit.sub(naiveValue);
+      expect(naiveShares.toNumber()).to.equal(1);
+      expect(naiveLoss.gt(loss.muln(1000))).to.equal(true);
     });
   });
 
@@ -106,7 +538,7 @@ describe("solvault", () => {
       const [positionPda] = getPositionPda(authority.publicKey);
 
       await program.methods
-        .deposit(depositAmount)
+        .deposit(0, depositAmount, new anchor.BN(0))
         .accounts({
           user: authority.publicKey,
           vault: vaultPda,
@@ -116,9 +548,9 @@ describe("solvault", () => {
         .rpc();
 
       const vault = await program.account.vault.fetch(vaultPda);
-      expect(vault.totalDeposited.toNumber()).to.equal(LAMPORTS_PER_SOL);
+      expect(vault.totalDeposited.toNumber()).to.equal(LAMPORTS_PER_SOL + DEAD_SHARES);
       // First deposit: 1 SOL = 1_000_000_000 shares (SHARES_PER_SOL)
-      expect(vault.totalShares.toNumber()).to.equal(SHARES_PER_SOL);
+      expect(vault.totalShares.toNumber()).to.equal(SHARES_PER_SOL + DEAD_SHARES);
       expect(vault.depositorCount.toNumber()).to.equal(1);
 
       const position = await program.account.userPosition.fetch(positionPda);
@@ -132,7 +564,7 @@ describe("solvault", () => {
       const [positionPda] = getPositionPda(authority.publicKey);
 
       await program.methods
-        .deposit(depositAmount)
+        .deposit(0, depositAmount, new anchor.BN(0))
         .accounts({
           user: authority.publicKey,
           vault: vaultPda,
@@ -142,9 +574,9 @@ describe("solvault", () => {
         .rpc();
 
       const vault = await program.account.vault.fetch(vaultPda);
-      expect(vault.totalDeposited.toNumber()).to.equal(3 * LAMPORTS_PER_SOL);
+      expect(vault.totalDeposited.toNumber()).to.equal(3 * LAMPORTS_PER_SOL + DEAD_SHARES);
       // 2 SOL * 1e9 shares / 1 SOL = 2e9 new shares, total 3e9
-      expect(vault.totalShares.toNumber()).to.equal(3 * SHARES_PER_SOL);
+      expect(vault.totalShares.toNumber()).to.equal(3 * SHARES_PER_SOL + DEAD_SHARES);
 
       const position = await program.account.userPosition.fetch(positionPda);
       expect(position.shares.toNumber()).to.equal(3 * SHARES_PER_SOL);
@@ -159,7 +591,7 @@ describe("solvault", () => {
       const [positionPda] = getPositionPda(user2.publicKey);
 
       await program.methods
-        .deposit(depositAmount)
+        .deposit(0, depositAmount, new anchor.BN(0))
         .accounts({
           user: user2.publicKey,
           vault: vaultPda,
@@ -170,7 +602,7 @@ describe("solvault", () => {
         .rpc();
 
       const vault = await program.account.vault.fetch(vaultPda);
-      expect(vault.totalDeposited.toNumber()).to.equal(4 * LAMPORTS_PER_SOL);
+      expect(vault.totalDeposited.toNumber()).to.equal(4 * LAMPORTS_PER_SOL + DEAD_SHARES);
       expect(vault.depositorCount.toNumber()).to.equal(2);
 
       const position = await program.account.userPosition.fetch(positionPda);
@@ -180,7 +612,7 @@ describe("solvault", () => {
 
       // Withdraw user2 fully to clean up state for later tests
       await program.methods
-        .withdraw(new anchor.BN(SHARES_PER_SOL))
+        .withdraw(0, new anchor.BN(SHARES_PER_SOL))
         .accounts({
           user: user2.publicKey,
           vault: vaultPda,
@@ -197,7 +629,7 @@ describe("solvault", () => {
 
       try {
         await program.methods
-          .deposit(tinyAmount)
+          .deposit(0, tinyAmount, new anchor.BN(0))
           .accounts({
             user: authority.publicKey,
             vault: vaultPda,
@@ -218,7 +650,7 @@ describe("solvault", () => {
 
       try {
         await program.methods
-          .deposit(hugeAmount)
+          .deposit(0, hugeAmount, new anchor.BN(0))
           .accounts({
             user: authority.publicKey,
             vault: vaultPda,
@@ -232,6 +664,19 @@ describe("solvault", () => {
         expect(err).to.exist;
       }
     });
+
+    it("prices deposits with the virtual shares/assets offset", async () => {
+      const amount = new anchor.BN(LAMPORTS_PER_SOL / 3);
+      const vault = await program.account.vault.fetch(vaultPda);
+      const preview = await program.methods
+        .previewDeposit(amount)
+        .accounts({ vault: vaultPda })
+        .view();
+
+      expect(preview.toString()).to.equal(
+        amount.mul(virtualShares(vault)).div(virtualAssets(vault)).toString()
+      );
+    });
   });
 
   // ─────────────────────────────────────────────────
@@ -247,7 +692,7 @@ describe("solvault", () => {
       );
 
       await program.methods
-        .withdraw(sharesToBurn)
+        .withdraw(0, sharesToBurn)
         .accounts({
           user: authority.publicKey,
           vault: vaultPda,
@@ -257,8 +702,8 @@ describe("solvault", () => {
         .rpc();
 
       const vault = await program.account.vault.fetch(vaultPda);
-      expect(vault.totalShares.toNumber()).to.equal(2 * SHARES_PER_SOL);
-      expect(vault.totalDeposited.toNumber()).to.equal(2 * LAMPORTS_PER_SOL);
+      expect(vault.totalShares.toNumber()).to.equal(2 * SHARES_PER_SOL + DEAD_SHARES);
+      expect(vault.totalDeposited.toNumber()).to.equal(2 * LAMPORTS_PER_SOL + DEAD_SHARES);
 
       const position = await program.account.userPosition.fetch(positionPda);
       expect(position.shares.toNumber()).to.equal(2 * SHARES_PER_SOL);
@@ -278,7 +723,7 @@ describe("solvault", () => {
 
       try {
         await program.methods
-          .withdraw(tooMany)
+          .withdraw(0, tooMany)
           .accounts({
             user: authority.publicKey,
             vault: vaultPda,
@@ -297,7 +742,7 @@ describe("solvault", () => {
 
       try {
         await program.methods
-          .withdraw(new anchor.BN(0))
+          .withdraw(0, new anchor.BN(0))
           .accounts({
             user: authority.publicKey,
             vault: vaultPda,
@@ -314,7 +759,7 @@ describe("solvault", () => {
     it("allows withdrawal even when vault is paused (emergency escape)", async () => {
       // Pause
       await program.methods
-        .updateConfig(null, null, true)
+        .updateConfig(null, null, true, null)
         .accounts({ authority: authority.publicKey, vault: vaultPda })
         .rpc();
 
@@ -325,7 +770,7 @@ describe("solvault", () => {
       // Withdraw a small amount — should succeed even though paused
       const withdrawShares = new anchor.BN(100_000);
       await program.methods
-        .withdraw(withdrawShares)
+        .withdraw(0, withdrawShares)
         .accounts({
           user: authority.publicKey,
           vault: vaultPda,
@@ -341,7 +786,7 @@ describe("solvault", () => {
 
       // Unpause for later tests
       await program.methods
-        .updateConfig(null, null, false)
+        .updateConfig(null, null, false, null)
         .accounts({ authority: authority.publicKey, vault: vaultPda })
         .rpc();
     });
@@ -422,13 +867,13 @@ describe("solvault", () => {
   describe("update_allocations", () => {
     it("updates allocation targets", async () => {
       const newAllocations = [
-        { protocolId: 0, targetPct: 50, currentAmount: new anchor.BN(0) },
-        { protocolId: 1, targetPct: 30, currentAmount: new anchor.BN(0) },
-        { protocolId: 2, targetPct: 20, currentAmount: new anchor.BN(0) },
+        allocation(0, 5000),
+        allocation(1, 3000),
+        allocation(2, 2000),
       ];
 
       await program.methods
-        .updateAllocations(newAllocations)
+        .updateAllocations(newAllocations, noRamp, noRamp)
         .accounts({
           authority: authority.publicKey,
           vault: vaultPda,
@@ -436,23 +881,22 @@ describe("solvault", () => {
         .rpc();
 
       const vault = await program.account.vault.fetch(vaultPda);
-      expect(vault.allocations.length).to.equal(3);
-      expect(vault.allocations[0].targetPct).to.equal(50);
-      expect(vault.allocations[1].targetPct).to.equal(30);
-      expect(vault.allocations[2].targetPct).to.equal(20);
+      expect(vault.allocations[0].targetBps).to.equal(5000);
+      expect(vault.allocations[1].targetBps).to.equal(3000);
+      expect(vault.allocations[2].targetBps).to.equal(2000);
       expect(vault.numAllocations).to.equal(3);
     });
 
-    it("rejects allocations not summing to 100", async () => {
+    it("rejects allocations not summing to 10_000 bps", async () => {
       const badAllocations = [
-        { protocolId: 0, targetPct: 50, currentAmount: new anchor.BN(0) },
-        { protocolId: 1, targetPct: 30, currentAmount: new anchor.BN(0) },
-        // total = 80, not 100
+        allocation(0, 5000),
+        allocation(1, 3000),
+        // total = 8_000, not 10_000
       ];
 
       try {
         await program.methods
-          .updateAllocations(badAllocations)
+          .updateAllocations(badAllocations, noRamp, noRamp)
           .accounts({
             authority: authority.publicKey,
             vault: vaultPda,
@@ -466,13 +910,13 @@ describe("solvault", () => {
 
     it("rejects duplicate protocol IDs", async () => {
       const dupeAllocations = [
-        { protocolId: 0, targetPct: 50, currentAmount: new anchor.BN(0) },
-        { protocolId: 0, targetPct: 50, currentAmount: new anchor.BN(0) },
+        allocation(0, 5000),
+        allocation(0, 5000),
       ];
 
       try {
         await program.methods
-          .updateAllocations(dupeAllocations)
+          .updateAllocations(dupeAllocations, noRamp, noRamp)
           .accounts({
             authority: authority.publicKey,
             vault: vaultPda,
@@ -487,7 +931,7 @@ describe("solvault", () => {
     it("rejects empty allocations", async () => {
       try {
         await program.methods
-          .updateAllocations([])
+          .updateAllocations([], noRamp, noRamp)
           .accounts({
             authority: authority.publicKey,
             vault: vaultPda,
@@ -504,13 +948,13 @@ describe("solvault", () => {
       await fundWallet(rando, 1 * LAMPORTS_PER_SOL);
 
       const validAllocations = [
-        { protocolId: 0, targetPct: 60, currentAmount: new anchor.BN(0) },
-        { protocolId: 1, targetPct: 40, currentAmount: new anchor.BN(0) },
+        allocation(0, 6000),
+        allocation(1, 4000),
       ];
 
       try {
         await program.methods
-          .updateAllocations(validAllocations)
+          .updateAllocations(validAllocations, noRamp, noRamp)
           .accounts({
             authority: rando.publicKey,
             vault: vaultPda,
@@ -522,471 +966,3889 @@ describe("solvault", () => {
         expect(err.toString()).to.contain("Unauthorized");
       }
     });
-  });
 
-  // ─────────────────────────────────────────────────
-  // UPDATE CONFIG
-  // ─────────────────────────────────────────────────
-  describe("update_config", () => {
-    it("updates performance fee", async () => {
+    it("rejects protocols missing from the registry", async () => {
+      try {
+        await program.methods
+          .updateAllocations(
+            [
+              allocation(0, 5000),
+              allocation(9, 5000),
+            ],
+            noRamp,
+            noRamp
+          )
+          .accounts({
+            authority: authority.publicKey,
+            vault: vaultPda,
+            registry: registryPda,
+          })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("ProtocolNotRegistered");
+      }
+    });
+
+    it("rejects protocols that are not active", async () => {
       await program.methods
-        .updateConfig(1000, null, null) // 10%
+        .registerProtocol(
+          5,
+          Keypair.generate().publicKey,
+          { nativeStake: {} },
+          Keypair.generate().publicKey,
+          1,
+          0
+        )
         .accounts({
           authority: authority.publicKey,
-          vault: vaultPda,
+          registry: registryPda,
         })
         .rpc();
-
-      const vault = await program.account.vault.fetch(vaultPda);
-      expect(vault.performanceFeeBps).to.equal(1000);
-    });
-
-    it("updates deposit cap", async () => {
-      const newCap = new anchor.BN(500 * LAMPORTS_PER_SOL);
       await program.methods
-        .updateConfig(null, newCap, null)
+        .updateProtocol(5, { frozen: {} }, null)
         .accounts({
           authority: authority.publicKey,
-          vault: vaultPda,
+          registry: registryPda,
         })
         .rpc();
 
-      const vault = await program.account.vault.fetch(vaultPda);
-      expect(vault.depositCap.toNumber()).to.equal(newCap.toNumber());
+      try {
+        await program.methods
+          .updateAllocations(
+            [
+              allocation(0, 5000),
+              allocation(5, 5000),
+            ],
+            noRamp,
+            noRamp
+          )
+          .accounts({
+            authority: authority.publicKey,
+            vault: vaultPda,
+            registry: registryPda,
+          })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("ProtocolNotActive");
+      }
     });
 
-    it("pauses the vault", async () => {
+    it("rejects registry updates from non-authority", async () => {
+      const rando = Keypair.generate();
+      await fundWallet(rando, 1 * LAMPORTS_PER_SOL);
+
+      try {
+        await program.methods
+          .updateProtocol(0, { deprecated: {} }, null)
+          .accounts({
+            authority: rando.publicKey,
+            registry: registryPda,
+          })
+          .signers([rando])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("Unauthorized");
+      }
+    });
+
+    const unlimited = {
+      maxExposureBps: 10_000,
+      maxLamports: new anchor.BN(0),
+      minTargetBps: 0,
+      maxTargetBps: 10_000,
+    };
+
+    it("rejects targets outside a protocol's risk limits", async () => {
       await program.methods
-        .updateConfig(null, null, true)
+        .setRiskLimits(1, { ...unlimited, maxTargetBps: 2_500 })
         .accounts({
           authority: authority.publicKey,
-          vault: vaultPda,
+          registry: registryPda,
         })
         .rpc();
 
-      const vault = await program.account.vault.fetch(vaultPda);
-      expect(vault.isPaused).to.equal(true);
-    });
-
-    it("rejects deposits when paused", async () => {
-      const [positionPda] = getPositionPda(authority.publicKey);
-
       try {
         await program.methods
-          .deposit(new anchor.BN(LAMPORTS_PER_SOL))
+          .updateAllocations(
+            [
+              allocation(0, 5000),
+              allocation(1, 3000),
+              allocation(2, 2000),
+            ],
+            noRamp,
+            noRamp
+          )
           .accounts({
-            user: authority.publicKey,
+            authority: authority.publicKey,
             vault: vaultPda,
-            position: positionPda,
-            systemProgram: SystemProgram.programId,
+            registry: registryPda,
           })
           .rpc();
         expect.fail("Should have thrown");
       } catch (err) {
-        expect(err.toString()).to.contain("VaultPaused");
+        expect(err.toString()).to.contain("RiskLimitBreached");
       }
-    });
 
-    it("unpauses the vault", async () => {
       await program.methods
-        .updateConfig(null, null, false)
+        .setRiskLimits(1, unlimited)
         .accounts({
           authority: authority.publicKey,
-          vault: vaultPda,
+          registry: registryPda,
         })
         .rpc();
-
-      const vault = await program.account.vault.fetch(vaultPda);
-      expect(vault.isPaused).to.equal(false);
     });
 
-    it("rejects fee above maximum (30%)", async () => {
+    it("rejects inconsistent risk limits", async () => {
       try {
         await program.methods
-          .updateConfig(5000, null, null) // 50% > 3000 bps max
+          .setRiskLimits(1, { ...unlimited, minTargetBps: 5_000, maxTargetBps: 4_000 })
           .accounts({
             authority: authority.publicKey,
-            vault: vaultPda,
+            registry: registryPda,
           })
           .rpc();
         expect.fail("Should have thrown");
       } catch (err) {
-        expect(err.toString()).to.contain("FeeTooHigh");
+        expect(err.toString()).to.contain("InvalidRiskLimits");
       }
     });
 
-    it("accepts fee at exactly maximum (30%)", async () => {
+    it("rebalance trims exposure that drifted above a protocol's cap", async () => {
+      await program.methods
+        .rebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+
+      // Tightening the cap below the live 50% exposure mimics a market move
       await program.methods
-        .updateConfig(3000, null, null) // exactly 30%
+        .setRiskLimits(0, { ...unlimited, maxExposureBps: 4_000 })
         .accounts({
           authority: authority.publicKey,
-          vault: vaultPda,
+          registry: registryPda,
         })
         .rpc();
 
+      const signature = await program.methods
+        .rebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc({ commitment: "confirmed" });
+      const tx = await provider.connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      expect(tx.meta.logMessages.join("\n")).to.contain("over its cap");
+
       const vault = await program.account.vault.fetch(vaultPda);
-      expect(vault.performanceFeeBps).to.equal(3000);
+      const total = vault.totalDeposited.toNumber();
+      expect(vault.allocations[0].currentAmount.toNumber()).to.equal(
+        Math.floor(total * 0.4)
+      );
 
-      // Restore to 5%
       await program.methods
-        .updateConfig(500, null, null)
-        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .setRiskLimits(0, unlimited)
+        .accounts({
+          authority: authority.publicKey,
+          registry: registryPda,
+        })
         .rpc();
     });
 
-    it("rejects update_config from non-authority", async () => {
-      const rando = Keypair.generate();
-      await fundWallet(rando, 1 * LAMPORTS_PER_SOL);
+    const poolA = Keypair.generate();
+    const poolB = Keypair.generate();
 
-      try {
-        await program.methods
-          .updateConfig(100, null, null)
-          .accounts({
-            authority: rando.publicKey,
-            vault: vaultPda,
-          })
-          .signers([rando])
-          .rpc();
-        expect.fail("Should have thrown");
-      } catch (err) {
-        expect(err.toString()).to.contain("Unauthorized");
-      }
-    });
+    it("allows two allocations to the same protocol with different targets", async () => {
+      // Protocol 6 stands in for a stake pool program; system-owned accounts
+      // play the role of its pools
+      await program.methods
+        .registerProtocol(
+          6,
+          SystemProgram.programId,
+          { stakePool: {} },
+          Keypair.generate().publicKey,
+          2,
+          8
+        )
+        .accounts({
+          authority: authority.publicKey,
+          registry: registryPda,
+        })
+        .rpc();
+      await fundWallet(poolA, 1 * LAMPORTS_PER_SOL);
+      await fundWallet(poolB, 1 * LAMPORTS_PER_SOL);
 
-    it("can update multiple config fields at once", async () => {
-      const newCap = new anchor.BN(2000 * LAMPORTS_PER_SOL);
       await program.methods
-        .updateConfig(800, newCap, false)
+        .updateAllocations(
+          [
+            allocation(0, 5000),
+            { protocolId: 6, targetBps: 2500, target: poolA.publicKey, params: Buffer.from([1, 2]) },
+            { protocolId: 6, targetBps: 2500, target: poolB.publicKey, params: Buffer.alloc(0) },
+          ],
+          noRamp,
+          noRamp
+        )
         .accounts({
           authority: authority.publicKey,
           vault: vaultPda,
+          registry: registryPda,
         })
+        .remainingAccounts([
+          { pubkey: poolA.publicKey, isWritable: false, isSigner: false },
+          { pubkey: poolB.publicKey, isWritable: false, isSigner: false },
+        ])
         .rpc();
 
       const vault = await program.account.vault.fetch(vaultPda);
-      expect(vault.performanceFeeBps).to.equal(800);
-      expect(vault.depositCap.toNumber()).to.equal(newCap.toNumber());
-      expect(vault.isPaused).to.equal(false);
+      expect(vault.numAllocations).to.equal(3);
+      expect(vault.allocations[1].target.toBase58()).to.equal(poolA.publicKey.toBase58());
+      expect(vault.allocations[1].paramsLen).to.equal(2);
+      expect(vault.allocations[1].params.slice(0, 2)).to.deep.equal([1, 2]);
+      expect(vault.allocations[2].target.toBase58()).to.equal(poolB.publicKey.toBase58());
     });
-  });
 
-  // ─────────────────────────────────────────────────
-  // COLLECT FEES
-  // ─────────────────────────────────────────────────
-  describe("collect_fees", () => {
-    it("rejects collect_fees when no fees accrued", async () => {
+    it("rejects a target account the protocol does not own", async () => {
       try {
         await program.methods
-          .collectFees()
+          .updateAllocations(
+            [
+              allocation(0, 5000),
+              { protocolId: 6, targetBps: 5000, target: vaultPda, params: Buffer.alloc(0) },
+            ],
+            noRamp,
+            noRamp
+          )
           .accounts({
             authority: authority.publicKey,
             vault: vaultPda,
+            registry: registryPda,
           })
+          .remainingAccounts([{ pubkey: vaultPda, isWritable: false, isSigner: false }])
           .rpc();
         expect.fail("Should have thrown");
       } catch (err) {
-        expect(err.toString()).to.contain("ZeroAmount");
+        expect(err.toString()).to.contain("InvalidAllocationTarget");
       }
     });
 
-    it("rejects collect_fees from non-authority", async () => {
-      const rando = Keypair.generate();
-      await fundWallet(rando, 1 * LAMPORTS_PER_SOL);
-
+    it("rejects params longer than the protocol accepts", async () => {
       try {
         await program.methods
-          .collectFees()
+          .updateAllocations(
+            [
+              allocation(0, 5000),
+              { protocolId: 6, targetBps: 5000, target: poolA.publicKey, params: Buffer.alloc(9) },
+            ],
+            noRamp,
+            noRamp
+          )
           .accounts({
-            authority: rando.publicKey,
+            authority: authority.publicKey,
             vault: vaultPda,
+            registry: registryPda,
           })
-          .signers([rando])
+          .remainingAccounts([
+            { pubkey: poolA.publicKey, isWritable: false, isSigner: false },
+          ])
           .rpc();
         expect.fail("Should have thrown");
       } catch (err) {
-        expect(err.toString()).to.contain("Unauthorized");
+        expect(err.toString()).to.contain("InvalidAllocationParams");
       }
     });
-  });
-
-  // ─────────────────────────────────────────────────
-  // CLOSE POSITION
-  // ─────────────────────────────────────────────────
-  describe("close_position", () => {
-    it("closes an empty position and reclaims rent", async () => {
-      const user = Keypair.generate();
-      await fundWallet(user, 5 * LAMPORTS_PER_SOL);
-      const [positionPda] = getPositionPda(user.publicKey);
 
-      // Deposit
+    it("splits fractional bps targets without leaving dust on one allocation", async () => {
+      const thirds = [
+        allocation(0, 3333),
+        allocation(1, 3333),
+        allocation(2, 3334),
+      ];
       await program.methods
-        .deposit(new anchor.BN(LAMPORTS_PER_SOL))
+        .updateAllocations(thirds, noRamp, noRamp)
         .accounts({
-          user: user.publicKey,
+          authority: authority.publicKey,
           vault: vaultPda,
-          position: positionPda,
-          systemProgram: SystemProgram.programId,
+          registry: registryPda,
         })
-        .signers([user])
+        .rpc();
+      await program.methods
+        .rebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
         .rpc();
 
-      const position = await program.account.userPosition.fetch(positionPda);
-      const userShares = position.shares;
+      const vault = await program.account.vault.fetch(vaultPda);
+      const total = vault.totalDeposited.toNumber();
+      const amounts = vault.allocations
+        .slice(0, 3)
+        .map((a) => a.currentAmount.toNumber());
+      expect(amounts.reduce((sum, a) => sum + a, 0)).to.equal(total);
+      thirds.forEach((t, i) => {
+        const exact = (total * t.targetBps) / 10_000;
+        expect(amounts[i]).to.be.within(Math.floor(exact), Math.ceil(exact));
+      });
+    });
 
-      // Withdraw all
+    it("ramps toward new targets without resetting current amounts", async () => {
+      const before = await program.account.vault.fetch(vaultPda);
+      const total = before.totalDeposited.toNumber();
+      const now = await provider.connection.getBlockTime(
+        await provider.connection.getSlot()
+      );
+
+      // Halfway through a window around `now`, 33.33% → 50% sits near 41.67%
       await program.methods
-        .withdraw(userShares)
+        .updateAllocations(
+          [allocation(0, 5000), allocation(1, 5000)],
+          new anchor.BN(now - 1000),
+          new anchor.BN(now + 1000)
+        )
         .accounts({
-          user: user.publicKey,
+          authority: authority.publicKey,
           vault: vaultPda,
-          position: positionPda,
-          systemProgram: SystemProgram.programId,
+          registry: registryPda,
         })
-        .signers([user])
         .rpc();
 
-      const balBefore = await provider.connection.getBalance(user.publicKey);
+      let vault = await program.account.vault.fetch(vaultPda);
+      // Protocol 2 stays in the table while it ramps down to zero
+      expect(vault.numAllocations).to.equal(3);
+      expect(vault.allocations[0].startBps).to.equal(3333);
+      expect(vault.allocations[2].targetBps).to.equal(0);
+      expect(vault.allocations[0].currentAmount.toNumber()).to.equal(
+        before.allocations[0].currentAmount.toNumber()
+      );
 
-      // Close position
       await program.methods
-        .closePosition()
-        .accounts({
-          user: user.publicKey,
-          position: positionPda,
-        })
-        .signers([user])
+        .rebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
         .rpc();
 
-      const balAfter = await provider.connection.getBalance(user.publicKey);
-      // Should have gained rent back (minus tx fee)
-      expect(balAfter).to.be.greaterThan(balBefore - 10_000);
-
-      // Position account should no longer exist
-      const positionAccount = await provider.connection.getAccountInfo(positionPda);
-      expect(positionAccount).to.be.null;
+      vault = await program.account.vault.fetch(vaultPda);
+      const first = vault.allocations[0].currentAmount.toNumber();
+      const dropped = vault.allocations[2].currentAmount.toNumber();
+      expect(first).to.be.within(total * 0.38, total * 0.46);
+      expect(dropped).to.be.within(total * 0.12, total * 0.21);
+      expect(
+        vault.allocations
+          .slice(0, 3)
+          .reduce((sum, a) => sum + a.currentAmount.toNumber(), 0)
+      ).to.equal(total);
     });
 
-    it("rejects close_position with remaining shares", async () => {
-      const [positionPda] = getPositionPda(authority.publicKey);
-
+    it("rejects a ramp that ends before it starts", async () => {
       try {
         await program.methods
-          .closePosition()
+          .updateAllocations(
+            [allocation(0, 5000), allocation(1, 5000)],
+            new anchor.BN(2_000),
+            new anchor.BN(1_000)
+          )
           .accounts({
-            user: authority.publicKey,
-            position: positionPda,
+            authority: authority.publicKey,
+            vault: vaultPda,
+            registry: registryPda,
           })
           .rpc();
         expect.fail("Should have thrown");
       } catch (err) {
-        expect(err.toString()).to.contain("InsufficientShares");
+        expect(err.toString()).to.contain("InvalidRamp");
       }
     });
 
-    it("re-deposit after closing position counts as new depositor", async () => {
-      const user = Keypair.generate();
-      await fundWallet(user, 5 * LAMPORTS_PER_SOL);
-      const [positionPda] = getPositionPda(user.publicKey);
-
-      // Deposit
+    it("an immediate switch drops allocations that were ramping out", async () => {
       await program.methods
-        .deposit(new anchor.BN(LAMPORTS_PER_SOL))
+        .updateAllocations([allocation(0, 5000), allocation(1, 5000)], noRamp, noRamp)
         .accounts({
-          user: user.publicKey,
+          authority: authority.publicKey,
           vault: vaultPda,
-          position: positionPda,
-          systemProgram: SystemProgram.programId,
+          registry: registryPda,
         })
-        .signers([user])
         .rpc();
 
-      const vaultAfterDeposit = await program.account.vault.fetch(vaultPda);
-      const countAfterDeposit = vaultAfterDeposit.depositorCount.toNumber();
+      const vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.numAllocations).to.equal(2);
+      expect(vault.allocations[2].targetBps).to.equal(0);
+    });
+  });
 
-      const position = await program.account.userPosition.fetch(positionPda);
+  // ─────────────────────────────────────────────────
+  // ALLOCATION SCHEDULES
+  // ─────────────────────────────────────────────────
+  describe("allocation schedules", () => {
+    let schedulePda: PublicKey;
 
-      // Withdraw all
+    before(() => {
+      [schedulePda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("schedule")],
+        program.programId
+      );
+    });
+
+    async function chainTime(): Promise<number> {
+      return provider.connection.getBlockTime(await provider.connection.getSlot());
+    }
+
+    it("rejects an activation time that has already passed", async () => {
+      try {
+        await program.methods
+          .scheduleAllocations(
+            [allocation(0, 5000), allocation(1, 5000)],
+            new anchor.BN((await chainTime()) - 10),
+            noRamp
+          )
+          .accounts({ authority: authority.publicKey, vault: vaultPda })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("InvalidSchedule");
+      }
+    });
+
+    it("cancels a pending allocation change", async () => {
+      const activationTs = (await chainTime()) + 1000;
       await program.methods
-        .withdraw(position.shares)
-        .accounts({
-          user: user.publicKey,
-          vault: vaultPda,
-          position: positionPda,
-          systemProgram: SystemProgram.programId,
-        })
-        .signers([user])
+        .scheduleAllocations(
+          [allocation(0, 6000), allocation(1, 4000)],
+          new anchor.BN(activationTs),
+          noRamp
+        )
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
         .rpc();
 
-      const vaultAfterWithdraw = await program.account.vault.fetch(vaultPda);
-      expect(vaultAfterWithdraw.depositorCount.toNumber()).to.equal(countAfterDeposit - 1);
+      // Depositors can read what is coming and when
+      const schedule = await program.account.allocationSchedule.fetch(schedulePda);
+      expect(schedule.activationTs.toNumber()).to.equal(activationTs);
+      expect(schedule.targets.map((t) => t.targetBps)).to.deep.equal([6000, 4000]);
 
-      // Close position
       await program.methods
-        .closePosition()
-        .accounts({
-          user: user.publicKey,
-          position: positionPda,
-        })
-        .signers([user])
+        .cancelAllocationSchedule()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+      expect(await provider.connection.getAccountInfo(schedulePda)).to.be.null;
+    });
+
+    it("rebalance applies a scheduled change only once it is due", async () => {
+      await program.methods
+        .scheduleAllocations(
+          [allocation(0, 4000), allocation(1, 4000), allocation(2, 2000)],
+          new anchor.BN((await chainTime()) + 2),
+          noRamp
+        )
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
         .rpc();
 
-      // Re-deposit
       await program.methods
-        .deposit(new anchor.BN(LAMPORTS_PER_SOL))
-        .accounts({
-          user: user.publicKey,
-          vault: vaultPda,
-          position: positionPda,
-          systemProgram: SystemProgram.programId,
-        })
-        .signers([user])
+        .rebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
         .rpc();
+      let vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.numAllocations).to.equal(2);
+      expect(await provider.connection.getAccountInfo(schedulePda)).to.not.be.null;
 
-      const vaultFinal = await program.account.vault.fetch(vaultPda);
-      // depositor_count should be incremented (new depositor because shares were 0)
-      expect(vaultFinal.depositorCount.toNumber()).to.equal(countAfterDeposit);
+      await new Promise((r) => setTimeout(r, 4000));
 
-      // Clean up: withdraw
-      const pos2 = await program.account.userPosition.fetch(positionPda);
       await program.methods
-        .withdraw(pos2.shares)
-        .accounts({
-          user: user.publicKey,
-          vault: vaultPda,
-          position: positionPda,
-          systemProgram: SystemProgram.programId,
-        })
-        .signers([user])
+        .rebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
         .rpc();
+      vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.numAllocations).to.equal(3);
+      expect(vault.allocations[0].targetBps).to.equal(4000);
+      expect(vault.allocations[2].targetBps).to.equal(2000);
+      expect(await provider.connection.getAccountInfo(schedulePda)).to.be.null;
     });
   });
 
   // ─────────────────────────────────────────────────
-  // TWO-STEP AUTHORITY TRANSFER
+  // INCREMENTAL REBALANCE
   // ─────────────────────────────────────────────────
-  describe("propose_authority / accept_authority", () => {
-    it("two-step authority transfer works correctly", async () => {
-      const newAuthority = Keypair.generate();
-      await fundWallet(newAuthority, 1 * LAMPORTS_PER_SOL);
+  describe("incremental rebalance", () => {
+    let sessionPda: PublicKey;
 
-      // Step 1: propose
+    before(() => {
+      [sessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("rebalance_session")],
+        program.programId
+      );
+    });
+
+    it("moves one allocation per step and lands where rebalance would", async () => {
       await program.methods
-        .proposeAuthority(newAuthority.publicKey)
-        .accounts({
-          authority: authority.publicKey,
-          vault: vaultPda,
-        })
+        .updateAllocations(
+          [allocation(0, 2000), allocation(1, 5000), allocation(2, 3000)],
+          noRamp,
+          noRamp
+        )
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
         .rpc();
+      const preview = await program.methods
+        .previewRebalance(0)
+        .accounts({ vault: vaultPda })
+        .view();
 
-      const vaultAfterPropose = await program.account.vault.fetch(vaultPda);
-      expect(vaultAfterPropose.pendingAuthority.toBase58()).to.equal(
-        newAuthority.publicKey.toBase58()
-      );
-      // Authority hasn't changed yet
-      expect(vaultAfterPropose.authority.toBase58()).to.equal(
-        authority.publicKey.toBase58()
+      await program.methods
+        .beginRebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+      let vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.rebalancing).to.equal(1);
+      expect(vault.rebalanceDeadlineSlot.toNumber()).to.be.greaterThan(0);
+      const session = await program.account.rebalanceSession.fetch(sessionPda);
+      expect(session.deltas.map((d) => d.toString())).to.deep.equal(
+        preview.allocations.map((m) => m.delta.toString())
       );
 
-      // Step 2: accept (new authority must sign)
+      // Steps can run in any order
+      for (const i of [2, 0, 1]) {
+        await program.methods
+          .rebalanceStep(i)
+          .accounts({ authority: authority.publicKey, vault: vaultPda })
+          .rpc();
+      }
       await program.methods
-        .acceptAuthority()
-        .accounts({
-          newAuthority: newAuthority.publicKey,
-          vault: vaultPda,
-        })
-        .signers([newAuthority])
+        .finishRebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
         .rpc();
 
-      const vaultAfterAccept = await program.account.vault.fetch(vaultPda);
-      expect(vaultAfterAccept.authority.toBase58()).to.equal(
-        newAuthority.publicKey.toBase58()
-      );
-      expect(vaultAfterAccept.pendingAuthority.toBase58()).to.equal(
-        PublicKey.default.toBase58()
-      );
+      vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.rebalancing).to.equal(0);
+      // No session state is left behind
+      expect(vault.rebalanceNav.toNumber()).to.equal(0);
+      expect(vault.rebalanceDeadlineSlot.toNumber()).to.equal(0);
+      expect(
+        vault.allocations
+          .slice(0, vault.numAllocations)
+          .map((a) => a.currentAmount.toString())
+      ).to.deep.equal(preview.allocations.map((m) => m.targetAmount.toString()));
+      expect(await provider.connection.getAccountInfo(sessionPda)).to.be.null;
+    });
 
-      // Transfer back so other tests continue to work
+    it("rejects repeated steps, early finishes and rebalances mid-session", async () => {
       await program.methods
-        .proposeAuthority(authority.publicKey)
-        .accounts({
-          authority: newAuthority.publicKey,
-          vault: vaultPda,
-        })
-        .signers([newAuthority])
+        .beginRebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
         .rpc();
-
       await program.methods
-        .acceptAuthority()
-        .accounts({
-          newAuthority: authority.publicKey,
-          vault: vaultPda,
-        })
+        .rebalanceStep(0)
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
         .rpc();
 
-      const vaultRestored = await program.account.vault.fetch(vaultPda);
-      expect(vaultRestored.authority.toBase58()).to.equal(authority.publicKey.toBase58());
+      const attempts: [string, () => Promise<string>][] = [
+        [
+          "InvalidRebalanceStep",
+          () =>
+            program.methods
+              .rebalanceStep(0)
+              .accounts({ authority: authority.publicKey, vault: vaultPda })
+              .rpc(),
+        ],
+        [
+          "RebalanceIncomplete",
+          () =>
+            program.methods
+              .finishRebalance()
+              .accounts({ authority: authority.publicKey, vault: vaultPda })
+              .rpc(),
+        ],
+        [
+          "RebalanceInProgress",
+          () =>
+            program.methods
+              .rebalance()
+              .accounts({ authority: authority.publicKey, vault: vaultPda })
+              .rpc(),
+        ],
+        [
+          "RebalanceInProgress",
+          () =>
+            program.methods
+              .updateAllocations([allocation(0, 5000), allocation(1, 5000)], noRamp, noRamp)
+              .accounts({ authority: authority.publicKey, vault: vaultPda })
+              .rpc(),
+        ],
+      ];
+      for (const [error, attempt] of attempts) {
+        try {
+          await attempt();
+          expect.fail("Should have thrown");
+        } catch (err) {
+          expect(err.toString()).to.contain(error);
+        }
+      }
     });
 
-    it("rejects propose from non-authority", async () => {
-      const rando = Keypair.generate();
-      await fundWallet(rando, 1 * LAMPORTS_PER_SOL);
-
-      try {
-        await program.methods
-          .proposeAuthority(rando.publicKey)
+    it("only lets withdrawals take funds deposited since the session began", async () => {
+      const [positionPda] = getPositionPda(authority.publicKey);
+      const withdraw = () =>
+        program.methods
+          .withdraw(0, new anchor.BN(SHARES_PER_SOL / 10))
           .accounts({
-            authority: rando.publicKey,
+            user: authority.publicKey,
             vault: vaultPda,
+            position: positionPda,
+            systemProgram: SystemProgram.programId,
           })
-          .signers([rando])
           .rpc();
+
+      try {
+        await withdraw();
         expect.fail("Should have thrown");
       } catch (err) {
-        expect(err.toString()).to.contain("Unauthorized");
+        expect(err.toString()).to.contain("RebalanceInProgress");
+      }
+      try {
+        await program.methods
+          .previewWithdraw(new anchor.BN(SHARES_PER_SOL / 10))
+          .accounts({ vault: vaultPda, position: positionPda })
+          .view();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("RebalanceInProgress");
       }
-    });
-
-    it("rejects accept from wrong signer", async () => {
-      const newAuth = Keypair.generate();
-      const wrongSigner = Keypair.generate();
-      await fundWallet(newAuth, 1 * LAMPORTS_PER_SOL);
-      await fundWallet(wrongSigner, 1 * LAMPORTS_PER_SOL);
 
-      // Propose newAuth
       await program.methods
-        .proposeAuthority(newAuth.publicKey)
+        .deposit(0, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(0))
         .accounts({
-          authority: authority.publicKey,
+          user: authority.publicKey,
           vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
         })
         .rpc();
+      await withdraw();
 
-      // Wrong signer tries to accept
+      for (const i of [1, 2]) {
+        await program.methods
+          .rebalanceStep(i)
+          .accounts({ authority: authority.publicKey, vault: vaultPda })
+          .rpc();
+      }
+      await program.methods
+        .finishRebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+    });
+
+    it("cancels a session so withdrawals resume", async () => {
+      const [positionPda] = getPositionPda(authority.publicKey);
+      const withdraw = () =>
+        program.methods
+          .withdraw(0, new anchor.BN(SHARES_PER_SOL / 10))
+          .accounts({
+            user: authority.publicKey,
+            vault: vaultPda,
+            position: positionPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .rpc();
+
+      await program.methods
+        .beginRebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+      await program.methods
+        .rebalanceStep(0)
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+      try {
+        await withdraw();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("RebalanceInProgress");
+      }
+
+      // Only the authority may cancel before the session lapses
+      const stranger = Keypair.generate();
       try {
         await program.methods
-          .acceptAuthority()
+          .cancelRebalance()
           .accounts({
-            newAuthority: wrongSigner.publicKey,
+            caller: stranger.publicKey,
+            authority: authority.publicKey,
             vault: vaultPda,
           })
-          .signers([wrongSigner])
+          .signers([stranger])
           .rpc();
         expect.fail("Should have thrown");
       } catch (err) {
         expect(err.toString()).to.contain("Unauthorized");
       }
 
-      // Clean up: reset pending
       await program.methods
-        .proposeAuthority(PublicKey.default)
+        .cancelRebalance()
+        .accounts({
+          caller: authority.publicKey,
+          authority: authority.publicKey,
+          vault: vaultPda,
+        })
+        .rpc();
+      const vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.rebalancing).to.equal(0);
+      expect(await provider.connection.getAccountInfo(sessionPda)).to.be.null;
+
+      await withdraw();
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // UPDATE CONFIG
+  // ─────────────────────────────────────────────────
+  describe("update_config", () => {
+    it("updates performance fee", async () => {
+      await program.methods
+        .updateConfig(1000, null, null, null) // 10%
+        .accounts({
+          authority: authority.publicKey,
+          vault: vaultPda,
+        })
+        .rpc();
+
+      const vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.performanceFeeBps).to.equal(1000);
+    });
+
+    it("updates deposit cap", async () => {
+      const newCap = new anchor.BN(500 * LAMPORTS_PER_SOL);
+      await program.methods
+        .updateConfig(null, newCap, null, null)
+        .accounts({
+          authority: authority.publicKey,
+          vault: vaultPda,
+        })
+        .rpc();
+
+      const vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.depositCap.toNumber()).to.equal(newCap.toNumber());
+    });
+
+    it("pauses the vault", async () => {
+      await program.methods
+        .updateConfig(null, null, true, null)
+        .accounts({
+          authority: authority.publicKey,
+          vault: vaultPda,
+        })
+        .rpc();
+
+      const vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.isPaused).to.equal(1);
+    });
+
+    it("rejects deposits when paused", async () => {
+      const [positionPda] = getPositionPda(authority.publicKey);
+
+      try {
+        await program.methods
+          .deposit(0, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(0))
+          .accounts({
+            user: authority.publicKey,
+            vault: vaultPda,
+            position: positionPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("VaultPaused");
+      }
+    });
+
+    it("unpauses the vault", async () => {
+      await program.methods
+        .updateConfig(null, null, false, null)
+        .accounts({
+          authority: authority.publicKey,
+          vault: vaultPda,
+        })
+        .rpc();
+
+      const vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.isPaused).to.equal(0);
+    });
+
+    it("rejects fee above maximum (30%)", async () => {
+      try {
+        await program.methods
+          .updateConfig(5000, null, null, null) // 50% > 3000 bps max
+          .accounts({
+            authority: authority.publicKey,
+            vault: vaultPda,
+          })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("FeeTooHigh");
+      }
+    });
+
+    it("accepts fee at exactly maximum (30%)", async () => {
+      await program.methods
+        .updateConfig(3000, null, null, null) // exactly 30%
+        .accounts({
+          authority: authority.publicKey,
+          vault: vaultPda,
+        })
+        .rpc();
+
+      const vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.performanceFeeBps).to.equal(3000);
+
+      // Restore to 5%
+      await program.methods
+        .updateConfig(500, null, null, null)
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+    });
+
+    it("rejects update_config from non-authority", async () => {
+      const rando = Keypair.generate();
+      await fundWallet(rando, 1 * LAMPORTS_PER_SOL);
+
+      try {
+        await program.methods
+          .updateConfig(100, null, null, null)
+          .accounts({
+            authority: rando.publicKey,
+            vault: vaultPda,
+          })
+          .signers([rando])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("Unauthorized");
+      }
+    });
+
+    it("can update multiple config fields at once", async () => {
+      const newCap = new anchor.BN(2000 * LAMPORTS_PER_SOL);
+      await program.methods
+        .updateConfig(800, newCap, false, null)
         .accounts({
           authority: authority.publicKey,
           vault: vaultPda,
         })
         .rpc();
+
+      const vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.performanceFeeBps).to.equal(800);
+      expect(vault.depositCap.toNumber()).to.equal(newCap.toNumber());
+      expect(vault.isPaused).to.equal(0);
+    });
+
+    it("holds freshly minted shares back for the configured slots after a deposit", async () => {
+      const [positionPda] = getPositionPda(authority.publicKey);
+      const accounts = {
+        user: authority.publicKey,
+        vault: vaultPda,
+        position: positionPda,
+        systemProgram: SystemProgram.programId,
+      };
+      await program.methods
+        .updateConfig(null, null, null, new anchor.BN(1_000_000))
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+
+      await program.methods
+        .deposit(0, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts(accounts)
+        .rpc();
+      const position = await program.account.userPosition.fetch(positionPda);
+      expect(position.lastDepositSlot.toNumber()).to.be.greaterThan(0);
+      expect(position.lockedShares.toNumber()).to.be.greaterThan(0);
+
+      // The whole position includes the locked shares
+      try {
+        await program.methods.withdraw(0, position.shares).accounts(accounts).rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("WithdrawalTooSoon");
+      }
+      try {
+        await program.methods
+          .previewWithdraw(position.shares)
+          .accounts({ vault: vaultPda, position: positionPda })
+          .view();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("WithdrawalTooSoon");
+      }
+
+      // Shares held from before the deposit stay free
+      const shares = new anchor.BN(SHARES_PER_SOL / 10);
+      await program.methods.withdraw(0, shares).accounts(accounts).rpc();
+
+      // With no delay, only the deposit's own slot is blocked
+      await program.methods
+        .updateConfig(null, null, null, new anchor.BN(0))
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+      await program.methods.withdraw(0, shares).accounts(accounts).rpc();
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // COLLECT FEES
+  // ─────────────────────────────────────────────────
+  describe("collect_fees", () => {
+    it("rejects collect_fees when no fees accrued", async () => {
+      try {
+        await program.methods
+          .collectFees()
+          .accounts({
+            authority: authority.publicKey,
+            vault: vaultPda,
+          })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("ZeroAmount");
+      }
+    });
+
+    it("rejects collect_fees from non-authority", async () => {
+      const rando = Keypair.generate();
+      await fundWallet(rando, 1 * LAMPORTS_PER_SOL);
+
+      try {
+        await program.methods
+          .collectFees()
+          .accounts({
+            authority: rando.publicKey,
+            vault: vaultPda,
+          })
+          .signers([rando])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("Unauthorized");
+      }
     });
   });
 
+  // ─────────────────────────────────────────────────
+  // CLOSE POSITION
+  // ─────────────────────────────────────────────────
+  describe("close_position", () => {
+    it("closes an empty position and reclaims rent", async () => {
+      const user = Keypair.generate();
+      await fundWallet(user, 5 * LAMPORTS_PER_SOL);
+      const [positionPda] = getPositionPda(user.publicKey);
+
+      // Deposit
+      await program.methods
+        .deposit(0, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const position = await program.account.userPosition.fetch(positionPda);
+      const userShares = position.shares;
+
+      // Withdraw all
+      await program.methods
+        .withdraw(0, userShares)
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const balBefore = await provider.connection.getBalance(user.publicKey);
+
+      // Close position
+      await program.methods
+        .closePosition(0)
+        .accounts({
+          user: user.publicKey,
+          position: positionPda,
+        })
+        .signers([user])
+        .rpc();
+
+      const balAfter = await provider.connection.getBalance(user.publicKey);
+      // Should have gained rent back (minus tx fee)
+      expect(balAfter).to.be.greaterThan(balBefore - 10_000);
+
+      // Position account should no longer exist
+      const positionAccount = await provider.connection.getAccountInfo(positionPda);
+      expect(positionAccount).to.be.null;
+    });
+
+    it("rejects close_position with remaining shares", async () => {
+      const [positionPda] = getPositionPda(authority.publicKey);
+
+      try {
+        await program.methods
+          .closePosition(0)
+          .accounts({
+            user: authority.publicKey,
+            position: positionPda,
+          })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("InsufficientShares");
+      }
+    });
+
+    it("re-deposit after closing position counts as new depositor", async () => {
+      const user = Keypair.generate();
+      await fundWallet(user, 5 * LAMPORTS_PER_SOL);
+      const [positionPda] = getPositionPda(user.publicKey);
+
+      // Deposit
+      await program.methods
+        .deposit(0, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const vaultAfterDeposit = await program.account.vault.fetch(vaultPda);
+      const countAfterDeposit = vaultAfterDeposit.depositorCount.toNumber();
+
+      const position = await program.account.userPosition.fetch(positionPda);
+
+      // Withdraw all
+      await program.methods
+        .withdraw(0, position.shares)
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const vaultAfterWithdraw = await program.account.vault.fetch(vaultPda);
+      expect(vaultAfterWithdraw.depositorCount.toNumber()).to.equal(countAfterDeposit - 1);
+
+      // Close position
+      await program.methods
+        .closePosition(0)
+        .accounts({
+          user: user.publicKey,
+          position: positionPda,
+        })
+        .signers([user])
+        .rpc();
+
+      // Re-deposit
+      await program.methods
+        .deposit(0, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const vaultFinal = await program.account.vault.fetch(vaultPda);
+      // depositor_count should be incremented (new depositor because shares were 0)
+      expect(vaultFinal.depositorCount.toNumber()).to.equal(countAfterDeposit);
+
+      // Clean up: withdraw
+      const pos2 = await program.account.userPosition.fetch(positionPda);
+      await program.methods
+        .withdraw(0, pos2.shares)
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // TWO-STEP AUTHORITY TRANSFER
+  // ─────────────────────────────────────────────────
+  describe("propose_authority / accept_authority", () => {
+    it("two-step authority transfer works correctly", async () => {
+      const newAuthority = Keypair.generate();
+      await fundWallet(newAuthority, 1 * LAMPORTS_PER_SOL);
+
+      // Step 1: propose
+      await program.methods
+        .proposeAuthority(newAuthority.publicKey)
+        .accounts({
+          authority: authority.publicKey,
+          vault: vaultPda,
+        })
+        .rpc();
+
+      const vaultAfterPropose = await program.account.vault.fetch(vaultPda);
+      expect(vaultAfterPropose.pendingAuthority.toBase58()).to.equal(
+        newAuthority.publicKey.toBase58()
+      );
+      // Authority hasn't changed yet
+      expect(vaultAfterPropose.authority.toBase58()).to.equal(
+        authority.publicKey.toBase58()
+      );
+
+      // Step 2: accept (new authority must sign)
+      await program.methods
+        .acceptAuthority()
+        .accounts({
+          newAuthority: newAuthority.publicKey,
+          vault: vaultPda,
+        })
+        .signers([newAuthority])
+        .rpc();
+
+      const vaultAfterAccept = await program.account.vault.fetch(vaultPda);
+      expect(vaultAfterAccept.authority.toBase58()).to.equal(
+        newAuthority.publicKey.toBase58()
+      );
+      expect(vaultAfterAccept.pendingAuthority.toBase58()).to.equal(
+        PublicKey.default.toBase58()
+      );
+
+      // Transfer back so other tests continue to work
+      await program.methods
+        .proposeAuthority(authority.publicKey)
+        .accounts({
+          authority: newAuthority.publicKey,
+          vault: vaultPda,
+        })
+        .signers([newAuthority])
+        .rpc();
+
+      await program.methods
+        .acceptAuthority()
+        .accounts({
+          newAuthority: authority.publicKey,
+          vault: vaultPda,
+        })
+        .rpc();
+
+      const vaultRestored = await program.account.vault.fetch(vaultPda);
+      expect(vaultRestored.authority.toBase58()).to.equal(authority.publicKey.toBase58());
+    });
+
+    it("rejects propose from non-authority", async () => {
+      const rando = Keypair.generate();
+      await fundWallet(rando, 1 * LAMPORTS_PER_SOL);
+
+      try {
+        await program.methods
+          .proposeAuthority(rando.publicKey)
+          .accounts({
+            authority: rando.publicKey,
+            vault: vaultPda,
+          })
+          .signers([rando])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("Unauthorized");
+      }
+    });
+
+    it("rejects accept from wrong signer", async () => {
+      const newAuth = Keypair.generate();
+      const wrongSigner = Keypair.generate();
+      await fundWallet(newAuth, 1 * LAMPORTS_PER_SOL);
+      await fundWallet(wrongSigner, 1 * LAMPORTS_PER_SOL);
+
+      // Propose newAuth
+      await program.methods
+        .proposeAuthority(newAuth.publicKey)
+        .accounts({
+          authority: authority.publicKey,
+          vault: vaultPda,
+        })
+        .rpc();
+
+      // Wrong signer tries to accept
+      try {
+        await program.methods
+          .acceptAuthority()
+          .accounts({
+            newAuthority: wrongSigner.publicKey,
+            vault: vaultPda,
+          })
+          .signers([wrongSigner])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("Unauthorized");
+      }
+
+      // Clean up: reset pending
+      await program.methods
+        .proposeAuthority(PublicKey.default)
+        .accounts({
+          authority: authority.publicKey,
+          vault: vaultPda,
+        })
+        .rpc();
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // PREVIEW / VIEW INSTRUCTIONS
+  // ─────────────────────────────────────────────────
+  describe("preview instructions", () => {
+    it("previewDeposit matches the shares minted by deposit", async () => {
+      const amount = new anchor.BN(LAMPORTS_PER_SOL);
+      const [positionPda] = getPositionPda(authority.publicKey);
+
+      const preview = await program.methods
+        .previewDeposit(amount)
+        .accounts({ vault: vaultPda })
+        .view();
+
+      const before = await program.account.userPosition.fetch(positionPda);
+      await program.methods
+        .deposit(0, amount, new anchor.BN(0))
+        .accounts({
+          user: authority.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      const after = await program.account.userPosition.fetch(positionPda);
+
+      expect(after.shares.sub(before.shares).toString()).to.equal(preview.toString());
+    });
+
+    it("previewWithdraw matches the amount released by withdraw", async () => {
+      const [positionPda] = getPositionPda(authority.publicKey);
+      const shares = new anchor.BN(SHARES_PER_SOL);
+
+      const preview = await program.methods
+        .previewWithdraw(shares)
+        .accounts({ vault: vaultPda, position: positionPda })
+        .view();
+
+      const vaultBefore = await program.account.vault.fetch(vaultPda);
+      await program.methods
+        .withdraw(0, shares)
+        .accounts({
+          user: authority.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      const vaultAfter = await program.account.vault.fetch(vaultPda);
+
+      expect(
+        vaultBefore.totalDeposited.sub(vaultAfter.totalDeposited).toString()
+      ).to.equal(preview.grossAmount.toString());
+      expect(
+        vaultAfter.accruedFees.sub(vaultBefore.accruedFees).toString()
+      ).to.equal(preview.fee.toString());
+      expect(preview.netAmount.add(preview.fee).toString()).to.equal(
+        preview.grossAmount.toString()
+      );
+    });
+
+    it("previewWithdraw fails with the same errors as withdraw", async () => {
+      const [positionPda] = getPositionPda(authority.publicKey);
+
+      try {
+        await program.methods
+          .previewWithdraw(new anchor.BN(999_999_999_999))
+          .accounts({ vault: vaultPda, position: positionPda })
+          .view();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("InsufficientShares");
+      }
+    });
+
+    it("getSharePrice reports the current exchange rate", async () => {
+      const vault = await program.account.vault.fetch(vaultPda);
+      const price = await program.methods
+        .getSharePrice()
+        .accounts({ vault: vaultPda })
+        .view();
+
+      expect(price.totalDeposited.toString()).to.equal(vault.totalDeposited.toString());
+      expect(price.totalShares.toString()).to.equal(vault.totalShares.toString());
+      const expected = new anchor.BN(SHARES_PER_SOL)
+        .mul(virtualAssets(vault))
+        .div(virtualShares(vault));
+      expect(price.lamportsPerSolShares.toString()).to.equal(expected.toString());
+    });
+
+    it("getPositionValue values a full exit", async () => {
+      const [positionPda] = getPositionPda(authority.publicKey);
+      const position = await program.account.userPosition.fetch(positionPda);
+
+      const value = await program.methods
+        .getPositionValue()
+        .accounts({ vault: vaultPda, position: positionPda })
+        .view();
+      const preview = await program.methods
+        .previewWithdraw(position.shares)
+        .accounts({ vault: vaultPda, position: positionPda })
+        .view();
+
+      expect(value.owner.toBase58()).to.equal(authority.publicKey.toBase58());
+      expect(value.shares.toString()).to.equal(position.shares.toString());
+      expect(value.costBasis.toString()).to.equal(position.depositedAmount.toString());
+      expect(value.netValue.toString()).to.equal(preview.netAmount.toString());
+    });
+
+    it("previewRebalance matches the amounts rebalance allocates", async () => {
+      const before = await program.account.vault.fetch(vaultPda);
+      const preview = await program.methods
+        .previewRebalance(0)
+        .accounts({ vault: vaultPda })
+        .view();
+
+      // The preview is read-only
+      const unchanged = await program.account.vault.fetch(vaultPda);
+      expect(unchanged.allocations.map((a) => a.currentAmount.toString())).to.deep.equal(
+        before.allocations.map((a) => a.currentAmount.toString())
+      );
+
+      await program.methods
+        .rebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+      const after = await program.account.vault.fetch(vaultPda);
+
+      expect(preview.totalDeposited.toString()).to.equal(after.totalDeposited.toString());
+      expect(preview.allocations.length).to.equal(after.numAllocations);
+      preview.allocations.forEach((move, i) => {
+        const alloc = after.allocations[i];
+        expect(move.protocolId).to.equal(alloc.protocolId);
+        expect(move.targetBps).to.equal(alloc.targetBps);
+        expect(move.targetAmount.toString()).to.equal(alloc.currentAmount.toString());
+        expect(move.delta.toString()).to.equal(
+          move.targetAmount.sub(move.currentAmount).toString()
+        );
+      });
+      expect(preview.fees.toNumber()).to.equal(0);
+      expect(preview.keeperTip.toNumber()).to.equal(0);
+    });
+
+    it("previewRebalance pages from the requested allocation", async () => {
+      const vault = await program.account.vault.fetch(vaultPda);
+      const preview = await program.methods
+        .previewRebalance(1)
+        .accounts({ vault: vaultPda })
+        .view();
+
+      expect(preview.allocations.length).to.equal(vault.numAllocations - 1);
+      expect(preview.allocations[0].protocolId).to.equal(vault.allocations[1].protocolId);
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // EXACT-IN / EXACT-OUT WITH SLIPPAGE BOUNDS
+  // ─────────────────────────────────────────────────
+  describe("slippage-bounded entry points", () => {
+    const ceilDiv = (a: anchor.BN, b: anchor.BN) =>
+      a.add(b).sub(new anchor.BN(1)).div(b);
+
+    function userAccounts() {
+      const [positionPda] = getPositionPda(authority.publicKey);
+      return {
+        user: authority.publicKey,
+        vault: vaultPda,
+        position: positionPda,
+        systemProgram: SystemProgram.programId,
+      };
+    }
+
+    it("deposit rejects when fewer than min_shares_out would be minted", async () => {
+      const amount = new anchor.BN(LAMPORTS_PER_SOL);
+      const quoted = await program.methods
+        .previewDeposit(amount)
+        .accounts({ vault: vaultPda })
+        .view();
+
+      try {
+        await program.methods
+          .deposit(0, amount, quoted.add(new anchor.BN(1)))
+          .accounts(userAccounts())
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("SlippageExceeded");
+      }
+
+      // Exactly the quoted amount passes
+      await program.methods.deposit(0, amount, quoted).accounts(userAccounts()).rpc();
+    });
+
+    it("mint charges the share cost rounded up", async () => {
+      // An odd share count so the cost has a fractional lamport
+      const shares = new anchor.BN(SHARES_PER_SOL / 2 + 7);
+      const vaultBefore = await program.account.vault.fetch(vaultPda);
+      const expectedCost = ceilDiv(
+        shares.mul(virtualAssets(vaultBefore)),
+        virtualShares(vaultBefore)
+      );
+
+      const positionBefore = await program.account.userPosition.fetch(
+        userAccounts().position
+      );
+      await program.methods
+        .mint(0, shares, expectedCost)
+        .accounts(userAccounts())
+        .rpc();
+
+      const vaultAfter = await program.account.vault.fetch(vaultPda);
+      const positionAfter = await program.account.userPosition.fetch(
+        userAccounts().position
+      );
+      expect(positionAfter.shares.sub(positionBefore.shares).toString()).to.equal(
+        shares.toString()
+      );
+      expect(
+        vaultAfter.totalDeposited.sub(vaultBefore.totalDeposited).toString()
+      ).to.equal(expectedCost.toString());
+    });
+
+    it("mint rejects when the cost exceeds max_lamports", async () => {
+      const shares = new anchor.BN(SHARES_PER_SOL);
+      const vault = await program.account.vault.fetch(vaultPda);
+      const cost = ceilDiv(shares.mul(virtualAssets(vault)), virtualShares(vault));
+
+      try {
+        await program.methods
+          .mint(0, shares, cost.sub(new anchor.BN(1)))
+          .accounts(userAccounts())
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("SlippageExceeded");
+      }
+    });
+
+    it("redeem enforces min_lamports_out", async () => {
+      const shares = new anchor.BN(SHARES_PER_SOL / 4);
+      const preview = await program.methods
+        .previewWithdraw(shares)
+        .accounts({ vault: vaultPda, position: userAccounts().position })
+        .view();
+
+      try {
+        await program.methods
+          .redeem(0, shares, preview.netAmount.add(new anchor.BN(1)))
+          .accounts(userAccounts())
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("SlippageExceeded");
+      }
+
+      const vaultBefore = await program.account.vault.fetch(vaultPda);
+      await program.methods
+        .redeem(0, shares, preview.netAmount)
+        .accounts(userAccounts())
+        .rpc();
+      const vaultAfter = await program.account.vault.fetch(vaultPda);
+      expect(vaultBefore.totalShares.sub(vaultAfter.totalShares).toString()).to.equal(
+        shares.toString()
+      );
+    });
+
+    it("withdraw_exact burns shares rounded up and pays at least the requested amount", async () => {
+      const lamports = new anchor.BN(LAMPORTS_PER_SOL / 3);
+      const vaultBefore = await program.account.vault.fetch(vaultPda);
+      // Fee-free lower bound on shares burned (rounded up)
+      const minShares = ceilDiv(
+        lamports.mul(virtualShares(vaultBefore)),
+        virtualAssets(vaultBefore)
+      );
+
+      try {
+        await program.methods
+          .withdrawExact(0, lamports, minShares.sub(new anchor.BN(1)))
+          .accounts(userAccounts())
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("SlippageExceeded");
+      }
+
+      await program.methods
+        .withdrawExact(0, lamports, minShares.mul(new anchor.BN(2)))
+        .accounts(userAccounts())
+        .rpc();
+
+      const vaultAfter = await program.account.vault.fetch(vaultPda);
+      const burned = vaultBefore.totalShares.sub(vaultAfter.totalShares);
+      expect(burned.gte(minShares)).to.equal(true);
+
+      const gross = vaultBefore.totalDeposited.sub(vaultAfter.totalDeposited);
+      const fee = vaultAfter.accruedFees.sub(vaultBefore.accruedFees);
+      expect(gross.sub(fee).gte(lamports)).to.equal(true);
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // DEPOSIT FOR BENEFICIARY
+  // ─────────────────────────────────────────────────
+  describe("deposit_for", () => {
+    const payer = Keypair.generate();
+    const beneficiary = Keypair.generate();
+
+    before(async () => {
+      await fundWallet(payer, 5 * LAMPORTS_PER_SOL);
+      await fundWallet(beneficiary, 1 * LAMPORTS_PER_SOL);
+    });
+
+    function depositForAccounts() {
+      const [positionPda] = getPositionPda(beneficiary.publicKey);
+      return {
+        payer: payer.publicKey,
+        vault: vaultPda,
+        position: positionPda,
+        systemProgram: SystemProgram.programId,
+      };
+    }
+
+    it("rejects creating a position without sponsoring rent", async () => {
+      try {
+        await program.methods
+          .depositFor(
+            beneficiary.publicKey,
+            0,
+            new anchor.BN(LAMPORTS_PER_SOL),
+            new anchor.BN(0),
+            false
+          )
+          .accounts(depositForAccounts())
+          .signers([payer])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("PositionNotInitialized");
+      }
+    });
+
+    it("payer funds a new position owned by the beneficiary", async () => {
+      const vaultBefore = await program.account.vault.fetch(vaultPda);
+
+      await program.methods
+        .depositFor(
+          beneficiary.publicKey,
+          0,
+          new anchor.BN(LAMPORTS_PER_SOL),
+          new anchor.BN(0),
+          true
+        )
+        .accounts(depositForAccounts())
+        .signers([payer])
+        .rpc();
+
+      const position = await program.account.userPosition.fetch(
+        depositForAccounts().position
+      );
+      expect(position.owner.toBase58()).to.equal(beneficiary.publicKey.toBase58());
+      expect(position.depositedAmount.toNumber()).to.equal(LAMPORTS_PER_SOL);
+
+      const vaultAfter = await program.account.vault.fetch(vaultPda);
+      expect(vaultAfter.depositorCount.toNumber()).to.equal(
+        vaultBefore.depositorCount.toNumber() + 1
+      );
+    });
+
+    it("tops up an existing position without sponsoring rent", async () => {
+      const before = await program.account.userPosition.fetch(
+        depositForAccounts().position
+      );
+
+      await program.methods
+        .depositFor(
+          beneficiary.publicKey,
+          0,
+          new anchor.BN(LAMPORTS_PER_SOL),
+          new anchor.BN(0),
+          false
+        )
+        .accounts(depositForAccounts())
+        .signers([payer])
+        .rpc();
+
+      const after = await program.account.userPosition.fetch(
+        depositForAccounts().position
+      );
+      expect(after.depositedAmount.toNumber()).to.equal(
+        before.depositedAmount.toNumber() + LAMPORTS_PER_SOL
+      );
+    });
+
+    it("only the beneficiary can withdraw the funded shares", async () => {
+      const [positionPda] = getPositionPda(beneficiary.publicKey);
+      const position = await program.account.userPosition.fetch(positionPda);
+
+      await program.methods
+        .withdraw(0, position.shares)
+        .accounts({
+          user: beneficiary.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([beneficiary])
+        .rpc();
+
+      const after = await program.account.userPosition.fetch(positionPda);
+      expect(after.shares.toNumber()).to.equal(0);
+    });
+
+    it("a deposit for someone else doesn't lock their existing shares", async () => {
+      const [positionPda] = getPositionPda(beneficiary.publicKey);
+      const topUp = LAMPORTS_PER_SOL / 10;
+      const depositFor = (lamports: number) =>
+        program.methods
+          .depositFor(beneficiary.publicKey, 0, new anchor.BN(lamports), new anchor.BN(0), false)
+          .accounts(depositForAccounts())
+          .instruction();
+      const withdraw = (shares: anchor.BN) =>
+        program.methods
+          .withdraw(0, shares)
+          .accounts({
+            user: beneficiary.publicKey,
+            vault: vaultPda,
+            position: positionPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .instruction();
+
+      await program.methods
+        .depositFor(beneficiary.publicKey, 0, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(0), false)
+        .accounts(depositForAccounts())
+        .signers([payer])
+        .rpc();
+      const { shares } = await program.account.userPosition.fetch(positionPda);
+
+      // A griefing top-up in the same slot can't hold back the shares already held
+      await provider.sendAndConfirm(
+        new Transaction().add(await depositFor(topUp), await withdraw(shares)),
+        [payer, beneficiary]
+      );
+      const position = await program.account.userPosition.fetch(positionPda);
+      expect(position.shares.toString()).to.equal(position.lockedShares.toString());
+
+      // ...but the shares it mints are held back
+      try {
+        await provider.sendAndConfirm(
+          new Transaction().add(
+            await depositFor(topUp),
+            await withdraw(position.shares.addn(1))
+          ),
+          [payer, beneficiary]
+        );
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("WithdrawalTooSoon");
+      }
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // POSITION TRANSFERS AND DELEGATES
+  // ─────────────────────────────────────────────────
+  describe("position transfers and delegates", () => {
+    const owner = Keypair.generate();
+    const newWallet = Keypair.generate();
+    const delegate = Keypair.generate();
+
+    before(async () => {
+      await fundWallet(owner, 5 * LAMPORTS_PER_SOL);
+      await fundWallet(delegate, 1 * LAMPORTS_PER_SOL);
+
+      const [positionPda] = getPositionPda(owner.publicKey);
+      await program.methods
+        .deposit(0, new anchor.BN(2 * LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts({
+          user: owner.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([owner])
+        .rpc();
+    });
+
+    it("transfers shares and proportional cost basis to a new wallet", async () => {
+      const [fromPda] = getPositionPda(owner.publicKey);
+      const [toPda] = getPositionPda(newWallet.publicKey);
+      const from = await program.account.userPosition.fetch(fromPda);
+      const half = from.shares.div(new anchor.BN(2));
+
+      await program.methods
+        .transferPositionShares(0, newWallet.publicKey, 0, half)
+        .accounts({
+          user: owner.publicKey,
+          vault: vaultPda,
+          fromPosition: fromPda,
+          toPosition: toPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([owner])
+        .rpc();
+
+      const fromAfter = await program.account.userPosition.fetch(fromPda);
+      const to = await program.account.userPosition.fetch(toPda);
+      const movedBasis = from.depositedAmount.mul(half).div(from.shares);
+
+      expect(to.owner.toBase58()).to.equal(newWallet.publicKey.toBase58());
+      expect(to.shares.toString()).to.equal(half.toString());
+      expect(to.depositedAmount.toString()).to.equal(movedBasis.toString());
+      expect(fromAfter.shares.toString()).to.equal(from.shares.sub(half).toString());
+      expect(fromAfter.depositedAmount.toString()).to.equal(
+        from.depositedAmount.sub(movedBasis).toString()
+      );
+    });
+
+    it("rejects transferring shares to yourself", async () => {
+      const [fromPda] = getPositionPda(owner.publicKey);
+
+      try {
+        await program.methods
+          .transferPositionShares(0, owner.publicKey, 0, new anchor.BN(SHARES_PER_SOL / 10))
+          .accounts({
+            user: owner.publicKey,
+            vault: vaultPda,
+            fromPosition: fromPda,
+            toPosition: fromPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([owner])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("InvalidTransferTarget");
+      }
+    });
+
+    it("rejects withdrawal by a key that is not the delegate", async () => {
+      const [positionPda] = getPositionPda(owner.publicKey);
+
+      try {
+        await program.methods
+          .delegateWithdraw(0, new anchor.BN(SHARES_PER_SOL / 10), new anchor.BN(0))
+          .accounts({
+            delegate: delegate.publicKey,
+            owner: owner.publicKey,
+            vault: vaultPda,
+            position: positionPda,
+          })
+          .signers([delegate])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("Unauthorized");
+      }
+    });
+
+    it("delegate withdraws and proceeds go to the owner", async () => {
+      const [positionPda] = getPositionPda(owner.publicKey);
+
+      await program.methods
+        .setDelegate(0, delegate.publicKey)
+        .accounts({ user: owner.publicKey, position: positionPda })
+        .signers([owner])
+        .rpc();
+
+      const position = await program.account.userPosition.fetch(positionPda);
+      expect(position.delegate.toBase58()).to.equal(delegate.publicKey.toBase58());
+
+      const ownerBefore = await provider.connection.getBalance(owner.publicKey);
+      await program.methods
+        .delegateWithdraw(0, position.shares, new anchor.BN(0))
+        .accounts({
+          delegate: delegate.publicKey,
+          owner: owner.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+        })
+        .signers([delegate])
+        .rpc();
+      const ownerAfter = await provider.connection.getBalance(owner.publicKey);
+
+      // Owner paid no fee for this transaction, so the full payout shows up
+      expect(ownerAfter).to.be.greaterThan(ownerBefore + 0.9 * LAMPORTS_PER_SOL);
+
+      const after = await program.account.userPosition.fetch(positionPda);
+      expect(after.shares.toNumber()).to.equal(0);
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // SUB-POSITIONS (indexed positions per wallet)
+  // ─────────────────────────────────────────────────
+  describe("sub-positions", () => {
+    const user = Keypair.generate();
+
+    before(async () => {
+      await fundWallet(user, 5 * LAMPORTS_PER_SOL);
+    });
+
+    it("keeps separate indexed positions for the same wallet", async () => {
+      const [firstPda] = getPositionPda(user.publicKey, 0);
+      const [secondPda] = getPositionPda(user.publicKey, 1);
+
+      await program.methods
+        .deposit(0, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: firstPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+      await program.methods
+        .deposit(1, new anchor.BN(2 * LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: secondPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const first = await program.account.userPosition.fetch(firstPda);
+      const second = await program.account.userPosition.fetch(secondPda);
+      expect(first.index).to.equal(0);
+      expect(second.index).to.equal(1);
+      expect(first.depositedAmount.toNumber()).to.equal(LAMPORTS_PER_SOL);
+      expect(second.depositedAmount.toNumber()).to.equal(2 * LAMPORTS_PER_SOL);
+    });
+
+    it("moves shares between a wallet's own sub-positions", async () => {
+      const [fromPda] = getPositionPda(user.publicKey, 1);
+      const [toPda] = getPositionPda(user.publicKey, 2);
+      const from = await program.account.userPosition.fetch(fromPda);
+      const half = from.shares.div(new anchor.BN(2));
+
+      await program.methods
+        .transferPositionShares(1, user.publicKey, 2, half)
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          fromPosition: fromPda,
+          toPosition: toPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const to = await program.account.userPosition.fetch(toPda);
+      expect(to.owner.toBase58()).to.equal(user.publicKey.toBase58());
+      expect(to.index).to.equal(2);
+      expect(to.shares.toString()).to.equal(half.toString());
+    });
+
+    it("rejects migrating when no legacy position exists", async () => {
+      const [legacyPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("position"), user.publicKey.toBuffer()],
+        program.programId
+      );
+      const [positionPda] = getPositionPda(user.publicKey, 3);
+
+      try {
+        await program.methods
+          .migrateLegacyPosition(3)
+          .accounts({
+            user: user.publicKey,
+            legacyPosition: legacyPda,
+            position: positionPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([user])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("NotLegacyPosition");
+      }
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // TOKEN VAULTS
+  // ─────────────────────────────────────────────────
+  describe("token base asset", () => {
+    const BASE_DECIMALS = 6;
+    const USER_TOKENS = 100_000_000;
+    const AUTHORITY_TOKENS = 1_000_000;
+    // `total_deposited`: after the discriminator and six pubkeys
+    const TOTAL_DEPOSITED_OFFSET = 8 + 6 * 32;
+
+    // A bank with a base mint owned by `tokenProgram`, a vault-owned token account,
+    // and funded token accounts for the authority and a user
+    async function startTokenBank(
+      tokenProgram: PublicKey,
+      baseMintData = mintData(BASE_DECIMALS, 1_000_000_000)
+    ) {
+      const [user, userWallet] = bankWallet();
+      const baseMint = Keypair.generate().publicKey;
+      const vaultTokenAccount = Keypair.generate().publicKey;
+      const authorityTokenAccount = Keypair.generate().publicKey;
+      const userTokenAccount = Keypair.generate().publicKey;
+      const { context, bank } = await startBank([
+        userWallet,
+        bankAccount(baseMint, tokenProgram, baseMintData),
+        bankAccount(vaultTokenAccount, tokenProgram, tokenAccountData(baseMint, vaultPda, 0)),
+        bankAccount(
+          userTokenAccount,
+          tokenProgram,
+          tokenAccountData(baseMint, user.publicKey, USER_TOKENS)
+        ),
+      ]);
+      // The bank's payer is only known once it has started
+      context.setAccount(
+        authorityTokenAccount,
+        bankAccount(
+          authorityTokenAccount,
+          tokenProgram,
+          tokenAccountData(baseMint, context.payer.publicKey, AUTHORITY_TOKENS)
+        ).info
+      );
+      return {
+        context,
+        bank,
+        user,
+        baseMint,
+        vaultTokenAccount,
+        authorityTokenAccount,
+        userTokenAccount,
+        tokenProgram,
+      };
+    }
+
+    function initializeTokenVault(t, overrides = {}) {
+      return t.bank.methods.initialize(500, new anchor.BN(0), defaultAllocations).accounts({
+        authority: t.context.payer.publicKey,
+        vault: vaultPda,
+        registry: registryPda,
+        baseMint: t.baseMint,
+        vaultTokenAccount: t.vaultTokenAccount,
+        authorityTokenAccount: t.authorityTokenAccount,
+        tokenProgram: t.tokenProgram,
+        systemProgram: SystemProgram.programId,
+        ...overrides,
+      });
+    }
+
+    function userAccounts(t, overrides = {}) {
+      return {
+        user: t.user.publicKey,
+        vault: vaultPda,
+        position: getPositionPda(t.user.publicKey)[0],
+        baseMint: t.baseMint,
+        vaultTokenAccount: t.vaultTokenAccount,
+        userTokenAccount: t.userTokenAccount,
+        tokenProgram: t.tokenProgram,
+        systemProgram: SystemProgram.programId,
+        ...overrides,
+      };
+    }
+
+    // There's no harvest instruction yet, so yield is booked by hand: base asset
+    // arrives in the vault's token account and `total_deposited` rises with it
+    async function bookYield(t, amount: anchor.BN) {
+      const vaultAccount = await t.context.banksClient.getAccount(vaultPda);
+      const data = Buffer.from(vaultAccount.data);
+      const totalDeposited = new anchor.BN(
+        data.subarray(TOTAL_DEPOSITED_OFFSET, TOTAL_DEPOSITED_OFFSET + 8),
+        "le"
+      );
+      u64(totalDeposited.add(amount)).copy(data, TOTAL_DEPOSITED_OFFSET);
+      t.context.setAccount(vaultPda, { ...vaultAccount, data });
+
+      const tokenAccount = await t.context.banksClient.getAccount(t.vaultTokenAccount);
+      const tokenData = Buffer.from(tokenAccount.data);
+      const balance = await tokenBalance(t.context, t.vaultTokenAccount);
+      u64(balance.add(amount)).copy(tokenData, 64);
+      t.context.setAccount(t.vaultTokenAccount, { ...tokenAccount, data: tokenData });
+    }
+
+    const tokenPrograms: [string, PublicKey][] = [
+      ["SPL Token", TOKEN_PROGRAM_ID],
+      ["Token-2022", TOKEN_2022_PROGRAM_ID],
+    ];
+    for (const [name, tokenProgram] of tokenPrograms) {
+      it(`deposits, withdraws and collects fees in an ${name} base asset`, async () => {
+        const t = await startTokenBank(tokenProgram);
+        await initializeTokenVault(t).rpc();
+
+        let vault = await t.bank.account.vault.fetch(vaultPda);
+        expect(vault.baseMint.toBase58()).to.equal(t.baseMint.toBase58());
+        expect(vault.vaultTokenAccount.toBase58()).to.equal(t.vaultTokenAccount.toBase58());
+        expect(vault.tokenProgram.toBase58()).to.equal(tokenProgram.toBase58());
+        // The authority funds the dead shares in the base asset
+        expect((await tokenBalance(t.context, t.vaultTokenAccount)).toNumber()).to.equal(DEAD_SHARES);
+        expect((await tokenBalance(t.context, t.authorityTokenAccount)).toNumber()).to.equal(
+          AUTHORITY_TOKENS - DEAD_SHARES
+        );
+
+        const amount = new anchor.BN(50_000_000);
+        await t.bank.methods
+          .deposit(0, amount, new anchor.BN(0))
+          .accounts(userAccounts(t))
+          .signers([t.user])
+          .rpc();
+        expect((await tokenBalance(t.context, t.vaultTokenAccount)).toNumber()).to.equal(
+          DEAD_SHARES + amount.toNumber()
+        );
+        expect((await tokenBalance(t.context, t.userTokenAccount)).toNumber()).to.equal(
+          USER_TOKENS - amount.toNumber()
+        );
+        const [positionPda] = getPositionPda(t.user.publicKey);
+        let position = await t.bank.account.userPosition.fetch(positionPda);
+        expect(position.shares.toString()).to.equal(amount.toString());
+
+        // Yield on the deposit makes the exit pay a performance fee
+        await bookYield(t, new anchor.BN(10_000_000));
+        await nextSlot(t.context);
+        vault = await t.bank.account.vault.fetch(vaultPda);
+        position = await t.bank.account.userPosition.fetch(positionPda);
+        await t.bank.methods
+          .withdraw(0, position.shares)
+          .accounts(userAccounts(t))
+          .signers([t.user])
+          .rpc();
+
+        const gross = position.shares.mul(virtualAssets(vault)).div(virtualShares(vault));
+        const fee = gross.sub(position.depositedAmount).muln(500).divn(10_000);
+        expect(fee.toNumber()).to.be.greaterThan(0);
+        expect((await tokenBalance(t.context, t.userTokenAccount)).toString()).to.equal(
+          new anchor.BN(USER_TOKENS).sub(amount).add(gross).sub(fee).toString()
+        );
+        vault = await t.bank.account.vault.fetch(vaultPda);
+        expect(vault.accruedFees.toString()).to.equal(fee.toString());
+
+        await t.bank.methods
+          .collectFees()
+          .accounts({
+            authority: t.context.payer.publicKey,
+            vault: vaultPda,
+            baseMint: t.baseMint,
+            vaultTokenAccount: t.vaultTokenAccount,
+            authorityTokenAccount: t.authorityTokenAccount,
+            tokenProgram,
+          })
+          .rpc();
+        expect((await tokenBalance(t.context, t.authorityTokenAccount)).toString()).to.equal(
+          fee.addn(AUTHORITY_TOKENS - DEAD_SHARES).toString()
+        );
+        vault = await t.bank.account.vault.fetch(vaultPda);
+        expect(vault.accruedFees.toNumber()).to.equal(0);
+        // What's left backs the remaining NAV exactly
+        expect((await tokenBalance(t.context, t.vaultTokenAccount)).toString()).to.equal(
+          vault.totalDeposited.toString()
+        );
+      });
+    }
+
+    it("requires the vault's own token accounts", async () => {
+      const t = await startTokenBank(TOKEN_PROGRAM_ID);
+
+      // Initialize takes every token account or none
+      let logs = await bankFailure(t.context, initializeTokenVault(t, { authorityTokenAccount: null }));
+      expect(logs).to.contain("MissingTokenAccounts");
+      await initializeTokenVault(t).rpc();
+
+      // A deposit without them is refused, not taken in SOL
+      logs = await bankFailure(
+        t.context,
+        t.bank.methods.deposit(0, new anchor.BN(1_000_000), new anchor.BN(0)).accounts(
+          userAccounts(t, {
+            baseMint: null,
+            vaultTokenAccount: null,
+            userTokenAccount: null,
+            tokenProgram: null,
+          })
+        ),
+        [t.user]
+      );
+      expect(logs).to.contain("MissingTokenAccounts");
+
+      // A token account standing in for the vault's own
+      logs = await bankFailure(
+        t.context,
+        t.bank.methods
+          .deposit(0, new anchor.BN(1_000_000), new anchor.BN(0))
+          .accounts(userAccounts(t, { vaultTokenAccount: t.userTokenAccount })),
+        [t.user]
+      );
+      expect(logs).to.contain("InvalidTokenAccount");
+
+      // A user account holding some other mint
+      const otherMint = Keypair.generate().publicKey;
+      const otherAccount = Keypair.generate().publicKey;
+      t.context.setAccount(
+        otherMint,
+        bankAccount(otherMint, TOKEN_PROGRAM_ID, mintData(BASE_DECIMALS, 1_000_000_000)).info
+      );
+      t.context.setAccount(
+        otherAccount,
+        bankAccount(
+          otherAccount,
+          TOKEN_PROGRAM_ID,
+          tokenAccountData(otherMint, t.user.publicKey, USER_TOKENS)
+        ).info
+      );
+      logs = await bankFailure(
+        t.context,
+        t.bank.methods
+          .deposit(0, new anchor.BN(1_000_000), new anchor.BN(0))
+          .accounts(userAccounts(t, { userTokenAccount: otherAccount })),
+        [t.user]
+      );
+      expect(logs).to.contain("InvalidTokenAccount");
+    });
+
+    it("rejects a Token-2022 mint that charges a transfer fee", async () => {
+      // Base mint, padding up to the account-type byte, then a single TLV entry:
+      // TransferFeeConfig (type 1, 108 bytes) with no authorities and no fee set
+      const feeMint = Buffer.alloc(166 + 4 + 108);
+      mintData(BASE_DECIMALS, 1_000_000_000).copy(feeMint, 0);
+      feeMint[165] = 1; // AccountType::Mint
+      feeMint.writeUInt16LE(1, 166);
+      feeMint.writeUInt16LE(108, 168);
+
+      const t = await startTokenBank(TOKEN_2022_PROGRAM_ID, feeMint);
+      const logs = await bankFailure(t.context, initializeTokenVault(t));
+      expect(logs).to.contain("UnsupportedMint");
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // LST DEPOSITS
+  // ─────────────────────────────────────────────────
+  describe("deposit_lst", () => {
+    it("rejects an LST that was never approved", async () => {
+      const user = Keypair.generate();
+      await fundWallet(user, 1 * LAMPORTS_PER_SOL);
+
+      const mint = Keypair.generate().publicKey;
+      const [lstConfigPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("lst"), mint.toBuffer()],
+        program.programId
+      );
+      const [positionPda] = getPositionPda(user.publicKey);
+
+      try {
+        await program.methods
+          .depositLst(0, mint, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(0))
+          .accounts({
+            user: user.publicKey,
+            vault: vaultPda,
+            position: positionPda,
+            lstConfig: lstConfigPda,
+            lstMint: mint,
+            rateState: Keypair.generate().publicKey,
+            userLstAccount: Keypair.generate().publicKey,
+            vaultLstAccount: Keypair.generate().publicKey,
+            tokenProgram: new PublicKey("TokenkegQfeZyiNwAJbNbGNPDzAsLv6y3AgFAtfnpS"),
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([user])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("AccountNotInitialized");
+      }
+    });
+
+    // A stake pool whose rate was last refreshed in `epoch`
+    function stakePool(lamports: number, supply: number, epoch: anchor.BN | number = 0) {
+      return (mint: PublicKey) => stakePoolData(mint, lamports, supply, epoch);
+    }
+
+    function setRateState(context, lst, owner: PublicKey, data: Buffer) {
+      context.setAccount(lst.rateState, bankAccount(lst.rateState, owner, data).info);
+    }
+
+    const lstSources = [
+      {
+        name: "an SPL stake pool LST at total_lamports / pool_token_supply",
+        owner: STAKE_POOL_PROGRAM_ID,
+        rateData: stakePool(1_100, 1_000),
+        rateSource: { splStakePool: {} },
+        protocolId: 0,
+        // 2 tokens at 1.1
+        solValue: 2.2 * LAMPORTS_PER_SOL,
+      },
+      {
+        name: "a Marinade LST at msol_price",
+        owner: MARINADE_PROGRAM_ID,
+        rateData: (mint: PublicKey) =>
+          marinadeStateData(mint, MARINADE_PRICE_DENOMINATOR.muln(5).divn(4)),
+        rateSource: { marinade: {} },
+        protocolId: 1,
+        // 2 tokens at 1.25
+        solValue: 2.5 * LAMPORTS_PER_SOL,
+      },
+    ];
+    for (const source of lstSources) {
+      it(`books ${source.name}`, async () => {
+        const [user, userWallet] = bankWallet();
+        const lst = lstFixture(source.owner, source.rateData);
+        const amount = new anchor.BN(2 * LAMPORTS_PER_SOL);
+        const [userLst, userLstAccount] = lst.holder(user.publicKey, amount.toNumber());
+        const { context, bank } = await startBank([userWallet, ...lst.accounts, userLstAccount]);
+        await initializeBankVault(context, bank);
+        await registerBankLst(context, bank, lst, source.protocolId, source.rateSource);
+
+        const vaultBefore = await bank.account.vault.fetch(vaultPda);
+        await bank.methods
+          .depositLst(0, lst.mint, amount, new anchor.BN(0))
+          .accounts(lstAccounts(user, lst, userLst))
+          .signers([user])
+          .rpc();
+
+        const solValue = new anchor.BN(source.solValue);
+        const shares = solValue.mul(virtualShares(vaultBefore)).div(virtualAssets(vaultBefore));
+        const position = await bank.account.userPosition.fetch(getPositionPda(user.publicKey)[0]);
+        expect(position.shares.toString()).to.equal(shares.toString());
+        expect(position.depositedAmount.toString()).to.equal(solValue.toString());
+
+        // Booked straight into the LST's allocation, not the idle reserve
+        const vault = await bank.account.vault.fetch(vaultPda);
+        expect(vault.totalDeposited.toString()).to.equal(
+          vaultBefore.totalDeposited.add(solValue).toString()
+        );
+        expect(vault.totalShares.toString()).to.equal(vaultBefore.totalShares.add(shares).toString());
+        expect(vault.lstValue.toString()).to.equal(solValue.toString());
+        for (const [slot, allocation] of vault.allocations.slice(0, 5).entries()) {
+          const booked = slot === source.protocolId ? solValue : new anchor.BN(0);
+          expect(allocation.currentAmount.toString()).to.equal(
+            vaultBefore.allocations[slot].currentAmount.add(booked).toString()
+          );
+        }
+
+        const config = await bank.account.lstConfig.fetch(lst.lstConfig);
+        expect(config.lstBalance.toString()).to.equal(amount.toString());
+        expect(config.bookedValue.toString()).to.equal(solValue.toString());
+        expect((await tokenBalance(context, lst.vaultLstAccount)).toString()).to.equal(
+          amount.toString()
+        );
+        expect((await tokenBalance(context, userLst)).toNumber()).to.equal(0);
+      });
+    }
+
+    it("rejects rate accounts that don't price the mint", async () => {
+      const lst = lstFixture(STAKE_POOL_PROGRAM_ID, stakePool(1_100, 1_000));
+      const { context, bank } = await startBank(lst.accounts);
+      await initializeBankVault(context, bank);
+      const register = (rateSource) =>
+        bank.methods.registerLst(0, rateSource).accounts({
+          authority: context.payer.publicKey,
+          vault: vaultPda,
+          mint: lst.mint,
+          rateState: lst.rateState,
+          vaultLstAccount: lst.vaultLstAccount,
+          lstConfig: lst.lstConfig,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        });
+      const otherMint = Keypair.generate().publicKey;
+      const stakePoolSource = { splStakePool: {} };
+      const marinadeSource = { marinade: {} };
+      const cases: [string, PublicKey, Buffer, object][] = [
+        [
+          "stake pool for another mint",
+          STAKE_POOL_PROGRAM_ID,
+          stakePoolData(otherMint, 1_100, 1_000, 0),
+          stakePoolSource,
+        ],
+        [
+          "stake pool not owned by the stake pool program",
+          SystemProgram.programId,
+          stakePoolData(lst.mint, 1_100, 1_000, 0),
+          stakePoolSource,
+        ],
+        ["uninitialized stake pool", STAKE_POOL_PROGRAM_ID, Buffer.alloc(300), stakePoolSource],
+        [
+          "Marinade state for another mint",
+          MARINADE_PROGRAM_ID,
+          marinadeStateData(otherMint, MARINADE_PRICE_DENOMINATOR),
+          marinadeSource,
+        ],
+        [
+          "stake pool read as Marinade state",
+          STAKE_POOL_PROGRAM_ID,
+          stakePoolData(lst.mint, 1_100, 1_000, 0),
+          marinadeSource,
+        ],
+      ];
+      for (const [name, owner, data, rateSource] of cases) {
+        setRateState(context, lst, owner, data);
+        const logs = await bankFailure(context, register(rateSource));
+        expect(logs, name).to.contain("InvalidRateSource");
+      }
+    });
+
+    it("refuses a stake pool rate that wasn't refreshed this epoch", async () => {
+      const [user, userWallet] = bankWallet();
+      const lst = lstFixture(STAKE_POOL_PROGRAM_ID, stakePool(1_100, 1_000));
+      const [userLst, userLstAccount] = lst.holder(user.publicKey, 2 * LAMPORTS_PER_SOL);
+      const { context, bank } = await startBank([userWallet, ...lst.accounts, userLstAccount]);
+      await initializeBankVault(context, bank);
+      await registerBankLst(context, bank, lst, 0, { splStakePool: {} });
+
+      // A new epoch starts before the pool's update has run
+      const clock = await context.banksClient.getClock();
+      const epoch = clock.epoch + BigInt(1);
+      context.setClock(
+        new Clock(
+          clock.slot,
+          clock.epochStartTimestamp,
+          epoch,
+          clock.leaderScheduleEpoch,
+          clock.unixTimestamp
+        )
+      );
+      const deposit = () =>
+        bank.methods
+          .depositLst(0, lst.mint, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(0))
+          .accounts(lstAccounts(user, lst, userLst));
+      const logs = await bankFailure(context, deposit(), [user]);
+      expect(logs).to.contain("StaleExchangeRate");
+
+      // Once the pool is updated for the epoch, the same deposit goes through
+      setRateState(
+        context,
+        lst,
+        STAKE_POOL_PROGRAM_ID,
+        stakePoolData(lst.mint, 1_200, 1_000, new anchor.BN(epoch.toString()))
+      );
+      await deposit().signers([user]).rpc();
+      const config = await bank.account.lstConfig.fetch(lst.lstConfig);
+      expect(config.bookedValue.toNumber()).to.equal(1.2 * LAMPORTS_PER_SOL);
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // STAKE ACCOUNT DEPOSITS
+  // ─────────────────────────────────────────────────
+  describe("deposit_stake", () => {
+    it("rejects approving a validator that is not a vote account", async () => {
+      const notVote = Keypair.generate();
+      await fundWallet(notVote, 0.01 * LAMPORTS_PER_SOL);
+      const [validatorConfigPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("validator"), notVote.publicKey.toBuffer()],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .registerValidator(0)
+          .accounts({
+            authority: authority.publicKey,
+            vault: vaultPda,
+            voteAccount: notVote.publicKey,
+            validatorConfig: validatorConfigPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("InvalidStakeAccount");
+      }
+    });
+
+    const STAKE_PROGRAM_ID = new PublicKey("Stake11111111111111111111111111111111111111");
+    const VOTE_PROGRAM_ID = new PublicKey("Vote111111111111111111111111111111111111111");
+    const STAKE_HISTORY_ID = new PublicKey("SysvarStakeHistory1111111111111111111111111");
+    const SYSVAR_OWNER_ID = new PublicKey("Sysvar1111111111111111111111111111111111111");
+    // Rent-exempt minimum of a 200-byte stake account
+    const STAKE_RENT_EXEMPT_RESERVE = 2_282_880;
+    const DELEGATED = 5 * LAMPORTS_PER_SOL;
+    const EPOCH = 2;
+
+    // StakeStateV2::Stake with `owner` as staker and withdrawer, delegated to `voter`
+    function stakeAccountData(owner: PublicKey, voter: PublicKey, activationEpoch: number): Buffer {
+      const data = Buffer.alloc(200);
+      data.writeUInt32LE(2, 0); // StakeStateV2::Stake
+      u64(STAKE_RENT_EXEMPT_RESERVE).copy(data, 4);
+      owner.toBuffer().copy(data, 12); // staker
+      owner.toBuffer().copy(data, 44); // withdrawer
+      voter.toBuffer().copy(data, 124);
+      u64(DELEGATED).copy(data, 156);
+      u64(activationEpoch).copy(data, 164);
+      u64(new anchor.BN("18446744073709551615")).copy(data, 172); // never deactivated
+      data.writeDoubleLE(0.25, 180);
+      return data;
+    }
+
+    // StakeHistory sysvar: `[epoch, effective, activating, deactivating]`, newest first
+    function stakeHistoryData(entries: number[][]): Buffer {
+      const data = Buffer.alloc(8 + entries.length * 32);
+      u64(entries.length).copy(data, 0);
+      for (const [i, entry] of entries.entries()) {
+        entry.forEach((value, field) => u64(value).copy(data, 8 + i * 32 + field * 8));
+      }
+      return data;
+    }
+
+    // A bank at epoch `EPOCH` with an approved validator (protocol 0) and a user's
+    // stake account delegated to it since `activationEpoch`
+    async function startStakeBank(activationEpoch: number, history: number[][]) {
+      const [user, userWallet] = bankWallet();
+      const vote = Keypair.generate().publicKey;
+      const stakeAccount = Keypair.generate().publicKey;
+      const { context, bank } = await startBank([
+        userWallet,
+        bankAccount(vote, VOTE_PROGRAM_ID, Buffer.alloc(3762)),
+        bankAccount(
+          stakeAccount,
+          STAKE_PROGRAM_ID,
+          stakeAccountData(user.publicKey, vote, activationEpoch),
+          STAKE_RENT_EXEMPT_RESERVE + DELEGATED
+        ),
+      ]);
+      await initializeBankVault(context, bank);
+      const [validatorConfig] = PublicKey.findProgramAddressSync(
+        [Buffer.from("validator"), vote.toBuffer()],
+        program.programId
+      );
+      await bank.methods
+        .registerValidator(0)
+        .accounts({
+          authority: context.payer.publicKey,
+          vault: vaultPda,
+          voteAccount: vote,
+          validatorConfig,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+
+      const clock = await context.banksClient.getClock();
+      context.setClock(
+        new Clock(
+          clock.slot,
+          clock.epochStartTimestamp,
+          BigInt(EPOCH),
+          clock.leaderScheduleEpoch,
+          clock.unixTimestamp
+        )
+      );
+      context.setAccount(
+        STAKE_HISTORY_ID,
+        bankAccount(STAKE_HISTORY_ID, SYSVAR_OWNER_ID, stakeHistoryData(history)).info
+      );
+
+      const depositStake = bank.methods.depositStake(0, new anchor.BN(0)).accounts({
+        user: user.publicKey,
+        vault: vaultPda,
+        position: getPositionPda(user.publicKey)[0],
+        stakeAccount,
+        validatorConfig,
+        systemProgram: SystemProgram.programId,
+      });
+      return { context, bank, user, stakeAccount, validatorConfig, depositStake };
+    }
+
+    it("takes over a fully active stake account and books it to the validator's allocation", async () => {
+      // The cluster had nothing left warming up when the stake activated
+      const { context, bank, user, stakeAccount, validatorConfig, depositStake } =
+        await startStakeBank(EPOCH - 1, [[EPOCH - 1, 1_000 * LAMPORTS_PER_SOL, 0, 0]]);
+      const vaultBefore = await bank.account.vault.fetch(vaultPda);
+      await depositStake.signers([user]).rpc();
+
+      // Delegated stake plus the rent-exempt reserve
+      const solValue = new anchor.BN(DELEGATED + STAKE_RENT_EXEMPT_RESERVE);
+      const shares = solValue.mul(virtualShares(vaultBefore)).div(virtualAssets(vaultBefore));
+      const position = await bank.account.userPosition.fetch(getPositionPda(user.publicKey)[0]);
+      expect(position.shares.toString()).to.equal(shares.toString());
+
+      const vault = await bank.account.vault.fetch(vaultPda);
+      expect(vault.totalDeposited.toString()).to.equal(
+        vaultBefore.totalDeposited.add(solValue).toString()
+      );
+      expect(vault.stakeValue.toString()).to.equal(solValue.toString());
+      expect(vault.allocations[0].currentAmount.toString()).to.equal(
+        vaultBefore.allocations[0].currentAmount.add(solValue).toString()
+      );
+      const config = await bank.account.validatorConfig.fetch(validatorConfig);
+      expect(config.stakeLamports.toString()).to.equal(solValue.toString());
+      expect(config.stakeAccounts.toNumber()).to.equal(1);
+
+      // The vault PDA is now both stake and withdraw authority
+      const stake = Buffer.from((await context.banksClient.getAccount(stakeAccount)).data);
+      expect(new PublicKey(stake.subarray(12, 44)).toBase58()).to.equal(vaultPda.toBase58());
+      expect(new PublicKey(stake.subarray(44, 76)).toBase58()).to.equal(vaultPda.toBase58());
+    });
+
+    it("refuses stake the cluster-wide warmup limit hasn't fully activated", async () => {
+      // Activated last epoch, but a thousand times the effective stake was warming up
+      // with it, so only a sliver of this delegation is effective yet
+      const { context, user, depositStake } = await startStakeBank(EPOCH - 1, [
+        [EPOCH - 1, 1_000 * LAMPORTS_PER_SOL, 1_000_000 * LAMPORTS_PER_SOL, 0],
+      ]);
+      const logs = await bankFailure(context, depositStake, [user]);
+      expect(logs).to.contain("StakeNotActive");
+    });
+
+    it("refuses stake activated this epoch", async () => {
+      const { context, user, depositStake } = await startStakeBank(EPOCH, []);
+      const logs = await bankFailure(context, depositStake, [user]);
+      expect(logs).to.contain("StakeNotActive");
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // IN-KIND REDEMPTIONS
+  // ─────────────────────────────────────────────────
+  describe("withdraw_in_kind", () => {
+    it("pays a pro-rata slice of idle SOL when no LSTs are held", async () => {
+      const user = Keypair.generate();
+      await fundWallet(user, 3 * LAMPORTS_PER_SOL);
+      const [positionPda] = getPositionPda(user.publicKey);
+
+      await program.methods
+        .deposit(0, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const position = await program.account.userPosition.fetch(positionPda);
+      const balBefore = await provider.connection.getBalance(user.publicKey);
+
+      await program.methods
+        .withdrawInKind(0, position.shares)
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          tokenProgram: new PublicKey("TokenkegQfeZyiNwAJbNbGNPDzAsLv6y3AgFAtfnpS"),
+        })
+        .signers([user])
+        .rpc();
+
+      const after = await program.account.userPosition.fetch(positionPda);
+      expect(after.shares.toNumber()).to.equal(0);
+      const balAfter = await provider.connection.getBalance(user.publicKey);
+      expect(balAfter).to.be.greaterThan(balBefore + 0.9 * LAMPORTS_PER_SOL);
+    });
+
+    // mSOL at 1.25 SOL, approved for the Marinade allocation (protocol 1, slot 1).
+    // The bank vault charges no performance fee, so every exit nets its gross value.
+    const MSOL_PRICE = MARINADE_PRICE_DENOMINATOR.muln(5).divn(4);
+
+    async function startMsolBank(wallets: BankAccount[], userTokens: number, owner: PublicKey) {
+      const msol = lstFixture(MARINADE_PROGRAM_ID, (mint) => marinadeStateData(mint, MSOL_PRICE));
+      const [userLst, userLstAccount] = msol.holder(owner, userTokens);
+      const { context, bank } = await startBank([...wallets, ...msol.accounts, userLstAccount]);
+      await initializeBankVault(context, bank, 0);
+      await registerBankLst(context, bank, msol, 1, { marinade: {} });
+      return { context, bank, msol, userLst };
+    }
+
+    it("pays withdrawAs in the LST and books it out at its deposit-time value", async () => {
+      const [user, userWallet] = bankWallet();
+      const { context, bank, msol, userLst } = await startMsolBank(
+        [userWallet],
+        2 * LAMPORTS_PER_SOL,
+        user.publicKey
+      );
+      const accounts = lstAccounts(user, msol, userLst);
+      await bank.methods
+        .depositLst(0, msol.mint, new anchor.BN(2 * LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts(accounts)
+        .signers([user])
+        .rpc();
+
+      // mSOL appreciates to 1.5 SOL after the deposit was booked at 1.25
+      const newPrice = MARINADE_PRICE_DENOMINATOR.muln(3).divn(2);
+      context.setAccount(
+        msol.rateState,
+        bankAccount(msol.rateState, MARINADE_PROGRAM_ID, marinadeStateData(msol.mint, newPrice)).info
+      );
+      await nextSlot(context);
+
+      const vaultBefore = await bank.account.vault.fetch(vaultPda);
+      const configBefore = await bank.account.lstConfig.fetch(msol.lstConfig);
+      const shares = new anchor.BN(SHARES_PER_SOL);
+      await bank.methods
+        .withdrawAs(0, msol.mint, shares, new anchor.BN(0))
+        .accounts(accounts)
+        .signers([user])
+        .rpc();
+
+      // Paid at the current rate...
+      const net = shares.mul(virtualAssets(vaultBefore)).div(virtualShares(vaultBefore));
+      const lstOut = net.mul(MARINADE_PRICE_DENOMINATOR).div(newPrice);
+      expect((await tokenBalance(context, userLst)).toString()).to.equal(lstOut.toString());
+
+      // ...but taken off the books at the rate it was booked at
+      const booked = configBefore.bookedValue.mul(lstOut).div(configBefore.lstBalance);
+      expect(booked.lt(net)).to.equal(true);
+      const config = await bank.account.lstConfig.fetch(msol.lstConfig);
+      expect(config.lstBalance.toString()).to.equal(configBefore.lstBalance.sub(lstOut).toString());
+      expect(config.bookedValue.toString()).to.equal(configBefore.bookedValue.sub(booked).toString());
+      const vault = await bank.account.vault.fetch(vaultPda);
+      expect(vault.lstValue.toString()).to.equal(vaultBefore.lstValue.sub(booked).toString());
+      expect(vault.lstValue.toString()).to.equal(config.bookedValue.toString());
+      expect(vault.allocations[1].currentAmount.toString()).to.equal(
+        vaultBefore.allocations[1].currentAmount.sub(booked).toString()
+      );
+      expect(vault.totalDeposited.toString()).to.equal(vaultBefore.totalDeposited.sub(net).toString());
+    });
+
+    it("pays in kind from booked SOL and the LSTs passed, leaving the rest on the books", async () => {
+      const [lstUser, lstUserWallet] = bankWallet();
+      const [solUser, solUserWallet] = bankWallet();
+      const [donor, donorWallet] = bankWallet(20 * LAMPORTS_PER_SOL);
+      const { context, bank, msol, userLst } = await startMsolBank(
+        [lstUserWallet, solUserWallet, donorWallet],
+        2 * LAMPORTS_PER_SOL,
+        lstUser.publicKey
+      );
+      await bank.methods
+        .depositLst(0, msol.mint, new anchor.BN(2 * LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts(lstAccounts(lstUser, msol, userLst))
+        .signers([lstUser])
+        .rpc();
+      await bank.methods
+        .deposit(0, new anchor.BN(2.5 * LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts({
+          user: solUser.publicKey,
+          vault: vaultPda,
+          position: getPositionPda(solUser.publicKey)[0],
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([solUser])
+        .rpc();
+      // Untracked lamports no share is backed by
+      await bank.provider.sendAndConfirm(
+        new Transaction().add(
+          SystemProgram.transfer({
+            fromPubkey: donor.publicKey,
+            toPubkey: vaultPda,
+            lamports: 5 * LAMPORTS_PER_SOL,
+          })
+        ),
+        [donor]
+      );
+      await nextSlot(context);
+
+      const [positionPda] = getPositionPda(lstUser.publicKey);
+      const lamportsOf = async (address: PublicKey) =>
+        (await context.banksClient.getAccount(address)).lamports;
+      const withdrawInKind = (shares: anchor.BN, remainingAccounts) =>
+        bank.methods
+          .withdrawInKind(0, shares)
+          .accounts({
+            user: lstUser.publicKey,
+            vault: vaultPda,
+            position: positionPda,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .remainingAccounts(remainingAccounts)
+          .signers([lstUser])
+          .rpc();
+
+      // Half the position, with the mSOL passed
+      let vaultBefore = await bank.account.vault.fetch(vaultPda);
+      const configBefore = await bank.account.lstConfig.fetch(msol.lstConfig);
+      let position = await bank.account.userPosition.fetch(positionPda);
+      let shares = position.shares.divn(2);
+      let userLamports = await lamportsOf(lstUser.publicKey);
+      let vaultLamports = await lamportsOf(vaultPda);
+      await withdrawInKind(shares, [
+        { pubkey: msol.lstConfig, isSigner: false, isWritable: true },
+        { pubkey: msol.mint, isSigner: false, isWritable: false },
+        { pubkey: msol.vaultLstAccount, isSigner: false, isWritable: true },
+        { pubkey: userLst, isSigner: false, isWritable: true },
+      ]);
+
+      // Priced like any other exit: the shares' value over NAV, from the books
+      let nav = vaultBefore.totalDeposited;
+      let net = shares.mul(virtualAssets(vaultBefore)).div(virtualShares(vaultBefore));
+      let solOut = nav.sub(vaultBefore.lstValue).mul(net).div(nav);
+      const lstOut = configBefore.lstBalance.mul(net).div(nav);
+      const booked = configBefore.bookedValue.mul(lstOut).div(configBefore.lstBalance);
+      let forfeited = vaultBefore.lstValue.mul(net).div(nav).sub(booked);
+
+      expect(await lamportsOf(lstUser.publicKey)).to.equal(userLamports + solOut.toNumber());
+      // The donation stays put
+      expect(await lamportsOf(vaultPda)).to.equal(vaultLamports - solOut.toNumber());
+      expect((await tokenBalance(context, userLst)).toString()).to.equal(lstOut.toString());
+      const config = await bank.account.lstConfig.fetch(msol.lstConfig);
+      expect(config.lstBalance.toString()).to.equal(configBefore.lstBalance.sub(lstOut).toString());
+      expect(config.bookedValue.toString()).to.equal(configBefore.bookedValue.sub(booked).toString());
+      let vault = await bank.account.vault.fetch(vaultPda);
+      expect(vault.lstValue.toString()).to.equal(vaultBefore.lstValue.sub(booked).toString());
+      expect(vault.totalDeposited.toString()).to.equal(
+        nav.sub(net.sub(forfeited)).toString()
+      );
+
+      // Half the rest, without the mSOL: it isn't written down, and its slice
+      // stays in NAV for the other holders
+      vaultBefore = vault;
+      position = await bank.account.userPosition.fetch(positionPda);
+      shares = position.shares.divn(2);
+      userLamports = await lamportsOf(lstUser.publicKey);
+      await withdrawInKind(shares, []);
+
+      nav = vaultBefore.totalDeposited;
+      net = shares.mul(virtualAssets(vaultBefore)).div(virtualShares(vaultBefore));
+      solOut = nav.sub(vaultBefore.lstValue).mul(net).div(nav);
+      forfeited = vaultBefore.lstValue.mul(net).div(nav);
+      expect(await lamportsOf(lstUser.publicKey)).to.equal(userLamports + solOut.toNumber());
+      vault = await bank.account.vault.fetch(vaultPda);
+      expect(vault.lstValue.toString()).to.equal(vaultBefore.lstValue.toString());
+      expect(vault.totalDeposited.toString()).to.equal(
+        nav.sub(net.sub(forfeited)).toString()
+      );
+      const untouched = await bank.account.lstConfig.fetch(msol.lstConfig);
+      expect(untouched.lstBalance.toString()).to.equal(config.lstBalance.toString());
+      expect(untouched.bookedValue.toString()).to.equal(config.bookedValue.toString());
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // COMPUTE BUDGET
+  // ─────────────────────────────────────────────────
+  describe("compute budget", () => {
+    // The borsh vault deserialized and re-serialized its whole allocation table on
+    // every deposit and withdrawal, so their cost grew with it. Loaded zero-copy,
+    // they read fields in place: measured in two banks that differ only in how
+    // many allocation slots are active, the cost is the same.
+    const MAX_HOT_PATH_UNITS = 30_000;
+    // `num_allocations`: discriminator, six pubkeys, eleven u64s, then the fee
+    // bps, `is_paused` and `async_mode`
+    const NUM_ALLOCATIONS_OFFSET = 8 + 6 * 32 + 11 * 8 + 2 + 1 + 1;
+    // Same user in both banks, so the position PDA and its bump search match
+    const user = Keypair.generate();
+
+    async function unitsConsumed(context, method, signers: Keypair[]): Promise<number> {
+      const tx = await bankTransaction(context, method, signers);
+      const meta = await context.banksClient.processTransaction(tx);
+      return Number(meta.computeUnitsConsumed);
+    }
+
+    async function hotPathUnits(numAllocations: number) {
+      const { context, bank } = await startBank([
+        bankAccount(user.publicKey, SystemProgram.programId, Buffer.alloc(0), 10 * LAMPORTS_PER_SOL),
+      ]);
+      await initializeBankVault(context, bank);
+      const vaultAccount = await context.banksClient.getAccount(vaultPda);
+      const data = Buffer.from(vaultAccount.data);
+      data[NUM_ALLOCATIONS_OFFSET] = numAllocations;
+      context.setAccount(vaultPda, { ...vaultAccount, data });
+
+      const [positionPda] = getPositionPda(user.publicKey);
+      const accounts = {
+        user: user.publicKey,
+        vault: vaultPda,
+        position: positionPda,
+        systemProgram: SystemProgram.programId,
+      };
+      // First deposit pays for creating the position; measure a top-up instead
+      await bank.methods
+        .deposit(0, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts(accounts)
+        .signers([user])
+        .rpc();
+      const deposit = await unitsConsumed(
+        context,
+        bank.methods
+          .deposit(0, new anchor.BN(2 * LAMPORTS_PER_SOL), new anchor.BN(0))
+          .accounts(accounts),
+        [user]
+      );
+
+      await nextSlot(context);
+      const position = await bank.account.userPosition.fetch(positionPda);
+      const withdraw = await unitsConsumed(
+        context,
+        bank.methods.withdraw(0, position.shares).accounts(accounts),
+        [user]
+      );
+      return { deposit, withdraw };
+    }
+
+    it("keeps deposit and withdraw cost flat as the allocation table fills", async () => {
+      const few = await hotPathUnits(defaultAllocations.length);
+      const full = await hotPathUnits(64);
+
+      expect(full.deposit).to.equal(few.deposit);
+      expect(full.withdraw).to.equal(few.withdraw);
+      expect(full.deposit).to.be.below(MAX_HOT_PATH_UNITS);
+      expect(full.withdraw).to.be.below(MAX_HOT_PATH_UNITS);
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // ACCOUNT MIGRATIONS
+  // ─────────────────────────────────────────────────
+  describe("account migrations", () => {
+    // Preloaded by Anchor.toml from tests/fixtures/v1-position.json: a position
+    // serialized in the pre-versioning layout (no `version` or `reserved` bytes)
+    const v1Owner = Keypair.fromSecretKey(
+      Uint8Array.from(v1PositionOwner)
+    );
+
+    it("upgrades a v1 position in place", async () => {
+      await fundWallet(v1Owner, 1 * LAMPORTS_PER_SOL);
+      const [positionPda] = getPositionPda(v1Owner.publicKey);
+
+      const before = await provider.connection.getAccountInfo(positionPda);
+      expect(before.data.length).to.equal(99);
+
+      await program.methods
+        .migratePosition(0)
+        .accounts({
+          user: v1Owner.publicKey,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([v1Owner])
+        .rpc();
+
+      const after = await provider.connection.getAccountInfo(positionPda);
+      expect(after.data.length).to.be.greaterThan(99);
+      const rent = await provider.connection.getMinimumBalanceForRentExemption(
+        after.data.length
+      );
+      expect(after.lamports).to.be.at.least(rent);
+
+      const position = await program.account.userPosition.fetch(positionPda);
+      expect(position.version).to.equal(2);
+      expect(position.owner.toBase58()).to.equal(v1Owner.publicKey.toBase58());
+      expect(position.shares.toNumber()).to.equal(LAMPORTS_PER_SOL);
+      expect(position.depositedAmount.toNumber()).to.equal(LAMPORTS_PER_SOL);
+      expect(position.lastDepositTs.toNumber()).to.equal(1_700_000_000);
+      expect(position.index).to.equal(0);
+    });
+
+    it("rejects migrating a position twice", async () => {
+      const [positionPda] = getPositionPda(v1Owner.publicKey);
+      try {
+        await program.methods
+          .migratePosition(0)
+          .accounts({
+            user: v1Owner.publicKey,
+            position: positionPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([v1Owner])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("AlreadyMigrated");
+      }
+    });
+
+    it("rejects migrating a vault already on the current layout", async () => {
+      const vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.version).to.equal(5);
+
+      try {
+        await program.methods
+          .migrateVault()
+          .accounts({
+            authority: authority.publicKey,
+            vault: vaultPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("AlreadyMigrated");
+      }
+    });
+
+    it("rejects migrating someone else's position", async () => {
+      const rando = Keypair.generate();
+      await fundWallet(rando, 1 * LAMPORTS_PER_SOL);
+      const [positionPda] = getPositionPda(rando.publicKey);
+      try {
+        await program.methods
+          .migratePosition(0)
+          .accounts({
+            user: rando.publicKey,
+            position: positionPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([rando])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("AccountOwnedByWrongProgram");
+      }
+    });
+
+    // Legacy vaults live at the vault PDA this suite initialises, so each fixture
+    // is migrated in its own bankrun bank rather than on the shared validator.
+    // Every fixture holds the same values, serialized in its version's layout.
+    const legacyVaultOwner = Keypair.fromSecretKey(
+      Uint8Array.from(legacyVaultAuthority)
+    );
+
+    async function migrateVaultFixture(fixture) {
+      const context = await startAnchor(".", [], [
+        {
+          address: new PublicKey(fixture.pubkey),
+          info: {
+            lamports: fixture.account.lamports,
+            data: Buffer.from(fixture.account.data[0], "base64"),
+            owner: new PublicKey(fixture.account.owner),
+            executable: false,
+          },
+        },
+        {
+          address: legacyVaultOwner.publicKey,
+          info: {
+            lamports: 10 * LAMPORTS_PER_SOL,
+            data: Buffer.alloc(0),
+            owner: SystemProgram.programId,
+            executable: false,
+          },
+        },
+      ]);
+      const bankrun = new Program<Solvault>(program.idl, new BankrunProvider(context));
+
+      await bankrun.methods
+        .migrateVault()
+        .accounts({
+          authority: legacyVaultOwner.publicKey,
+          vault: vaultPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([legacyVaultOwner])
+        .rpc();
+
+      // Grown to the current layout, the same size as the vault initialised above
+      const migrated = await context.banksClient.getAccount(vaultPda);
+      const current = await provider.connection.getAccountInfo(vaultPda);
+      expect(migrated.data.length).to.equal(current.data.length);
+      return bankrun.account.vault.fetch(vaultPda);
+    }
+
+    function expectLegacyVaultFields(vault) {
+      const [, bump] = PublicKey.findProgramAddressSync(
+        [Buffer.from("vault")],
+        program.programId
+      );
+      expect(vault.version).to.equal(5);
+      expect(vault.authority.toBase58()).to.equal(legacyVaultOwner.publicKey.toBase58());
+      expect(vault.pendingAuthority.toBase58()).to.equal(
+        new PublicKey(Buffer.alloc(32, 1)).toBase58()
+      );
+      expect(vault.keeper.toBase58()).to.equal(new PublicKey(Buffer.alloc(32, 2)).toBase58());
+      expect(vault.baseMint.toBase58()).to.equal(PublicKey.default.toBase58());
+      expect(vault.vaultTokenAccount.toBase58()).to.equal(PublicKey.default.toBase58());
+      expect(vault.tokenProgram.toBase58()).to.equal(PublicKey.default.toBase58());
+      expect(vault.totalDeposited.toNumber()).to.equal(5_000_000_000);
+      expect(vault.totalShares.toNumber()).to.equal(4_000_000_000);
+      expect(vault.depositCap.toNumber()).to.equal(100_000_000_000);
+      expect(vault.accruedFees.toNumber()).to.equal(12_345);
+      expect(vault.lastRebalanceTs.toNumber()).to.equal(1_700_000_000);
+      expect(vault.depositorCount.toNumber()).to.equal(7);
+      expect(vault.currentEpoch.toNumber()).to.equal(3);
+      expect(vault.pendingDeposits.toNumber()).to.equal(1_000_000);
+      expect(vault.pendingRedemptions.toNumber()).to.equal(2_000_000);
+      expect(vault.lstValue.toNumber()).to.equal(300_000_000);
+      expect(vault.stakeValue.toNumber()).to.equal(200_000_000);
+      expect(vault.performanceFeeBps).to.equal(1_000);
+      expect(vault.isPaused).to.equal(1);
+      expect(vault.asyncMode).to.equal(1);
+      expect(vault.bump).to.equal(bump);
+      // Targets come out in bps (v1-v3 stored 60% / 40%), amounts unchanged
+      expect(vault.numAllocations).to.equal(2);
+      expect(
+        vault.allocations
+          .slice(0, 3)
+          .map((a) => [a.protocolId, a.targetBps, a.currentAmount.toNumber()])
+      ).to.deep.equal([
+        [0, 6000, 3_000_000_000],
+        [3, 4000, 2_000_000_000],
+        [0, 0, 0],
+      ]);
+      expect(vault.allocations[0].target.toBase58()).to.equal(PublicKey.default.toBase58());
+      expect(vault.allocations[0].paramsLen).to.equal(0);
+    }
+
+    it("upgrades a v1 (unversioned borsh) vault, keeping every field", async () => {
+      expectLegacyVaultFields(await migrateVaultFixture(v1Vault));
+    });
+
+    it("upgrades a v2 borsh vault, keeping every field", async () => {
+      expectLegacyVaultFields(await migrateVaultFixture(v2Vault));
+    });
+
+    it("upgrades a v3 zero-copy vault with percentage targets", async () => {
+      expect(Buffer.from(v3Vault.account.data[0], "base64").length).to.equal(1576);
+      expectLegacyVaultFields(await migrateVaultFixture(v3Vault));
+    });
+
+    it("upgrades a v4 zero-copy vault with narrow allocation slots", async () => {
+      expect(Buffer.from(v4Vault.account.data[0], "base64").length).to.equal(1576);
+      expectLegacyVaultFields(await migrateVaultFixture(v4Vault));
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // EPOCH BATCHES (async deposits / redemptions)
+  // ─────────────────────────────────────────────────
+  describe("epoch batches", () => {
+    const keeper = Keypair.generate();
+    const user = Keypair.generate();
+
+    before(async () => {
+      await fundWallet(keeper, 1 * LAMPORTS_PER_SOL);
+      await fundWallet(user, 5 * LAMPORTS_PER_SOL);
+    });
+
+    it("rejects batch requests when async mode is disabled", async () => {
+      const vault = await program.account.vault.fetch(vaultPda);
+      const [batchPda] = getBatchPda(vault.currentEpoch);
+      const [ticketPda] = getTicketPda(vault.currentEpoch, user.publicKey);
+
+      try {
+        await program.methods
+          .requestDeposit(new anchor.BN(LAMPORTS_PER_SOL))
+          .accounts({
+            user: user.publicKey,
+            vault: vaultPda,
+            batch: batchPda,
+            ticket: ticketPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([user])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("AsyncModeDisabled");
+      }
+    });
+
+    it("enables async mode and blocks instant deposits", async () => {
+      await program.methods
+        .updateEpochConfig(true, keeper.publicKey)
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+
+      const vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.asyncMode).to.equal(1);
+      expect(vault.keeper.toBase58()).to.equal(keeper.publicKey.toBase58());
+
+      const [positionPda] = getPositionPda(user.publicKey);
+      try {
+        await program.methods
+          .deposit(0, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(0))
+          .accounts({
+            user: user.publicKey,
+            vault: vaultPda,
+            position: positionPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([user])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("AsyncModeEnabled");
+      }
+      try {
+        await program.methods
+          .previewDeposit(new anchor.BN(LAMPORTS_PER_SOL))
+          .accounts({ vault: vaultPda })
+          .view();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("AsyncModeEnabled");
+      }
+    });
+
+    it("settles queued deposits at a single share price", async () => {
+      const vaultBefore = await program.account.vault.fetch(vaultPda);
+      const epoch = vaultBefore.currentEpoch;
+      const [batchPda] = getBatchPda(epoch);
+      const [ticketPda] = getTicketPda(epoch, user.publicKey);
+      const [positionPda] = getPositionPda(user.publicKey);
+      const amount = new anchor.BN(2 * LAMPORTS_PER_SOL);
+
+      await program.methods
+        .requestDeposit(amount)
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          batch: batchPda,
+          ticket: ticketPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const vaultQueued = await program.account.vault.fetch(vaultPda);
+      expect(vaultQueued.pendingDeposits.toNumber()).to.equal(amount.toNumber());
+      // Queued lamports are not part of NAV until settlement
+      expect(vaultQueued.totalDeposited.toNumber()).to.equal(
+        vaultBefore.totalDeposited.toNumber()
+      );
+
+      // Claiming before settlement fails
+      try {
+        await program.methods
+          .claimBatch(epoch, 0)
+          .accounts({
+            user: user.publicKey,
+            vault: vaultPda,
+            batch: batchPda,
+            ticket: ticketPda,
+            position: positionPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([user])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("EpochNotSettled");
+      }
+
+      // Keeper closes the epoch
+      await program.methods
+        .closeEpoch()
+        .accounts({
+          caller: keeper.publicKey,
+          vault: vaultPda,
+          batch: batchPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([keeper])
+        .rpc();
+
+      const batch = await program.account.epochBatch.fetch(batchPda);
+      expect(batch.isSettled).to.equal(true);
+      const expectedShares = amount
+        .mul(virtualShares(vaultBefore))
+        .div(virtualAssets(vaultBefore));
+      expect(batch.sharesMinted.toString()).to.equal(expectedShares.toString());
+
+      const vaultSettled = await program.account.vault.fetch(vaultPda);
+      expect(vaultSettled.currentEpoch.toNumber()).to.equal(epoch.toNumber() + 1);
+      expect(vaultSettled.pendingDeposits.toNumber()).to.equal(0);
+
+      // Shares have to land in a position
+      try {
+        await program.methods
+          .claimBatch(epoch, 0)
+          .accounts({
+            user: user.publicKey,
+            vault: vaultPda,
+            batch: batchPda,
+            ticket: ticketPda,
+            position: null,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([user])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("PositionRequired");
+      }
+
+      await program.methods
+        .claimBatch(epoch, 0)
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          batch: batchPda,
+          ticket: ticketPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const position = await program.account.userPosition.fetch(positionPda);
+      expect(position.shares.toString()).to.equal(expectedShares.toString());
+      expect(position.depositedAmount.toNumber()).to.equal(amount.toNumber());
+
+      const ticketAccount = await provider.connection.getAccountInfo(ticketPda);
+      expect(ticketAccount).to.be.null;
+      // Its only ticket is claimed, so the batch is closed too
+      expect(await provider.connection.getAccountInfo(batchPda)).to.be.null;
+    });
+
+    it("settles queued redemptions and pays out on claim", async () => {
+      const vaultBefore = await program.account.vault.fetch(vaultPda);
+      const epoch = vaultBefore.currentEpoch;
+      const [batchPda] = getBatchPda(epoch);
+      const [ticketPda] = getTicketPda(epoch, user.publicKey);
+      const [positionPda] = getPositionPda(user.publicKey);
+      const position = await program.account.userPosition.fetch(positionPda);
+
+      await program.methods
+        .requestWithdraw(0, position.shares)
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          batch: batchPda,
+          ticket: ticketPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const escrowed = await program.account.userPosition.fetch(positionPda);
+      expect(escrowed.shares.toNumber()).to.equal(0);
+
+      await program.methods
+        .closeEpoch()
+        .accounts({
+          caller: authority.publicKey,
+          vault: vaultPda,
+          batch: batchPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+
+      const vaultSettled = await program.account.vault.fetch(vaultPda);
+      const batch = await program.account.epochBatch.fetch(batchPda);
+      expect(vaultSettled.pendingRedemptions.toString()).to.equal(
+        batch.lamportsOwed.toString()
+      );
+
+      // Redemption-only tickets claim without a position, so no rent is paid
+      // for an empty one
+      const [unusedPositionPda] = getPositionPda(user.publicKey, 1);
+      const balBefore = await provider.connection.getBalance(user.publicKey);
+      await program.methods
+        .claimBatch(epoch, 1)
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          batch: batchPda,
+          ticket: ticketPda,
+          position: null,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const balAfter = await provider.connection.getBalance(user.publicKey);
+      expect(balAfter).to.be.greaterThan(balBefore + 1.9 * LAMPORTS_PER_SOL);
+      expect(await provider.connection.getAccountInfo(unusedPositionPda)).to.be
+        .null;
+
+      const vaultClaimed = await program.account.vault.fetch(vaultPda);
+      expect(vaultClaimed.pendingRedemptions.toNumber()).to.equal(0);
+      expect(await provider.connection.getAccountInfo(batchPda)).to.be.null;
+    });
+
+    it("keeps a batch open until its last ticket is claimed", async () => {
+      const other = Keypair.generate();
+      await fundWallet(other, 2 * LAMPORTS_PER_SOL);
+      const { currentEpoch: epoch } = await program.account.vault.fetch(vaultPda);
+      const [batchPda] = getBatchPda(epoch);
+      const claimAccounts = (owner: Keypair) => ({
+        user: owner.publicKey,
+        vault: vaultPda,
+        batch: batchPda,
+        ticket: getTicketPda(epoch, owner.publicKey)[0],
+        position: getPositionPda(owner.publicKey)[0],
+        systemProgram: SystemProgram.programId,
+      });
+
+      // An amount that doesn't divide the minted shares evenly
+      const amount = new anchor.BN(Math.floor(LAMPORTS_PER_SOL / 3));
+      for (const owner of [user, other]) {
+        await program.methods
+          .requestDeposit(amount)
+          .accounts(claimAccounts(owner))
+          .signers([owner])
+          .rpc();
+      }
+      await program.methods
+        .closeEpoch()
+        .accounts({
+          caller: authority.publicKey,
+          vault: vaultPda,
+          batch: batchPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      const settled = await program.account.epochBatch.fetch(batchPda);
+
+      await program.methods
+        .claimBatch(epoch, 0)
+        .accounts(claimAccounts(user))
+        .signers([user])
+        .rpc();
+      const { shares: userShares } = await program.account.userPosition.fetch(
+        getPositionPda(user.publicKey)[0]
+      );
+      const open = await program.account.epochBatch.fetch(batchPda);
+      expect(open.depositLamports.toString()).to.equal(amount.toString());
+      expect(open.sharesMinted.toString()).to.equal(
+        settled.sharesMinted.sub(userShares).toString()
+      );
+
+      // The last claim takes what rounding left in the batch and closes it
+      await program.methods
+        .claimBatch(epoch, 0)
+        .accounts(claimAccounts(other))
+        .signers([other])
+        .rpc();
+      const otherPosition = await program.account.userPosition.fetch(
+        getPositionPda(other.publicKey)[0]
+      );
+      expect(otherPosition.shares.toString()).to.equal(open.sharesMinted.toString());
+      expect(await provider.connection.getAccountInfo(batchPda)).to.be.null;
+
+      // Claimed shares are freshly minted, so they can't be queued out within the delay
+      await program.methods
+        .updateConfig(null, null, null, new anchor.BN(1_000_000))
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+      const { currentEpoch } = await program.account.vault.fetch(vaultPda);
+      try {
+        await program.methods
+          .requestWithdraw(0, otherPosition.shares)
+          .accounts({
+            user: other.publicKey,
+            vault: vaultPda,
+            position: getPositionPda(other.publicKey)[0],
+            batch: getBatchPda(currentEpoch)[0],
+            ticket: getTicketPda(currentEpoch, other.publicKey)[0],
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([other])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("WithdrawalTooSoon");
+      }
+      await program.methods
+        .updateConfig(null, null, null, new anchor.BN(0))
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+    });
+
+    it("closes an epoch with no requests without leaving a batch behind", async () => {
+      const { currentEpoch: epoch } = await program.account.vault.fetch(vaultPda);
+      const [batchPda] = getBatchPda(epoch);
+      await program.methods
+        .closeEpoch()
+        .accounts({
+          caller: keeper.publicKey,
+          vault: vaultPda,
+          batch: batchPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([keeper])
+        .rpc();
+
+      const vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.currentEpoch.toNumber()).to.equal(epoch.toNumber() + 1);
+      expect(await provider.connection.getAccountInfo(batchPda)).to.be.null;
+    });
+
+    it("rejects close_epoch from non-authority, non-keeper", async () => {
+      const rando = Keypair.generate();
+      await fundWallet(rando, 1 * LAMPORTS_PER_SOL);
+      const vault = await program.account.vault.fetch(vaultPda);
+      const [batchPda] = getBatchPda(vault.currentEpoch);
+
+      try {
+        await program.methods
+          .closeEpoch()
+          .accounts({
+            caller: rando.publicKey,
+            vault: vaultPda,
+            batch: batchPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([rando])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("Unauthorized");
+      }
+    });
+
+    after(async () => {
+      await program.methods
+        .updateEpochConfig(false, PublicKey.default)
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // YIELD FEED
+  // ─────────────────────────────────────────────────
+  describe("yield feed", () => {
+    const reporter = Keypair.generate();
+    let feedPda: PublicKey;
+
+    const reports = [
+      { protocolId: 0, apyBps: 780, tvl: new anchor.BN(9_000_000 * LAMPORTS_PER_SOL) },
+      { protocolId: 1, apyBps: 710, tvl: new anchor.BN(7_000_000 * LAMPORTS_PER_SOL) },
+      { protocolId: 2, apyBps: 820, tvl: new anchor.BN(2_500_000 * LAMPORTS_PER_SOL) },
+      { protocolId: 3, apyBps: 640, tvl: new anchor.BN(1_200_000 * LAMPORTS_PER_SOL) },
+      { protocolId: 4, apyBps: 1150, tvl: new anchor.BN(600_000 * LAMPORTS_PER_SOL) },
+    ];
+
+    before(async () => {
+      [feedPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("yield_feed")],
+        program.programId
+      );
+      await fundWallet(reporter, 1 * LAMPORTS_PER_SOL);
+    });
+
+    it("reporter publishes APY and TVL and rebalance accepts fresh data", async () => {
+      await program.methods
+        .initializeYieldFeed(reporter.publicKey, new anchor.BN(3600))
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+      await program.methods
+        .reportYields(reports)
+        .accounts({ reporter: reporter.publicKey, feed: feedPda, registry: registryPda })
+        .signers([reporter])
+        .rpc();
+
+      const feed = await program.account.yieldFeed.fetch(feedPda);
+      expect(feed.reports.length).to.equal(5);
+      expect(feed.reports[4].apyBps).to.equal(1150);
+      expect(feed.reports[4].updatedTs.toNumber()).to.be.greaterThan(0);
+
+      await program.methods
+        .rebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+    });
+
+    it("rejects reports from anyone but the reporter", async () => {
+      try {
+        await program.methods
+          .reportYields(reports)
+          .accounts({ reporter: authority.publicKey, feed: feedPda, registry: registryPda })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("Unauthorized");
+      }
+    });
+
+    it("rejects reports for unregistered protocols", async () => {
+      try {
+        await program.methods
+          .reportYields([{ protocolId: 42, apyBps: 500, tvl: new anchor.BN(0) }])
+          .accounts({ reporter: reporter.publicKey, feed: feedPda, registry: registryPda })
+          .signers([reporter])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("ProtocolNotRegistered");
+      }
+    });
+
+    it("rebalance refuses stale yield data", async () => {
+      await program.methods
+        .updateYieldFeed(null, new anchor.BN(1))
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+      await new Promise((r) => setTimeout(r, 2500));
+
+      try {
+        await program.methods
+          .rebalance()
+          .accounts({ authority: authority.publicKey, vault: vaultPda })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("StaleYieldData");
+      }
+
+      await program.methods
+        .updateYieldFeed(null, new anchor.BN(3600))
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+    });
+
+    const unlimited = {
+      maxExposureBps: 10_000,
+      maxLamports: new anchor.BN(0),
+      minTargetBps: 0,
+      maxTargetBps: 10_000,
+    };
+
+    it("auto-allocate fills the best risk-adjusted yields up to their caps", async () => {
+      await program.methods
+        .reportYields(reports)
+        .accounts({ reporter: reporter.publicKey, feed: feedPda, registry: registryPda })
+        .signers([reporter])
+        .rpc();
+      await program.methods
+        .setRiskLimits(0, { ...unlimited, maxTargetBps: 5_000 })
+        .accounts({ authority: authority.publicKey, registry: registryPda })
+        .rpc();
+      await program.methods
+        .setRiskLimits(1, { ...unlimited, minTargetBps: 1_000 })
+        .accounts({ authority: authority.publicKey, registry: registryPda })
+        .rpc();
+      await program.methods
+        .setRiskLimits(2, { ...unlimited, maxTargetBps: 3_000 })
+        .accounts({ authority: authority.publicKey, registry: registryPda })
+        .rpc();
+      await program.methods
+        .setAutoAllocate(true, 0)
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+
+      await program.methods
+        .rebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+
+      // Risk-adjusted: 0 → 680, 2 → 620, 1 → 610. Protocol 1 keeps its minimum
+      // and takes what is left once 0 and 2 hit their caps.
+      const vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.autoAllocate).to.equal(1);
+      expect(
+        vault.allocations.slice(0, vault.numAllocations).map((a) => a.targetBps)
+      ).to.deep.equal([5000, 2000, 3000]);
+    });
+
+    it("auto-allocate moves at most max_turnover_bps per rebalance", async () => {
+      for (const id of [0, 1, 2]) {
+        await program.methods
+          .setRiskLimits(id, unlimited)
+          .accounts({ authority: authority.publicKey, registry: registryPda })
+          .rpc();
+      }
+      await program.methods
+        .setAutoAllocate(true, 1_000)
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+
+      await program.methods
+        .rebalance()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+
+      // The optimum is all-in on protocol 0, a 5000 bps move; a fifth of it is taken
+      const vault = await program.account.vault.fetch(vaultPda);
+      expect(
+        vault.allocations.slice(0, vault.numAllocations).map((a) => a.targetBps)
+      ).to.deep.equal([6000, 1600, 2400]);
+
+      await program.methods
+        .setAutoAllocate(false, 0)
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+    });
+
+    it("rejects a turnover limit above 100%", async () => {
+      try {
+        await program.methods
+          .setAutoAllocate(true, 10_001)
+          .accounts({ authority: authority.publicKey, vault: vaultPda })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("InvalidAllocations");
+      }
+    });
+  });
+
+  // ─────────────────────────────────────────────────
+  // CIRCUIT BREAKER
+  // ─────────────────────────────────────────────────
+  describe("circuit breaker", () => {
+    const user = Keypair.generate();
+    const window = new anchor.BN(3600);
+    let positionPda: PublicKey;
+
+    async function withdraw(shares: anchor.BN) {
+      await program.methods
+        .withdraw(0, shares)
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+    }
+
+    before(async () => {
+      await fundWallet(user, 3 * LAMPORTS_PER_SOL);
+      [positionPda] = getPositionPda(user.publicKey);
+      await program.methods
+        .deposit(0, new anchor.BN(2 * LAMPORTS_PER_SOL), new anchor.BN(0))
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+    });
+
+    it("rejects an outflow limit above 100%", async () => {
+      try {
+        await program.methods
+          .setCircuitBreaker(10_001, window, { pause: {} })
+          .accounts({ authority: authority.publicKey, vault: vaultPda })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("InvalidBreakerConfig");
+      }
+    });
+
+    it("only lets a redemption of at least 1% of NAV trip the breaker", async () => {
+      const [whale, whaleWallet] = bankWallet(110 * LAMPORTS_PER_SOL);
+      const [minnow, minnowWallet] = bankWallet();
+      const { context, bank } = await startBank([whaleWallet, minnowWallet]);
+      await initializeBankVault(context, bank);
+      const accounts = (user: Keypair) => ({
+        user: user.publicKey,
+        vault: vaultPda,
+        position: getPositionPda(user.publicKey)[0],
+        systemProgram: SystemProgram.programId,
+      });
+      for (const [user, amount] of [
+        [whale, 100 * LAMPORTS_PER_SOL],
+        [minnow, 0.5 * LAMPORTS_PER_SOL],
+      ] as [Keypair, number][]) {
+        await bank.methods
+          .deposit(0, new anchor.BN(amount), new anchor.BN(0))
+          .accounts(accounts(user))
+          .signers([user])
+          .rpc();
+      }
+      // 10% of NAV per window, nearly all of it used by the whale
+      await bank.methods
+        .setCircuitBreaker(1_000, window, { pause: {} })
+        .accounts({ authority: context.payer.publicKey, vault: vaultPda })
+        .rpc();
+      await nextSlot(context);
+      await bank.methods
+        .withdraw(0, new anchor.BN(9.9 * SHARES_PER_SOL))
+        .accounts(accounts(whale))
+        .signers([whale])
+        .rpc();
+
+      const { currentEpoch } = await bank.account.vault.fetch(vaultPda);
+      const request = (user: Keypair, shares: anchor.BN) =>
+        bank.methods.requestWithdraw(0, shares).accounts({
+          ...accounts(user),
+          batch: getBatchPda(currentEpoch)[0],
+          ticket: getTicketPda(currentEpoch, user.publicKey)[0],
+        });
+
+      // The minnow's whole position is over what's left of the limit, but too small
+      // to stop everyone else's instant withdrawals
+      const minnowShares = (
+        await bank.account.userPosition.fetch(getPositionPda(minnow.publicKey)[0])
+      ).shares;
+      const logs = await bankFailure(context, request(minnow, minnowShares), [minnow]);
+      expect(logs).to.contain("OutflowLimitExceeded");
+      let vault = await bank.account.vault.fetch(vaultPda);
+      expect(vault.breakerTripped).to.equal(0);
+
+      // 5 SOL is well over 1% of NAV, so it queues and trips the breaker
+      await request(whale, new anchor.BN(5 * SHARES_PER_SOL)).signers([whale]).rpc();
+      vault = await bank.account.vault.fetch(vaultPda);
+      expect(vault.breakerTripped).to.equal(1);
+      expect(vault.isPaused).to.equal(1);
+
+      // With the breaker already tripped, smaller redemptions queue too
+      await request(minnow, minnowShares).signers([minnow]).rpc();
+      const ticket = await bank.account.batchTicket.fetch(
+        getTicketPda(currentEpoch, minnow.publicKey)[0]
+      );
+      expect(ticket.redeemShares.toString()).to.equal(minnowShares.toString());
+    });
+
+    it("turns away withdrawals over the limit and queues them instead", async () => {
+      // Limit the window to roughly half a SOL of outflow
+      const before = await program.account.vault.fetch(vaultPda);
+      const limitBps = Math.ceil(
+        (LAMPORTS_PER_SOL / 2) * 10_000 / before.totalDeposited.toNumber()
+      );
+      await program.methods
+        .setCircuitBreaker(limitBps, window, { pause: {} })
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+
+      // A small withdrawal fits and is recorded against the window
+      await withdraw(new anchor.BN(SHARES_PER_SOL / 10));
+      let vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.recentOutflow.toNumber()).to.be.greaterThan(0);
+      expect(vault.breakerTripped).to.equal(0);
+
+      // Withdrawing the rest instantly fails
+      const { shares } = await program.account.userPosition.fetch(positionPda);
+      try {
+        await withdraw(shares);
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("OutflowLimitExceeded");
+      }
+      try {
+        await program.methods
+          .previewWithdraw(shares)
+          .accounts({ vault: vaultPda, position: positionPda })
+          .view();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("OutflowLimitExceeded");
+      }
+
+      // Requesting it queues the redemption, trips the breaker and pauses the vault
+      const [batchPda] = getBatchPda(vault.currentEpoch);
+      const [ticketPda] = getTicketPda(vault.currentEpoch, user.publicKey);
+      await program.methods
+        .requestWithdraw(0, shares)
+        .accounts({
+          user: user.publicKey,
+          vault: vaultPda,
+          position: positionPda,
+          batch: batchPda,
+          ticket: ticketPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.breakerTripped).to.equal(1);
+      expect(vault.isPaused).to.equal(1);
+      expect(vault.asyncMode).to.equal(0);
+      const ticket = await program.account.batchTicket.fetch(ticketPda);
+      expect(ticket.redeemShares.toString()).to.equal(shares.toString());
+      const position = await program.account.userPosition.fetch(positionPda);
+      expect(position.shares.toNumber()).to.equal(0);
+    });
+
+    it("blocks rebalancing while tripped in pause mode", async () => {
+      try {
+        await program.methods
+          .rebalance()
+          .accounts({ authority: authority.publicKey, vault: vaultPda })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("CircuitBreakerActive");
+      }
+      try {
+        await program.methods.previewRebalance(0).accounts({ vault: vaultPda }).view();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("CircuitBreakerActive");
+      }
+    });
+
+    it("only queues redemptions outside async mode when they exceed the limit", async () => {
+      const vault = await program.account.vault.fetch(vaultPda);
+      const [batchPda] = getBatchPda(vault.currentEpoch);
+      const [ticketPda] = getTicketPda(vault.currentEpoch, authority.publicKey);
+      const [authorityPosition] = getPositionPda(authority.publicKey);
+      try {
+        await program.methods
+          .requestWithdraw(0, new anchor.BN(1_000))
+          .accounts({
+            user: authority.publicKey,
+            vault: vaultPda,
+            position: authorityPosition,
+            batch: batchPda,
+            ticket: ticketPda,
+            systemProgram: SystemProgram.programId,
+          })
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err) {
+        expect(err.toString()).to.contain("AsyncModeDisabled");
+      }
+    });
+
+    it("resets the breaker and switches to async mode in queue mode", async () => {
+      await program.methods
+        .resetCircuitBreaker()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+      await program.methods
+        .updateConfig(null, null, false, null)
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+
+      let vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.breakerTripped).to.equal(0);
+      expect(vault.recentOutflow.toNumber()).to.equal(0);
+
+      // With a 1 bps limit, any sizeable redemption trips the breaker in queue mode
+      await program.methods
+        .setCircuitBreaker(1, window, { queue: {} })
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+      const [authorityPosition] = getPositionPda(authority.publicKey);
+      const [batchPda] = getBatchPda(vault.currentEpoch);
+      const [ticketPda] = getTicketPda(vault.currentEpoch, authority.publicKey);
+      await program.methods
+        .requestWithdraw(0, new anchor.BN(SHARES_PER_SOL / 10))
+        .accounts({
+          user: authority.publicKey,
+          vault: vaultPda,
+          position: authorityPosition,
+          batch: batchPda,
+          ticket: ticketPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+
+      vault = await program.account.vault.fetch(vaultPda);
+      expect(vault.breakerTripped).to.equal(1);
+      expect(vault.asyncMode).to.equal(1);
+      expect(vault.isPaused).to.equal(0);
+
+      // Clean up for later tests
+      await program.methods
+        .resetCircuitBreaker()
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+      await program.methods
+        .setCircuitBreaker(0, window, { pause: {} })
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+      await program.methods
+        .updateEpochConfig(false, null)
+        .accounts({ authority: authority.publicKey, vault: vaultPda })
+        .rpc();
+    });  });
+
   // ─────────────────────────────────────────────────
   // FULL FLOW: deposit → rebalance → withdraw
   // ─────────────────────────────────────────────────
@@ -1000,7 +4862,7 @@ describe("solvault", () => {
 
       // Deposit
       await program.methods
-        .deposit(depositAmount)
+        .deposit(0, depositAmount, new anchor.BN(0))
         .accounts({
           user: user.publicKey,
           vault: vaultPda,
@@ -1031,7 +4893,7 @@ describe("solvault", () => {
       const balBefore = await provider.connection.getBalance(user.publicKey);
 
       await program.methods
-        .withdraw(userShares)
+        .withdraw(0, userShares)
         .accounts({
           user: user.publicKey,
           vault: vaultPda,